@@ -0,0 +1,2 @@
+ALTER TABLE devices
+ADD COLUMN metadata JSONB NOT NULL DEFAULT '{}';
@@ -0,0 +1,12 @@
+/* There's nowhere for uuids to go, so those users will have to set their staging location again */
+UPDATE users SET staging_type = 'none', staging_data = NULL WHERE staging_type IN ('uuid', 'part_uuid');
+
+ALTER TYPE StagingKind RENAME TO StagingKind_old;
+CREATE TYPE StagingKind AS ENUM ('none', 'mountpoint', 'label');
+
+ALTER TABLE users
+ALTER COLUMN staging_type DROP DEFAULT,
+ALTER COLUMN staging_type TYPE StagingKind USING staging_type::text::StagingKind,
+ALTER COLUMN staging_type SET DEFAULT 'none';
+
+DROP TYPE StagingKind_old;
@@ -0,0 +1,10 @@
+/* New values can't be added to an enum inside a transaction, so we swap the type out instead */
+ALTER TYPE StagingKind RENAME TO StagingKind_old;
+CREATE TYPE StagingKind AS ENUM ('none', 'mountpoint', 'label', 'uuid', 'part_uuid');
+
+ALTER TABLE users
+ALTER COLUMN staging_type DROP DEFAULT,
+ALTER COLUMN staging_type TYPE StagingKind USING staging_type::text::StagingKind,
+ALTER COLUMN staging_type SET DEFAULT 'none';
+
+DROP TYPE StagingKind_old;
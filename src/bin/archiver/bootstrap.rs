@@ -0,0 +1,77 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use failure::Error;
+
+use archiver::bootstrap::{self, BootstrapKind};
+use archiver::cli;
+use archiver::client;
+use archiver::mountable::UdisksMounter;
+
+use crate::api_base;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("bootstrap")
+        .about("Prepares a new card or device for use with archiver, and adds it to the config")
+        .arg(Arg::with_name("DEVICE")
+             .help("Path to the device, or its label or UUID (eg, LABEL=NO\\ NAME or UUID=5C8A-1F2B)")
+             .required(true)
+             .index(1))
+        .arg(Arg::with_name("name")
+             .long("name")
+             .takes_value(true)
+             .required(true)
+             .help("Name of the device in the config"))
+        .arg(Arg::with_name("kind")
+             .long("kind")
+             .takes_value(true)
+             .possible_values(&["mass_storage", "flysight"])
+             .default_value("mass_storage")
+             .help("What kind of device this is"))
+        .arg(Arg::with_name("label")
+             .long("label")
+             .takes_value(true)
+             .help("Relabel the filesystem before bootstrapping it"))
+        .arg(Arg::with_name("extensions")
+             .long("extensions")
+             .takes_value(true)
+             .use_delimiter(true)
+             .default_value("mp4")
+             .help("Extensions to archive from a mass storage device"))
+        .arg(Arg::with_name("web")
+             .long("web")
+             .help("Register the device with the web interface, rather than in the local config"))
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let name = matches.value_of("name").unwrap();
+    let kind: BootstrapKind = matches.value_of("kind").unwrap().parse()?;
+    let extensions: Vec<String> = matches.values_of("extensions")
+        .unwrap()
+        .map(|e| e.to_lowercase())
+        .collect();
+
+    let device = bootstrap::find_device(matches.value_of("DEVICE").unwrap())?;
+    if let Some(label) = matches.value_of("label") {
+        bootstrap::relabel(&device, label)?;
+    }
+
+    let mount = UdisksMounter::mount_root(&device)?;
+    let folder = bootstrap::prepare(mount.path(), name)?;
+    println!("Prepared {:?} as {}", folder, name);
+    mount.unmount()?;
+
+    let location = bootstrap::location_for(&device)?;
+    if matches.is_present("web") {
+        let base = api_base(matches);
+        let mut client = client::ArchiverClient::new(&base)?;
+        client.load_token()?;
+        let metadata = bootstrap::web_metadata(kind, &extensions);
+        client.register_device(name, kind.name(), &location.to_identifier(), metadata)?;
+        println!("Registered {} ({}) with {}, run `archiver config fetch` to pick it up", name, location, base);
+    } else {
+        let config_path = cli::config_path(matches)?;
+        bootstrap::register_locally(&config_path, kind, name, &location, &extensions)?;
+        println!("Added {} ({}) to {}", name, location, config_path.display());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,53 @@
+use clap::{App, AppSettings, ArgMatches, SubCommand};
+use failure::Error;
+
+use archiver::config;
+use archiver::client;
+
+use std::fs::{self, File};
+use std::io::Write;
+
+use crate::api_base;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("config")
+        .about("Manages the configuration")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("fetch")
+            .about("Fetches configuration from upstream")
+            )
+        .subcommand(
+            SubCommand::with_name("path")
+            .about("Prints the path of the configuration file that would be used")
+            )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("fetch", Some(sub)) => fetch(sub),
+        ("path", Some(sub)) => {
+            println!("{}", archiver::cli::config_path(sub)?.display());
+            Ok(())
+        },
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+fn fetch(matches: &ArgMatches) -> Result<(), Error> {
+    let base = api_base(matches);
+    info!("Creating client");
+    let mut client = client::ArchiverClient::new(&base)?;
+    client.load_token()?;
+    info!("Fetching config from {}", &base);
+    let config = client.fetch_config()?;
+    let filename = config::config_file_for_writing(matches.value_of("config"));
+    if let Some(parent) = filename.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let mut fh = File::create(&filename)?;
+    fh.write_all(config.to_toml()?.as_bytes())?;
+    info!("Wrote config to {:?}", &filename);
+
+    Ok(())
+}
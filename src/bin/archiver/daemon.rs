@@ -0,0 +1,18 @@
+use clap::{App, ArgMatches, SubCommand};
+use failure::Error;
+
+use archiver::cli;
+use archiver::daemon;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("daemon")
+        .about("Stays running, staging and uploading footage from devices as they're attached, and on a schedule")
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    // The daemon creates a new context each time it reloads its config, so it holds the lock
+    // itself for as long as it runs.
+    let _lock = cli::lock(matches)?;
+
+    daemon::run(|| cli::create_ctx_without_lock(matches))
+}
@@ -0,0 +1,35 @@
+use clap::{App, ArgMatches, SubCommand};
+use failure::Error;
+use rpassword;
+
+use archiver::config;
+use archiver::client;
+use std::io::{self, Write};
+
+use crate::api_base;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("login")
+        .about("Logs into the archiver web interface for configuration management")
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let base = api_base(matches);
+    let client = client::ArchiverClient::new(&base)?;
+    let mut email = String::new();
+    let stdin = io::stdin();
+    let password;
+    println!("Logging into {}", base);
+    print!("email: ");
+    io::stdout().flush()?;
+    stdin.read_line(&mut email)?;
+    password = rpassword::prompt_password_stdout("password: ")?;
+    println!("Logging in");
+    let token = client.login(email.trim_end(), &password)?;
+    println!("Token recieved, saving to ~/{}", config::TOKEN_FILE_NAME);
+
+    // TODO(richo) rewrite config including token
+    config::AccessToken::save(&token)?;
+
+    Ok(())
+}
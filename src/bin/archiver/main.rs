@@ -0,0 +1,67 @@
+#[macro_use]
+extern crate log;
+
+use clap::{AppSettings, ArgMatches};
+
+use archiver::cli;
+use archiver::config;
+
+mod bootstrap;
+mod config_cmd;
+mod daemon;
+mod login;
+mod run;
+mod scan;
+mod stage;
+mod staging;
+mod status;
+mod test;
+
+/// The api base from the config, falling back to the default if there's no config to load yet,
+/// since logging in and fetching one is how most people get their first config.
+fn api_base(matches: &ArgMatches) -> String {
+    match cli::load_config(matches) {
+        Ok(cfg) => cfg.api_base().to_string(),
+        Err(_) => {
+            info!("Error loading config, proceeding with default api base");
+            config::DEFAULT_API_BASE.to_string()
+        },
+    }
+}
+
+fn main() {
+    archiver::cli::run(|| {
+        let matches = cli::base_opts()
+            .about("Archives footage and GPS data from cameras and other devices")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(run::subcommand())
+            .subcommand(stage::subcommand())
+            .subcommand(daemon::subcommand())
+            .subcommand(staging::subcommand())
+            .subcommand(status::subcommand())
+            .subcommand(scan::subcommand())
+            .subcommand(bootstrap::subcommand())
+            .subcommand(login::subcommand())
+            .subcommand(config_cmd::subcommand())
+            .subcommand(test::subcommand())
+            .get_matches();
+
+        if let (_, Some(sub)) = matches.subcommand() {
+            cli::init_progress(sub);
+        }
+
+        match matches.subcommand() {
+            ("run", Some(sub)) => run::run(sub),
+            ("stage", Some(sub)) => stage::run(sub),
+            ("daemon", Some(sub)) => daemon::run(sub),
+            ("staging", Some(sub)) => staging::run(sub),
+            ("status", Some(sub)) => status::run(sub),
+            ("scan", Some(sub)) => scan::run(sub),
+            ("bootstrap", Some(sub)) => bootstrap::run(sub),
+            ("login", Some(sub)) => login::run(sub),
+            ("config", Some(sub)) => config_cmd::run(sub),
+            ("test", Some(sub)) => test::run(sub),
+            _ => unreachable!("clap requires a subcommand"),
+        }
+    })
+}
@@ -0,0 +1,63 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use failure::Error;
+
+use archiver::cancel::Cancel;
+use archiver::cli;
+use archiver::plan;
+use archiver::run;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("run")
+        .about("Performs a single run, uploading footage from all connected devices")
+        .arg(
+            Arg::with_name("jobs")
+            .long("jobs")
+            .short("j")
+            .takes_value(true)
+            .help("How many devices to stage from at once, overriding the config")
+            )
+        .arg(
+            Arg::with_name("dry-run")
+            .long("dry-run")
+            .help("Print what would be staged, uploaded and deleted, without doing any of it")
+            )
+        .arg(
+            Arg::with_name("hash")
+            .long("hash")
+            .requires("dry-run")
+            .help("For a dry run, read every file on the attached devices to tell whether the backends already have it")
+            )
+        .arg(
+            Arg::with_name("format")
+            .long("format")
+            .takes_value(true)
+            .possible_values(&["text", "json"])
+            .default_value("text")
+            .help("How to print the plan for a dry run")
+            )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let ctx = cli::create_ctx(matches)?;
+
+    if matches.is_present("dry-run") {
+        let plan = plan::plan(&ctx, matches.is_present("hash"));
+        match matches.value_of("format") {
+            Some("json") => println!("{}", plan.to_json()?),
+            _ => print!("{}", plan),
+        }
+        return Ok(());
+    }
+
+    let concurrency = match matches.value_of("jobs") {
+        Some(jobs) => jobs.parse()?,
+        None => ctx.cfg.staging_concurrency(),
+    };
+
+    // The first interrupt lets the current files finish, the second aborts them.
+    let cancel = Cancel::on_interrupt()?;
+    let report = run::run(&ctx, concurrency, &cancel);
+    run::report(&ctx, &report)?;
+
+    Ok(())
+}
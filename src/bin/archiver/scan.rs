@@ -0,0 +1,29 @@
+use clap::{App, ArgMatches, SubCommand};
+use failure::Error;
+
+use archiver::cli;
+use archiver::ptp_device;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("scan")
+        .about("Scans for attached devices and prints information found.")
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let ctx = cli::create_ctx_without_lock(matches)?;
+    println!("Found the following PTP devices:");
+
+    for device in ptp_device::locate_ptp_devices(&ctx)?.iter() {
+        println!(
+            "  {:04x}:{:04x} {} {} ({:?}) : {}",
+            device.vendor_id,
+            device.product_id,
+            device.manufacturer,
+            device.model,
+            device.vendor,
+            device.serial
+        );
+    }
+
+    Ok(())
+}
@@ -0,0 +1,44 @@
+use clap::{App, Arg, ArgMatches, SubCommand};
+use failure::Error;
+use std::path::PathBuf;
+
+use archiver::cancel::Cancel;
+use archiver::cli;
+use archiver::manual_file::ManualFile;
+use archiver::staging;
+use archiver::mountable::Mountable;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("stage")
+        .about("Stages media from the local filesystem for the next upload run")
+        .arg(Arg::with_name("PATH")
+             .help("Path to upload from")
+             .required(true)
+             .index(1))
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let ctx = cli::create_ctx(matches)?;
+
+    let dir = matches.value_of("PATH").expect("Couldn't get path");
+    let path = PathBuf::from(dir);
+    let device_name = path.file_name()
+        .expect("Couldn't get file name")
+        .to_str()
+        .expect("Couldn't convert device name to str")
+        .to_string();
+
+    let cancel = Cancel::on_interrupt()?;
+    let staging = ctx.staging().mount()?;
+    info!("Staging to: {:?}", &staging);
+
+    for file in ManualFile::iter_from(path) {
+        if cancel.is_stopping() {
+            info!("Stopping, the rest of {} hasn't been staged", dir);
+            break;
+        }
+        staging::stage_file(file, &staging, &device_name, &cancel)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,183 @@
+use std::io::{self, Write};
+use std::path::Path;
+use std::time::SystemTime;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::{bail, Error};
+
+use archiver::cancel::Cancel;
+use archiver::cli;
+use archiver::formatting;
+use archiver::mountable::Mountable;
+use archiver::progress;
+use archiver::staged::{self, StagedEntry};
+use archiver::storage;
+
+fn id_arg<'a, 'b>() -> Arg<'a, 'b> {
+    Arg::with_name("id")
+        .required(true)
+        .help("The id of the staged file, or any unique prefix of it")
+}
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("staging")
+        .about("Inspects and manages the files waiting in staging")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("list")
+            .about("Lists everything in staging")
+            )
+        .subcommand(
+            SubCommand::with_name("show")
+            .about("Shows everything we know about a staged file")
+            .arg(id_arg())
+            )
+        .subcommand(
+            SubCommand::with_name("retry")
+            .about("Tries uploading a staged file to every backend again")
+            .arg(id_arg())
+            )
+        .subcommand(
+            SubCommand::with_name("drop")
+            .about("Deletes a staged file without uploading it")
+            .arg(id_arg())
+            .arg(
+                Arg::with_name("yes")
+                .long("yes")
+                .short("y")
+                .help("Don't ask for confirmation")
+                )
+            )
+        .subcommand(
+            SubCommand::with_name("export")
+            .about("Copies a staged file out of staging")
+            .arg(id_arg())
+            .arg(
+                Arg::with_name("path")
+                .required(true)
+                .help("Where to copy the file to")
+                )
+            )
+        .subcommand(
+            SubCommand::with_name("verify")
+            .about("Hashes everything in staging again, and checks it against its manifest")
+            )
+}
+
+fn age(entry: &StagedEntry) -> Result<String, Error> {
+    let elapsed = SystemTime::now().duration_since(entry.file.staged_at()?).unwrap_or_default();
+    Ok(formatting::human_readable_time(time::Duration::from_std(elapsed)?))
+}
+
+fn size(entry: &StagedEntry) -> String {
+    format!("{}b", formatting::human_readable_size(entry.manifest.size as usize))
+}
+
+fn list(entries: Vec<StagedEntry>) -> Result<(), Error> {
+    if entries.is_empty() {
+        println!("Nothing in staging");
+    }
+    for entry in entries {
+        println!("{}  {:<12} {} ({}, staged {} ago)",
+                 entry.short_id(), &entry.manifest.device_name, entry.manifest.remote_path().display(), size(&entry), age(&entry)?);
+        for error in entry.file.upload_errors()? {
+            println!("          {} failed at {}: {}", &error.backend, &error.time, &error.error);
+        }
+    }
+    Ok(())
+}
+
+fn show(entry: StagedEntry) -> Result<(), Error> {
+    println!("id:          {}", &entry.id);
+    println!("device:      {}", &entry.manifest.device_name);
+    println!("remote path: {}", entry.manifest.remote_path().display());
+    println!("size:        {}", size(&entry));
+    println!("staged:      {} ago", age(&entry)?);
+    println!("content:     {}", entry.file.content_path.display());
+    println!("manifest:    {}", entry.file.manifest_path().display());
+    println!("hash:        {}", hex::encode(&entry.manifest.content_hash));
+    if let Some(jump) = &entry.manifest.jump_summary {
+        println!("jump:        {:?}", jump);
+    }
+    let errors = entry.file.upload_errors()?;
+    if !errors.is_empty() {
+        println!("last upload errors:");
+        for error in errors {
+            println!("  {} at {}: {}", &error.backend, &error.time, &error.error);
+        }
+    }
+    Ok(())
+}
+
+fn confirm(prompt: &str) -> Result<bool, Error> {
+    print!("{} [y/N] ", prompt);
+    io::stdout().flush()?;
+    let mut answer = String::new();
+    io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().eq_ignore_ascii_case("y"))
+}
+
+fn drop_entry(entry: StagedEntry, matches: &ArgMatches) -> Result<(), Error> {
+    let prompt = format!("Drop {} from staging without uploading it?", entry.manifest.remote_path().display());
+    if !matches.is_present("yes") && !confirm(&prompt)? {
+        println!("Leaving it where it is");
+        return Ok(());
+    }
+    entry.file.delete()?;
+    println!("Dropped {}", entry.short_id());
+    Ok(())
+}
+
+fn verify(entries: Vec<StagedEntry>) -> Result<(), Error> {
+    let mut failed = 0;
+    for entry in &entries {
+        let result = match entry.file.verify(&entry.manifest) {
+            Ok(true) => "ok".to_string(),
+            Ok(false) => {
+                failed += 1;
+                "doesn't match its manifest".to_string()
+            },
+            Err(e) => {
+                failed += 1;
+                format!("couldn't be read: {}", e)
+            },
+        };
+        println!("{}  {} {}", entry.short_id(), entry.manifest.remote_path().display(), result);
+    }
+    if failed > 0 {
+        bail!("{} of {} staged files failed verification", failed, entries.len());
+    }
+    Ok(())
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    // Changing what's in staging could pull the rug out from under a run, but just looking can't.
+    let ctx = match matches.subcommand_name() {
+        Some("retry") | Some("drop") => cli::create_ctx(matches)?,
+        _ => cli::create_ctx_without_lock(matches)?,
+    };
+    let staging = ctx.staging().mount()?;
+
+    match matches.subcommand() {
+        ("list", Some(_)) => list(staged::entries(&staging)?),
+        ("show", Some(sub)) => show(staged::find(&staging, sub.value_of("id").unwrap())?),
+        ("retry", Some(sub)) => {
+            let entry = staged::find(&staging, sub.value_of("id").unwrap())?;
+            let mut report = Default::default();
+            let cancel = Cancel::on_interrupt()?;
+            storage::upload_file(entry.file, entry.manifest, &ctx.cfg.backends(), &mut report, &cancel);
+            writeln!(progress::report_output(), "{}", report.to_plaintext()?)?;
+            Ok(())
+        },
+        ("drop", Some(sub)) => drop_entry(staged::find(&staging, sub.value_of("id").unwrap())?, sub),
+        ("export", Some(sub)) => {
+            let entry = staged::find(&staging, sub.value_of("id").unwrap())?;
+            let path = Path::new(sub.value_of("path").unwrap());
+            let bytes = staged::export(&entry, path)?;
+            println!("Exported {} to {} ({}b)", entry.short_id(), path.display(), formatting::human_readable_size(bytes as usize));
+            Ok(())
+        },
+        ("verify", Some(_)) => verify(staged::entries(&staging)?),
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
@@ -0,0 +1,27 @@
+use clap::{App, ArgMatches, SubCommand};
+use failure::Error;
+
+use archiver::lock::{self, LockStatus};
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("status")
+        .about("Shows whether archiver is running, and who holds the lock. With --wait, waits until it's released first")
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    let path = lock::lock_path()?;
+    if matches.is_present("wait") {
+        lock::wait_until_free(&path)?;
+    }
+
+    println!("Lock: {}", path.display());
+    match lock::status(&path)? {
+        LockStatus::Free => println!("Not held"),
+        LockStatus::Held(ref holder) if holder.is_stale() => {
+            println!("Held by {}, which is no longer running. The next run will reclaim it.", holder)
+        },
+        LockStatus::Held(holder) => println!("Held by {}", holder),
+        LockStatus::HeldByUnknown => println!("Held, but it doesn't say by whom. If nothing else is running, remove it."),
+    }
+    Ok(())
+}
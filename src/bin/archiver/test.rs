@@ -0,0 +1,76 @@
+use std::path::PathBuf;
+use std::fs;
+
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use failure::Error;
+
+use archiver::cli;
+use archiver::device;
+use archiver::mountable;
+
+pub fn subcommand<'a, 'b>() -> App<'a, 'b> {
+    SubCommand::with_name("test")
+        .about("Smoke tests parts of the archiver setup")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .subcommand(
+            SubCommand::with_name("location")
+            .about("Smoke test the location and mounting")
+            )
+        .subcommand(
+            SubCommand::with_name("mounting")
+            .about("Smoke test the mounting infrastructure")
+            .arg(Arg::with_name("LABEL")
+                 .help("Label of the device to test mount")
+                 .required(true)
+                 .index(1))
+            )
+        .subcommand(
+            SubCommand::with_name("notifications")
+            .about("Send a test notification")
+            )
+}
+
+pub fn run(matches: &ArgMatches) -> Result<(), Error> {
+    match matches.subcommand() {
+        ("location", Some(sub)) => location(sub),
+        ("mounting", Some(sub)) => mounting(sub),
+        ("notifications", Some(sub)) => notifications(sub),
+        _ => unreachable!("clap requires a subcommand"),
+    }
+}
+
+fn location(matches: &ArgMatches) -> Result<(), Error> {
+    let ctx = cli::create_ctx_without_lock(matches)?;
+
+    let devices = device::attached_devices(&ctx);
+
+    for device in devices {
+        println!("  {:?}", &device);
+    }
+
+    Ok(())
+}
+
+fn mounting(matches: &ArgMatches) -> Result<(), Error> {
+    // Loading the config is part of the smoke test, even though we mount by label.
+    cli::create_ctx_without_lock(matches)?;
+
+    let mut pb = PathBuf::from("/dev/disk/by-label");
+    pb.push(matches.value_of("LABEL").expect("no label"));
+
+    let mp = mountable::UdisksMounter::mount(pb)?;
+    for file in fs::read_dir(mp.path())? {
+        println!("  {:?}", &file?);
+    }
+    mp.unmount()?;
+
+    Ok(())
+}
+
+fn notifications(matches: &ArgMatches) -> Result<(), Error> {
+    let ctx = cli::create_ctx(matches)?;
+
+    ctx.notify("Test notification!")?;
+
+    Ok(())
+}
@@ -0,0 +1,226 @@
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use failure::Error;
+use serde_json;
+use toml;
+
+use crate::config::{Config, MountableDeviceLocation};
+use crate::mountable::{self, DeviceIdentity, MOUNTABLE_DEVICE_FOLDER};
+
+/// The kinds of device that live on a filesystem, and so can be bootstrapped.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum BootstrapKind {
+    MassStorage,
+    Flysight,
+}
+
+impl BootstrapKind {
+    /// The name of this kind, both in the config and to the web service.
+    pub fn name(&self) -> &'static str {
+        match self {
+            BootstrapKind::MassStorage => "mass_storage",
+            BootstrapKind::Flysight => "flysight",
+        }
+    }
+}
+
+impl FromStr for BootstrapKind {
+    type Err = Error;
+
+    fn from_str(kind: &str) -> Result<BootstrapKind, Error> {
+        match kind {
+            "mass_storage" => Ok(BootstrapKind::MassStorage),
+            "flysight" => Ok(BootstrapKind::Flysight),
+            kind => bail!("Can't bootstrap a {} device", kind),
+        }
+    }
+}
+
+impl fmt::Display for BootstrapKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name())
+    }
+}
+
+/// Find the block device for `spec`, which is either a path to it, or a label or UUID in the
+/// style of fstab.
+pub fn find_device(spec: &str) -> Result<PathBuf, Error> {
+    let device = if spec.starts_with('/') {
+        PathBuf::from(spec)
+    } else {
+        match mountable::device_for_location(&MountableDeviceLocation::from_identifier(spec)) {
+            Some(device) => device,
+            None => bail!("Don't know how to find {} on this platform", spec),
+        }
+    };
+    if !device.exists() {
+        bail!("{:?} doesn't exist, is the card inserted?", &device);
+    }
+    // Relabelling will pull the rug out from under a /dev/disk/by-label path
+    Ok(fs::canonicalize(device)?)
+}
+
+/// Ask blkid about `tag` on the filesystem on `device`.
+fn blkid(device: &Path, tag: &str) -> Result<Option<String>, Error> {
+    let child = Command::new("blkid")
+        .arg("-o").arg("value")
+        .arg("-s").arg(tag)
+        .arg(device)
+        .output()?;
+
+    if !child.status.success() {
+        bail!("blkid couldn't read {:?}: {}", device, String::from_utf8_lossy(&child.stderr).trim());
+    }
+    let value = String::from_utf8_lossy(&child.stdout).trim().to_string();
+    Ok(if value.is_empty() { None } else { Some(value) })
+}
+
+/// Change the label of the filesystem on `device`.
+pub fn relabel(device: &Path, label: &str) -> Result<(), Error> {
+    let fstype = blkid(device, "TYPE")?;
+    let tool = match fstype.as_ref().map(|t| &t[..]) {
+        Some("vfat") => "fatlabel",
+        Some("exfat") => "exfatlabel",
+        Some("ext2") | Some("ext3") | Some("ext4") => "e2label",
+        fstype => bail!("Don't know how to relabel a {:?} filesystem", fstype),
+    };
+
+    info!("Labelling {:?} as {} with {}", device, label, tool);
+    let child = Command::new(tool)
+        .arg(device)
+        .arg(label)
+        .output()?;
+
+    if !child.status.success() {
+        bail!("{} failed: {}", tool, String::from_utf8_lossy(&child.stderr).trim());
+    }
+    Ok(())
+}
+
+/// The most specific location we can find `device` by in future. Filesystem UUIDs are preferred,
+/// since most cards have the same label.
+pub fn location_for(device: &Path) -> Result<MountableDeviceLocation, Error> {
+    if let Some(uuid) = blkid(device, "UUID")? {
+        return Ok(MountableDeviceLocation::Uuid(uuid));
+    }
+    if let Some(label) = blkid(device, "LABEL")? {
+        return Ok(MountableDeviceLocation::Label(label));
+    }
+    bail!("{:?} has neither a UUID nor a label to find it by", device)
+}
+
+/// Create the archiver folder on the filesystem mounted at `root`, and an identity file naming it
+/// `name`.
+///
+/// Bootstrapping a device twice is harmless, but a device that already belongs to something else
+/// has to be cleaned up by hand.
+pub fn prepare(root: &Path, name: &str) -> Result<PathBuf, Error> {
+    let folder = root.join(MOUNTABLE_DEVICE_FOLDER);
+    fs::create_dir_all(&folder)?;
+
+    match DeviceIdentity::from_path(&folder)? {
+        Some(ref identity) if identity.name != name => {
+            bail!("{:?} already belongs to {}", root, identity.name)
+        },
+        Some(_) => info!("{:?} is already bootstrapped as {}", root, name),
+        None => {
+            info!("Writing identity for {} to {:?}", name, &folder);
+            DeviceIdentity::new(name.to_string()).write(&folder)?;
+        },
+    }
+    Ok(folder)
+}
+
+/// A config entry for a newly bootstrapped device, to be appended to the config file.
+pub fn config_entry(kind: BootstrapKind, name: &str, location: &MountableDeviceLocation, extensions: &[String]) -> String {
+    let quote = |s: &str| toml::Value::String(s.to_string()).to_string();
+    let location = match location {
+        MountableDeviceLocation::Mountpoint(path) => format!("mountpoint = {}", quote(&path.to_string_lossy())),
+        MountableDeviceLocation::Label(lbl) => format!("label = {}", quote(lbl)),
+        MountableDeviceLocation::Uuid(uuid) => format!("uuid = {}", quote(uuid)),
+        MountableDeviceLocation::PartUuid(uuid) => format!("partuuid = {}", quote(uuid)),
+    };
+
+    let mut entry = format!("\n[[{}]]\nname = {}\n{}\n", kind.name(), quote(name), location);
+    if kind == BootstrapKind::MassStorage {
+        let extensions: Vec<_> = extensions.iter().map(|e| quote(e)).collect();
+        entry.push_str(&format!("extensions = [{}]\n", extensions.join(", ")));
+    }
+    entry
+}
+
+/// The metadata to register a newly bootstrapped device with the web service with, which is
+/// where the web service keeps the settings that would otherwise be in its config entry.
+pub fn web_metadata(kind: BootstrapKind, extensions: &[String]) -> Option<serde_json::Value> {
+    match kind {
+        BootstrapKind::MassStorage => Some(json!({ "extensions": extensions })),
+        BootstrapKind::Flysight => None,
+    }
+}
+
+/// Add a newly bootstrapped device to the config at `path`.
+pub fn register_locally(path: &Path, kind: BootstrapKind, name: &str, location: &MountableDeviceLocation, extensions: &[String]) -> Result<(), Error> {
+    let cfg = Config::from_file(path)?;
+    let taken = cfg.mass_storages().iter().map(|d| &d.name)
+        .chain(cfg.flysights().iter().map(|d| &d.name))
+        .any(|existing| existing == name);
+    if taken {
+        bail!("There's already a device called {} in {}", name, path.display());
+    }
+
+    let mut body = fs::read_to_string(path)?;
+    body.push_str(&config_entry(kind, name, location, extensions));
+    // Make sure that we're not about to write out something that we can't read back in
+    body.parse::<Config>()?;
+    fs::write(path, body)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_prepares_devices() {
+        let root = test_helpers::tempdir();
+
+        let folder = prepare(root.path(), "front").unwrap();
+        assert_eq!(folder, root.path().join("archiver"));
+        assert_eq!(DeviceIdentity::from_path(&folder).unwrap(), Some(DeviceIdentity::new("front".into())));
+
+        // Again is fine, but not as something else
+        prepare(root.path(), "front").unwrap();
+        assert!(prepare(root.path(), "back").is_err());
+    }
+
+    #[test]
+    fn test_web_metadata() {
+        let extensions = vec!["mp4".to_string(), "lrv".to_string()];
+        assert_eq!(
+            web_metadata(BootstrapKind::MassStorage, &extensions),
+            Some(json!({ "extensions": ["mp4", "lrv"] })),
+        );
+        assert_eq!(web_metadata(BootstrapKind::Flysight, &extensions), None);
+    }
+
+    #[test]
+    fn test_registers_devices_locally() {
+        let dir = test_helpers::tempdir();
+        let path = dir.path().join("archiver.toml");
+        fs::copy("archiver.toml.example", &path).unwrap();
+
+        let location = MountableDeviceLocation::Uuid("5C8A-1F2B".into());
+        register_locally(&path, BootstrapKind::MassStorage, "front", &location, &["mp4".into(), "lrv".into()]).unwrap();
+        assert!(register_locally(&path, BootstrapKind::Flysight, "front", &location, &[]).is_err());
+
+        let cfg = Config::from_file(&path).unwrap();
+        let front = cfg.mass_storages().iter().find(|d| d.name == "front").unwrap();
+        assert_eq!(front.location, location);
+        assert_eq!(front.extensions, vec!["mp4".to_string(), "lrv".to_string()]);
+    }
+}
@@ -0,0 +1,122 @@
+use std::io::{self, Read};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use failure::Error;
+use libc;
+use signal_hook;
+
+const RUNNING: usize = 0;
+const STOPPING: usize = 1;
+const ABORTING: usize = 2;
+
+/// Asks staging and uploading to stop once they're done with the file they're working on, so that
+/// nothing is left half staged or half uploaded. If that's not soon enough, they can be asked to
+/// abort the transfers they're in the middle of instead, which still leaves everything tidied up.
+///
+/// Clones share their state, so that one can be handed to each thread doing the work.
+#[derive(Debug, Clone, Default)]
+pub struct Cancel {
+    level: Arc<AtomicUsize>,
+}
+
+impl Cancel {
+    pub fn new() -> Cancel {
+        Default::default()
+    }
+
+    /// Create a token that's stopped by the first SIGINT or SIGTERM we get, and aborted by the
+    /// second. Since those signals don't kill us, everything still gets to clean up after itself,
+    /// closing sessions and unmounting devices. If cleaning up gets stuck, a third signal kills
+    /// us the way it normally would.
+    pub fn on_interrupt() -> Result<Cancel, Error> {
+        let cancel = Cancel::new();
+        for &signal in &[signal_hook::SIGINT, signal_hook::SIGTERM] {
+            let level = Arc::clone(&cancel.level);
+            // Only touching an atomic, signal and raise keeps this safe to run in a signal handler.
+            unsafe {
+                signal_hook::register(signal, move || {
+                    if level.fetch_add(1, Ordering::SeqCst) >= ABORTING {
+                        libc::signal(signal, libc::SIG_DFL);
+                        libc::raise(signal);
+                    }
+                })?;
+            }
+        }
+        Ok(cancel)
+    }
+
+    /// Ask everything holding this to stop at the next opportunity.
+    pub fn stop(&self) {
+        let _ = self.level.compare_exchange(RUNNING, STOPPING, Ordering::SeqCst, Ordering::SeqCst);
+    }
+
+    /// Ask everything holding this to abort whatever it's transferring.
+    pub fn abort(&self) {
+        self.level.store(ABORTING, Ordering::SeqCst);
+    }
+
+    pub fn is_stopping(&self) -> bool {
+        self.level.load(Ordering::SeqCst) >= STOPPING
+    }
+
+    pub fn is_aborting(&self) -> bool {
+        self.level.load(Ordering::SeqCst) >= ABORTING
+    }
+
+    /// Wrap `reader` so that reading from it fails once we're aborting.
+    pub fn reader<R: Read>(&self, reader: R) -> Abortable<R> {
+        Abortable {
+            reader,
+            cancel: self.clone(),
+        }
+    }
+}
+
+/// A reader that fails once its `Cancel` is aborting, which is how transfers are cut short.
+#[derive(Debug)]
+pub struct Abortable<R> {
+    reader: R,
+    cancel: Cancel,
+}
+
+impl<R> Abortable<R> {
+    pub fn get_ref(&self) -> &R {
+        &self.reader
+    }
+}
+
+impl<R: Read> Read for Abortable<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.cancel.is_aborting() {
+            return Err(io::Error::new(io::ErrorKind::Other, "Transfer aborted"));
+        }
+        self.reader.read(buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    #[test]
+    fn test_aborting_cuts_transfers_short() {
+        let cancel = Cancel::new();
+        let mut reader = cancel.reader(Cursor::new(vec![0; 16]));
+        let mut buf = [0; 8];
+        assert_eq!(reader.read(&mut buf).unwrap(), 8);
+
+        cancel.stop();
+        assert!(cancel.is_stopping());
+        assert!(!cancel.is_aborting());
+        assert_eq!(reader.read(&mut buf).unwrap(), 8);
+
+        cancel.abort();
+        assert!(cancel.is_aborting());
+        assert!(reader.read(&mut buf).is_err());
+        // Stopping again doesn't take back the abort
+        cancel.stop();
+        assert!(cancel.is_aborting());
+    }
+}
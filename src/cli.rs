@@ -1,7 +1,16 @@
+use std::path::PathBuf;
+
 use crate::{AUTHOR, VERSION};
+use crate::config::{self, Config};
+use crate::ctx::{self, Ctx};
+use crate::device::DeviceRegistry;
+use crate::lock::{self, Lock};
+use crate::progress::{self, TtyLogger, TtyProgress};
 
-use clap::{App, Arg};
+use clap::{App, Arg, ArgMatches};
 use dotenv;
+use failure::Error;
+use log;
 
 /// Create the base set of clap options common to all cli commands
 pub fn base_opts<'a, 'b>() -> App<'a, 'b> {
@@ -12,17 +21,103 @@ pub fn base_opts<'a, 'b>() -> App<'a, 'b> {
             Arg::with_name("config")
                 .long("config")
                 .takes_value(true)
-                .help("Path to configuration file"),
+                .global(true)
+                .help("Path to configuration file, rather than looking for one"),
+        )
+        .arg(
+            Arg::with_name("no-lock")
+                .long("no-lock")
+                .alias("no-cron")
+                .global(true)
+                .help("Don't invoke any of the locking machinery to ensure only one archiver runs at a time"),
         )
+        .arg(
+            Arg::with_name("wait")
+                .long("wait")
+                .global(true)
+                .help("If another archiver holds the lock, wait for it to finish rather than giving up"),
+        )
+        .arg(
+            Arg::with_name("progress")
+                .long("progress")
+                .takes_value(true)
+                .possible_values(&["auto", "tty", "json", "none"])
+                .global(true)
+                .help("How to show the progress of transfers. `json` writes an event per line to stdout, \
+                       moving reports to stderr, and `auto`, the default, draws it on the terminal if there is one"),
+        )
+}
+
+/// Send progress wherever `--progress` asks for it to go.
+pub fn init_progress(matches: &ArgMatches) {
+    match matches.value_of("progress").unwrap_or("auto") {
+        "auto" if progress::stderr_is_tty() => progress::install(TtyProgress::new()),
+        "tty" => progress::install(TtyProgress::new()),
+        "json" => progress::install_json_stdout(),
+        _ => {},
+    }
+}
+
+/// The config file that `--config` points at, or that we found by looking for one.
+pub fn config_path(matches: &ArgMatches) -> Result<PathBuf, Error> {
+    config::find_config_file(matches.value_of("config"))
+}
+
+/// Load the config file that `--config` points at, or that we found by looking for one.
+pub fn load_config(matches: &ArgMatches) -> Result<Config, Error> {
+    load_config_with_registry(matches, &DeviceRegistry::default())
+}
+
+fn load_config_with_registry(matches: &ArgMatches, registry: &DeviceRegistry) -> Result<Config, Error> {
+    let path = config_path(matches)?;
+    info!("Loading config from {:?}", &path);
+    Config::from_file_with_registry(&path, registry)
+}
+
+/// Create a context from the config, taking the lock unless `--no-lock` was passed.
+pub fn create_ctx(matches: &ArgMatches) -> Result<Ctx, Error> {
+    let registry = DeviceRegistry::default();
+    let cfg = load_config_with_registry(matches, &registry)?;
+    match self::lock(matches)? {
+        Some(lock) => Ctx::create_with_lock(cfg, registry, lock),
+        None => Ctx::create_without_lock(cfg, registry),
+    }
+}
+
+/// Take the lock unless `--no-lock` was passed, waiting for it if `--wait` was. This is also for
+/// commands that create contexts without it but need to stay locked for as long as they run, like
+/// the daemon.
+pub fn lock(matches: &ArgMatches) -> Result<Option<Lock>, Error> {
+    if matches.is_present("no-lock") {
+        Ok(None)
+    } else if matches.is_present("wait") {
+        lock::acquire_waiting(&lock::lock_path()?).map(Some)
+    } else {
+        ctx::acquire_lock().map(Some)
+    }
+}
+
+/// Create a context from the config without taking the lock, for commands that don't touch
+/// devices or staging.
+pub fn create_ctx_without_lock(matches: &ArgMatches) -> Result<Ctx, Error> {
+    let registry = DeviceRegistry::default();
+    let cfg = load_config_with_registry(matches, &registry)?;
+    Ctx::create_without_lock(cfg, registry)
 }
 
 /// Setup logging for archiver. This sets the log level to INFO if unset and configures the logging
-/// facade favoured by archiver's clis.
+/// facade favoured by archiver's clis, writing above the progress display if one is drawn.
 pub fn init_logging() {
     if ::std::env::var_os("RUST_LOG").is_none() {
         ::std::env::set_var("RUST_LOG", "INFO");
     }
-    pretty_env_logger::init();
+    let mut builder = pretty_env_logger::formatted_builder();
+    if let Ok(filters) = ::std::env::var("RUST_LOG") {
+        builder.parse_filters(&filters);
+    }
+    let logger = builder.build();
+    log::set_max_level(logger.filter());
+    log::set_boxed_logger(Box::new(TtyLogger::new(logger))).expect("Logging was already set up");
 }
 
 /// Run a given closure with logging configured, and deal with any errors. This allows you to have
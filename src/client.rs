@@ -109,6 +109,45 @@ impl ArchiverClient {
         }
     }
 
+    pub fn register_device(
+        &self,
+        name: &str,
+        kind: &str,
+        identifier: &str,
+        metadata: Option<serde_json::Value>,
+    ) -> Result<(), Error> {
+        let mut endpoint = self.base.clone();
+        endpoint.set_path("/device");
+
+        let headers = self.json_content_type(
+            self.add_authorization(
+                HeaderMap::new())?);
+
+        let payload = messages::RegisterDevice {
+            name: name.into(),
+            kind: kind.into(),
+            identifier: identifier.into(),
+            metadata,
+        };
+
+        let mut resp = self
+            .client
+            .post(endpoint)
+            .body(serde_json::to_string(&payload)?)
+            .headers(headers)
+            .send()?;
+
+        if resp.status() == 500 {
+            Err(ClientError::ServerError(resp.text()?))?;
+        }
+
+        let resp: messages::RegisterDeviceResp = resp.json()?;
+        match resp {
+            messages::RegisterDeviceResp::Registered => Ok(()),
+            messages::RegisterDeviceResp::Error(e) => Err(format_err!("{}", e)),
+        }
+    }
+
     pub fn login(&self, email: &str, password: &str) -> Result<SessionToken, Error> {
         let mut endpoint = self.base.clone();
         endpoint.set_path("/json/signin");
@@ -1,3 +1,6 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::ffi::OsString;
 use std::fs::File;
 use std::io::{Read, Write};
 use std::path::{Path, PathBuf};
@@ -5,12 +8,16 @@ use std::str::FromStr;
 use std::fmt;
 
 use failure::Error;
+use glob;
+use serde::de::DeserializeOwned;
 use toml;
 use url;
 
+use crate::device::DeviceRegistry;
 use crate::dropbox;
 use crate::mailer::SendgridMailer;
 use crate::pushover_notifier::{Notify, PushoverNotifier};
+use crate::schedule::Schedule;
 use crate::web_notifier::WebNotifier;
 use crate::vimeo::VimeoClient;
 use crate::mountable::{Mountable, MountableFilesystem};
@@ -20,6 +27,62 @@ use crate::storage::MaybeStorageAdaptor;
 // TODO(richo) Change this once we have a canonical domain
 pub static DEFAULT_API_BASE: &'static str = "https://archiver-web.onrender.com/";
 pub static TOKEN_FILE_NAME: &'static str = ".archiver-token";
+/// The name of the config file that we look for.
+pub static CONFIG_FILE_NAME: &'static str = "archiver.toml";
+/// An environment variable that can point at the config file to use.
+pub static CONFIG_ENV_VAR: &'static str = "ARCHIVER_CONFIG";
+/// How many devices to stage from at once, unless the config says otherwise.
+pub const DEFAULT_STAGING_CONCURRENCY: usize = 4;
+
+/// Everywhere we look for a config file, in the order that we look: the current directory, then
+/// `$XDG_CONFIG_HOME` and each of `$XDG_CONFIG_DIRS`.
+pub fn config_search_path() -> Vec<PathBuf> {
+    let home = get_home().ok().map(|home| home.as_ref().to_path_buf());
+    config_search_path_with(|var| env::var_os(var), home)
+}
+
+fn config_search_path_with<F>(var: F, home: Option<PathBuf>) -> Vec<PathBuf>
+where F: Fn(&str) -> Option<OsString>,
+{
+    // The XDG spec says that relative paths in either of these should be ignored.
+    let config_home = var("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .filter(|path| path.is_absolute())
+        .or_else(|| home.map(|home| home.join(".config")));
+    let config_dirs = var("XDG_CONFIG_DIRS")
+        .filter(|dirs| !dirs.is_empty())
+        .unwrap_or_else(|| "/etc/xdg".into());
+
+    let mut out = vec![PathBuf::from(CONFIG_FILE_NAME)];
+    out.extend(config_home.into_iter()
+               .chain(env::split_paths(&config_dirs).filter(|path| path.is_absolute()))
+               .map(|dir| dir.join("archiver").join(CONFIG_FILE_NAME)));
+    out
+}
+
+/// The config file that was asked for, either explicitly or with `ARCHIVER_CONFIG`, or otherwise
+/// the first one in the search path that exists.
+fn locate_config_file(explicit: Option<&str>) -> Option<PathBuf> {
+    explicit.map(PathBuf::from)
+        .or_else(|| env::var_os(CONFIG_ENV_VAR).map(PathBuf::from))
+        .or_else(|| config_search_path().into_iter().find(|path| path.is_file()))
+}
+
+/// Find the config file to load.
+pub fn find_config_file(explicit: Option<&str>) -> Result<PathBuf, Error> {
+    match locate_config_file(explicit) {
+        Some(path) => Ok(path),
+        None => Err(ConfigError::NoConfigFile(config_search_path()))?,
+    }
+}
+
+/// Find the config file to write to. This is the one we'd load if there is one, and otherwise a
+/// new one in `$XDG_CONFIG_HOME`.
+pub fn config_file_for_writing(explicit: Option<&str>) -> PathBuf {
+    locate_config_file(explicit)
+        .or_else(|| config_search_path().into_iter().nth(1))
+        .unwrap_or_else(|| PathBuf::from(CONFIG_FILE_NAME))
+}
 
 #[derive(RedactedDebug)]
 pub struct AccessToken(#[redacted] String);
@@ -84,16 +147,13 @@ impl AccessToken {
     }
 }
 
-#[derive(Debug, Eq, PartialEq)]
-pub enum DeviceConfig {
-    Gopro(GoproConfig),
-    MassStorage(MassStorageConfig),
-    Flysight(FlysightConfig),
-    UnknownDevice(String),
-}
-
-#[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
-#[serde(deny_unknown_fields)]
+// The built in kinds of device keep their own typed sections, rather than being parsed by their
+// `DeviceKind`s, since so much of archiver reads them directly.
+//
+// Unknown sections are collected rather than rejected, since they may belong to a device kind that
+// is registered at runtime. Loading the config rejects the ones that don't, against the default
+// registry unless it's given another.
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
 pub struct Config {
     archiver: ArchiverConfig,
     staging: StagingConfig,
@@ -102,12 +162,18 @@ pub struct Config {
     // youtube: Option<YoutubeConfig>,
     flysight: Option<Vec<FlysightConfig>>,
     gopro: Option<Vec<GoproConfig>>,
+    ptp: Option<Vec<PtpConfig>>,
     mass_storage: Option<Vec<MassStorageConfig>>,
     local_backup: Option<Vec<LocalBackupConfig>>,
     // gswoop: Option<GswoopConfig>,
     sendgrid: Option<SendgridConfig>,
     pushover: Option<PushoverConfig>,
     web_notifications: Option<WebNotificationsConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    daemon: Option<DaemonConfig>,
+    /// Sections for kinds of device that aren't built in, keyed by the name of the kind.
+    #[serde(flatten)]
+    devices: BTreeMap<String, toml::Value>,
 }
 
 #[derive(Debug, Default)]
@@ -119,18 +185,21 @@ pub struct ConfigBuilder {
     // youtube: Option<YoutubeConfig>,
     flysight: Option<Vec<FlysightConfig>>,
     gopro: Option<Vec<GoproConfig>>,
+    ptp: Option<Vec<PtpConfig>>,
     mass_storage: Option<Vec<MassStorageConfig>>,
     local_backup: Option<Vec<LocalBackupConfig>>,
     // gswoop: Option<GswoopConfig>,
     sendgrid: Option<SendgridConfig>,
     pushover: Option<PushoverConfig>,
     web_notifications: Option<WebNotificationsConfig>,
+    devices: BTreeMap<String, toml::Value>,
 }
 
 lazy_static! {
     static ref EMPTY_MASS_STORAGES: Vec<MassStorageConfig> = vec![];
     static ref EMPTY_FLYSIGHTS: Vec<FlysightConfig> = vec![];
     static ref EMPTY_GOPROS: Vec<GoproConfig> = vec![];
+    static ref EMPTY_PTPS: Vec<PtpConfig> = vec![];
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -139,6 +208,9 @@ lazy_static! {
 pub struct StagingConfig {
     #[serde(flatten)]
     pub(crate) location: MountableDeviceLocation,
+    /// How to mount this location, if not the default for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) mounter: Option<Mounter>,
 }
 
 #[cfg(feature = "web")]
@@ -148,7 +220,9 @@ impl StagingConfig {
     #[cfg(feature = "web")]
     pub fn data_for_db(&self) -> String {
         match &self.location {
-            MountableDeviceLocation::Label(buf) => buf.to_string(),
+            MountableDeviceLocation::Label(buf) |
+            MountableDeviceLocation::Uuid(buf) |
+            MountableDeviceLocation::PartUuid(buf) => buf.to_string(),
             MountableDeviceLocation::Mountpoint(buf) => buf.to_string_lossy().into(),
         }
     }
@@ -158,6 +232,8 @@ impl StagingConfig {
         match &self.location {
             MountableDeviceLocation::Label(_) => StagingKind::Label,
             MountableDeviceLocation::Mountpoint(_) => StagingKind::Mountpoint,
+            MountableDeviceLocation::Uuid(_) => StagingKind::Uuid,
+            MountableDeviceLocation::PartUuid(_) => StagingKind::PartUuid,
         }
     }
 }
@@ -167,6 +243,21 @@ impl StagingConfig {
 pub struct ArchiverConfig {
     api_base: Option<String>,
     api_token: Option<String>,
+    /// How many devices to stage from at once.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    staging_concurrency: Option<usize>,
+}
+
+/// How the daemon should behave, on top of staging devices as they're attached.
+#[derive(Default, Serialize, Deserialize, Debug, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DaemonConfig {
+    /// Run a full cycle this often, eg `6h`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    interval: Option<String>,
+    /// Run a full cycle whenever this cron expression matches.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    cron: Option<String>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq)]
@@ -191,6 +282,13 @@ pub enum MountableDeviceLocation {
     Mountpoint(PathBuf),
     #[serde(rename = "label")]
     Label(String),
+    /// The UUID of the filesystem, as shown by `blkid`. Every card a camera formats gets the same
+    /// label, but not the same UUID.
+    #[serde(rename = "uuid")]
+    Uuid(String),
+    /// The UUID of the partition, which unlike the filesystem UUID survives being reformatted.
+    #[serde(rename = "partuuid")]
+    PartUuid(String),
 }
 
 impl MountableDeviceLocation {
@@ -201,6 +299,41 @@ impl MountableDeviceLocation {
     pub fn from_label(lbl: String) -> MountableDeviceLocation {
         MountableDeviceLocation::Label(lbl)
     }
+
+    /// Parse an identifier in the style of fstab, ie `UUID=1234-ABCD` or `PARTUUID=...`. Anything
+    /// else is taken to be a label, optionally written as `LABEL=...`.
+    pub fn from_identifier(identifier: &str) -> MountableDeviceLocation {
+        if identifier.starts_with("UUID=") {
+            MountableDeviceLocation::Uuid(identifier["UUID=".len()..].to_string())
+        } else if identifier.starts_with("PARTUUID=") {
+            MountableDeviceLocation::PartUuid(identifier["PARTUUID=".len()..].to_string())
+        } else if identifier.starts_with("LABEL=") {
+            MountableDeviceLocation::Label(identifier["LABEL=".len()..].to_string())
+        } else {
+            MountableDeviceLocation::Label(identifier.to_string())
+        }
+    }
+
+    /// The inverse of `from_identifier`.
+    pub fn to_identifier(&self) -> String {
+        match self {
+            MountableDeviceLocation::Mountpoint(path) => path.to_string_lossy().into(),
+            MountableDeviceLocation::Label(lbl) => format!("LABEL={}", lbl),
+            MountableDeviceLocation::Uuid(uuid) => format!("UUID={}", uuid),
+            MountableDeviceLocation::PartUuid(uuid) => format!("PARTUUID={}", uuid),
+        }
+    }
+
+    /// How to mount this location when the config doesn't say. Devices found by label or UUID are
+    /// mounted with udisks, and mountpoints are assumed to be mounted already.
+    pub fn default_mounter(&self) -> Mounter {
+        match self {
+            MountableDeviceLocation::Mountpoint(_) => Mounter::External,
+            MountableDeviceLocation::Label(_) |
+            MountableDeviceLocation::Uuid(_) |
+            MountableDeviceLocation::PartUuid(_) => Mounter::Udisks,
+        }
+    }
 }
 
 impl fmt::Display for MountableDeviceLocation {
@@ -212,10 +345,97 @@ impl fmt::Display for MountableDeviceLocation {
             MountableDeviceLocation::Label(label) => {
                 write!(f, "Label({})", label)
             },
+            MountableDeviceLocation::Uuid(uuid) => {
+                write!(f, "Uuid({})", uuid)
+            },
+            MountableDeviceLocation::PartUuid(uuid) => {
+                write!(f, "PartUuid({})", uuid)
+            },
         }
     }
 }
 
+/// How a device gets mounted before we use it, and unmounted afterwards.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Mounter {
+    /// Ask udisks to mount the device, which doesn't need root. Doesn't work for mountpoints.
+    Udisks,
+    /// Use mount(8), which needs the device or mountpoint to be in fstab if we're not root.
+    Mount,
+    /// Expect the device to be mounted already, and leave it mounted when we're done.
+    #[serde(rename = "none")]
+    External,
+}
+
+/// Something to do to a device once all of its files have been staged.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum DeviceAction {
+    /// Turn the camera off, so that it doesn't sit on the charger running hot.
+    PowerDown,
+    /// Set the camera's clock from ours.
+    SyncClock,
+    /// Unmount the device and power it off so that it can be removed.
+    Eject,
+    /// Write the configured template over the flysight's `config.txt`.
+    PushConfig,
+}
+
+impl fmt::Display for DeviceAction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            DeviceAction::PowerDown => "power_down",
+            DeviceAction::SyncClock => "sync_clock",
+            DeviceAction::Eject => "eject",
+            DeviceAction::PushConfig => "push_config",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// What to do with the files left on a device once they've been staged.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Hash)]
+#[serde(tag = "policy", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AfterStaging {
+    /// Remove them from the device.
+    Delete,
+    /// Move them into `archived/` on the device. If `min_free_space` is set, the oldest archived
+    /// files are pruned whenever there are fewer than that many bytes free.
+    Archive {
+        #[serde(default)]
+        min_free_space: Option<u64>,
+    },
+    /// Leave anything modified in the last `days` days where it is, and delete the rest. Files
+    /// that are left are recorded on the device so they aren't staged again, and are deleted by a
+    /// later run once they're old enough.
+    KeepRecent {
+        days: u64,
+    },
+}
+
+impl Default for AfterStaging {
+    fn default() -> Self {
+        AfterStaging::Delete
+    }
+}
+
+/// A format that flysight tracks can be converted into when they're staged.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Copy, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TrackFormat {
+    Gpx,
+    Kml,
+}
+
+impl TrackFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            TrackFormat::Gpx => "gpx",
+            TrackFormat::Kml => "kml",
+        }
+    }
+}
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Hash)]
 #[serde(deny_unknown_fields)]
@@ -223,12 +443,42 @@ pub struct FlysightConfig {
     pub name: String,
     #[serde(flatten)]
     pub location: MountableDeviceLocation,
+    /// How to mount this location, if not the default for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mounter: Option<Mounter>,
+    /// Actions to perform once this flysight has been staged.
+    #[serde(default)]
+    pub post_staging: Vec<DeviceAction>,
+    /// Formats to convert each track into, staged alongside the original CSV.
+    #[serde(default)]
+    pub exports: Vec<TrackFormat>,
+    /// The contents of `config.txt` to write back to this flysight with `push_config`.
+    #[serde(default)]
+    pub config_template: Option<String>,
+    /// What to do with tracks once they've been staged.
+    #[serde(default)]
+    pub after_staging: AfterStaging,
 }
 
 impl FlysightConfig {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// A flysight mounted at `path`, with everything else left at its defaults for tests to
+    /// override.
+    #[cfg(test)]
+    pub fn test_config<P: Into<PathBuf>>(name: &str, path: P) -> FlysightConfig {
+        FlysightConfig {
+            name: name.into(),
+            location: MountableDeviceLocation::from_mountpoint(path.into()),
+            mounter: None,
+            post_staging: vec![],
+            exports: vec![],
+            config_template: None,
+            after_staging: Default::default(),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
@@ -236,6 +486,9 @@ impl FlysightConfig {
 pub struct LocalBackupConfig {
     #[serde(flatten)]
     pub location: MountableDeviceLocation,
+    /// How to mount this location, if not the default for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mounter: Option<Mounter>,
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone, Hash)]
@@ -244,7 +497,70 @@ pub struct MassStorageConfig {
     pub name: String,
     #[serde(flatten)]
     pub location: MountableDeviceLocation,
+    /// How to mount this location, if not the default for it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub mounter: Option<Mounter>,
     pub extensions: Vec<String>,
+    /// Actions to perform once this device has been staged.
+    #[serde(default)]
+    pub post_staging: Vec<DeviceAction>,
+    #[serde(flatten)]
+    pub filters: MassStorageFilters,
+    /// What to do with files once they've been staged.
+    #[serde(default)]
+    pub after_staging: AfterStaging,
+}
+
+impl MassStorageConfig {
+    /// A device mounted at `path` that stages mp4s, with everything else left at its defaults for
+    /// tests to override.
+    #[cfg(test)]
+    pub fn test_config<P: Into<PathBuf>>(name: &str, path: P) -> MassStorageConfig {
+        MassStorageConfig {
+            name: name.into(),
+            location: MountableDeviceLocation::from_mountpoint(path.into()),
+            mounter: None,
+            extensions: vec!["mp4".into()],
+            post_staging: vec![],
+            filters: Default::default(),
+            after_staging: Default::default(),
+        }
+    }
+}
+
+/// Narrows down which files on a mass storage device get staged, on top of its `extensions`.
+///
+/// Patterns are globs relative to the root of the device, and are matched case insensitively.
+/// Hidden files and directories are always skipped.
+#[derive(Serialize, Deserialize, Debug, Default, Eq, PartialEq, Clone, Hash)]
+pub struct MassStorageFilters {
+    /// Only stage files matching at least one of these patterns.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+    /// Never stage files matching any of these patterns.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude: Vec<String>,
+    /// Directories not to look in at all, eg `MISC`.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_dirs: Vec<String>,
+    /// Sizes in bytes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+    /// Skip files modified in the last this many minutes, which might still be being written.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub older_than_minutes: Option<u64>,
+}
+
+impl MassStorageFilters {
+    /// Every pattern used by these filters, so that they can be checked up front.
+    pub fn patterns(&self) -> impl Iterator<Item = &String> {
+        self.include
+            .iter()
+            .chain(self.exclude.iter())
+            .chain(self.exclude_dirs.iter())
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
@@ -273,6 +589,51 @@ pub struct SendgridConfig {
 pub struct GoproConfig {
     pub name: String,
     pub serial: String,
+    /// Archive photos (including timelapses and bursts) as well as videos.
+    #[serde(default)]
+    pub photos: bool,
+    /// Archive the low resolution proxies (`.LRV`) and thumbnails (`.THM`) alongside each video.
+    #[serde(default)]
+    pub proxies: bool,
+    /// Actions to perform once this gopro has been staged.
+    #[serde(default)]
+    pub post_staging: Vec<DeviceAction>,
+}
+
+/// A PTP or MTP camera, matched by its USB vendor and product ids and its serial number. At
+/// least one of these must be set, and every one that is set must match.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct PtpConfig {
+    pub name: String,
+    pub vendor_id: Option<u16>,
+    pub product_id: Option<u16>,
+    pub serial: Option<String>,
+    /// Actions to perform once this device has been staged.
+    #[serde(default)]
+    pub post_staging: Vec<DeviceAction>,
+}
+
+impl PtpConfig {
+    /// Whether this config matches a device with these USB ids, before we've asked it for its
+    /// serial. Configs which only match on serial never match here.
+    pub fn matches_usb_ids(&self, vendor_id: u16, product_id: u16) -> bool {
+        (self.vendor_id.is_some() || self.product_id.is_some()) &&
+            self.vendor_id.map_or(true, |id| id == vendor_id) &&
+            self.product_id.map_or(true, |id| id == product_id)
+    }
+
+    /// Whether this config matches a device with these USB ids and serial.
+    pub fn matches(&self, vendor_id: u16, product_id: u16, serial: &str) -> bool {
+        self.is_matchable() &&
+            self.vendor_id.map_or(true, |id| id == vendor_id) &&
+            self.product_id.map_or(true, |id| id == product_id) &&
+            self.serial.as_ref().map_or(true, |s| s == serial)
+    }
+
+    fn is_matchable(&self) -> bool {
+        self.vendor_id.is_some() || self.product_id.is_some() || self.serial.is_some()
+    }
 }
 
 #[derive(Fail, Debug, PartialEq)]
@@ -291,28 +652,54 @@ pub enum ConfigError {
     InvalidApiBase(url::ParseError),
     #[fail(display = "The token file does not exist. Did you login?")]
     NoTokenFile,
+    #[fail(display = "PTP device {} must set at least one of `vendor_id`, `product_id` or `serial`.", _0)]
+    UnmatchablePtpDevice(String),
+    #[fail(display = "Flysight {} must set a `config_template` to use `push_config`.", _0)]
+    MissingConfigTemplate(String),
+    #[fail(display = "Mass storage device {} has an invalid pattern `{}`: {}.", _0, _1, _2)]
+    InvalidPattern(String, String, String),
+    #[fail(display = "Unknown section `{}`. Is it a kind of device that hasn't been registered?", _0)]
+    UnknownDeviceKind(String),
+    #[fail(display = "Only one of `interval` and `cron` can be set for the daemon.")]
+    ConflictingSchedules,
+    #[fail(display = "Invalid daemon schedule `{}`: {}.", _0, _1)]
+    InvalidSchedule(String, String),
+    #[fail(display = "Couldn't find a config file, looked for {:?}. Set ARCHIVER_CONFIG or pass --config to use another.", _0)]
+    NoConfigFile(Vec<PathBuf>),
 }
 
 impl FromStr for Config {
     type Err = ConfigError;
 
     fn from_str(body: &str) -> Result<Self, Self::Err> {
-        match toml::from_str(body) {
-            Ok(config) => Self::check_config(config),
-            Err(e) => Err(ConfigError::ParseError(e))?,
-        }
+        Self::from_str_with_registry(body, &DeviceRegistry::default())
     }
 }
 
 
 impl Config {
-    pub fn from_file(path: &str) -> Result<Config, Error> {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Config, Error> {
+        Self::from_file_with_registry(path, &DeviceRegistry::default())
+    }
+
+    /// Load a config that may have sections for the kinds of device in `registry`, rather than
+    /// just the ones built in to archiver.
+    pub fn from_file_with_registry<P: AsRef<Path>>(path: P, registry: &DeviceRegistry) -> Result<Config, Error> {
         let mut fh = File::open(path)?;
         let mut contents = String::new();
         fh.read_to_string(&mut contents)?;
 
 
-        Ok(contents.parse()?)
+        Ok(Self::from_str_with_registry(&contents, registry)?)
+    }
+
+    /// Parse a config that may have sections for the kinds of device in `registry`, rather than
+    /// just the ones built in to archiver.
+    pub fn from_str_with_registry(body: &str, registry: &DeviceRegistry) -> Result<Config, ConfigError> {
+        match toml::from_str(body) {
+            Ok(config) => Self::check_config(config, registry),
+            Err(e) => Err(ConfigError::ParseError(e))?,
+        }
     }
 
     /// Get a ConfigBuilder with which you can construct a Config object
@@ -325,19 +712,47 @@ impl Config {
         toml::to_string(self).map_err(|e| ConfigError::GenerateError(e).into())
     }
 
-    fn check_config(config: Config) -> Result<Config, ConfigError> {
+    fn check_config(config: Config, registry: &DeviceRegistry) -> Result<Config, ConfigError> {
         if config.dropbox.is_none() && config.vimeo.is_none() {
             Err(ConfigError::MissingBackend)?;
         }
 
+        for kind in config.other_device_kinds() {
+            if registry.get(kind).is_none() {
+                Err(ConfigError::UnknownDeviceKind(kind.to_string()))?;
+            }
+        }
+
         Config::check_staging(&config.staging)?;
 
+        for ptp in config.ptps() {
+            if !ptp.is_matchable() {
+                Err(ConfigError::UnmatchablePtpDevice(ptp.name.clone()))?;
+            }
+        }
+
+        for mass_storage in config.mass_storages() {
+            for pattern in mass_storage.filters.patterns() {
+                if let Err(e) = glob::Pattern::new(pattern) {
+                    Err(ConfigError::InvalidPattern(mass_storage.name.clone(), pattern.clone(), e.msg.to_string()))?;
+                }
+            }
+        }
+
+        for flysight in config.flysights() {
+            if flysight.post_staging.contains(&DeviceAction::PushConfig) && flysight.config_template.is_none() {
+                Err(ConfigError::MissingConfigTemplate(flysight.name.clone()))?;
+            }
+        }
+
         if let Some(base) = &config.archiver.api_base {
             if let Err(err) = url::Url::parse(&base) {
                 Err(ConfigError::InvalidApiBase(err))?;
             }
         }
 
+        config.daemon_schedule()?;
+
         Ok(config)
     }
 
@@ -349,7 +764,9 @@ impl Config {
                     return Err(ConfigError::RelativeStaging.into());
                 }
             },
-            MountableDeviceLocation::Label(_) => {},
+            MountableDeviceLocation::Label(_) |
+            MountableDeviceLocation::Uuid(_) |
+            MountableDeviceLocation::PartUuid(_) => {},
         }
         Ok(())
     }
@@ -362,6 +779,29 @@ impl Config {
         }
     }
 
+    /// When the daemon should run a full cycle, if it should run them at all.
+    pub fn daemon_schedule(&self) -> Result<Option<Schedule>, ConfigError> {
+        let daemon = match &self.daemon {
+            Some(daemon) => daemon,
+            None => return Ok(None),
+        };
+        match (&daemon.interval, &daemon.cron) {
+            (Some(_), Some(_)) => Err(ConfigError::ConflictingSchedules),
+            (Some(interval), None) => Schedule::interval(interval)
+                .map(Some)
+                .map_err(|e| ConfigError::InvalidSchedule(interval.clone(), e)),
+            (None, Some(cron)) => Schedule::cron(cron)
+                .map(Some)
+                .map_err(|e| ConfigError::InvalidSchedule(cron.clone(), e)),
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// How many devices to stage from at once.
+    pub fn staging_concurrency(&self) -> usize {
+        self.archiver.staging_concurrency.unwrap_or(DEFAULT_STAGING_CONCURRENCY)
+    }
+
     // Do we eventually want to make a camera/mass_storage distinction?
     pub fn mass_storages(&self) -> &Vec<MassStorageConfig> {
         match self.mass_storage {
@@ -384,6 +824,29 @@ impl Config {
         }
     }
 
+    pub fn ptps(&self) -> &Vec<PtpConfig> {
+        match self.ptp {
+            None => &EMPTY_PTPS,
+            Some(ref v) => v,
+        }
+    }
+
+    /// The names of the sections in this config that belong to device kinds that aren't built in.
+    pub fn other_device_kinds(&self) -> impl Iterator<Item = &str> {
+        self.devices.keys().map(|k| &k[..])
+    }
+
+    /// Parse the entries in the `kind` section, for device kinds that aren't built in. The built in
+    /// sections are always parsed into their own fields, so this finds nothing for them, even for
+    /// a kind registered in place of a built in one.
+    pub fn devices_of<T>(&self, kind: &str) -> Result<Vec<T>, Error>
+    where T: DeserializeOwned {
+        match self.devices.get(kind) {
+            Some(entries) => Ok(entries.clone().try_into()?),
+            None => Ok(vec![]),
+        }
+    }
+
     pub fn notifier(&self) -> Option<Box<dyn Notify>> {
         // Loool
         if let Some(ref web) = self.web_notifications {
@@ -505,6 +968,19 @@ impl ConfigBuilder {
         gopros.into_iter().fold(self, |cfg, gopro| cfg.gopro(gopro))
     }
 
+    /// Add this ptp device to the config object
+    pub fn ptp(mut self, ptp: PtpConfig) -> Self {
+        let mut ptps = self.ptp.unwrap_or_else(|| vec![]);
+        ptps.push(ptp);
+        self.ptp = Some(ptps);
+        self
+    }
+
+    /// Add multiple ptp devices to this config
+    pub fn ptps(self, ptps: Vec<PtpConfig>) -> Self {
+        ptps.into_iter().fold(self, |cfg, ptp| cfg.ptp(ptp))
+    }
+
     /// Add a local backup to this config
     pub fn local_backup(mut self, local_backup: LocalBackupConfig) -> Self {
         let mut local_backups = self.local_backup.unwrap_or_else(|| vec![]);
@@ -539,8 +1015,26 @@ impl ConfigBuilder {
         self
     }
 
+    /// Add an entry to the `kind` section of this config, for kinds of device that aren't built
+    /// in.
+    pub fn device(mut self, kind: &str, entry: toml::Value) -> Self {
+        let section = self.devices
+            .entry(kind.to_string())
+            .or_insert_with(|| toml::Value::Array(vec![]));
+        if let toml::Value::Array(entries) = section {
+            entries.push(entry);
+        }
+        self
+    }
+
     /// Finalise this config object
     pub fn finish(self) -> Result<Config, ConfigError> {
+        self.finish_with_registry(&DeviceRegistry::default())
+    }
+
+    /// Finalise this config object, checking its device sections against `registry` rather than
+    /// the kinds that are built in.
+    pub fn finish_with_registry(self, registry: &DeviceRegistry) -> Result<Config, ConfigError> {
         let staging = match self.staging {
             Some(staging) => staging,
             None => return Err(ConfigError::MissingStaging),
@@ -552,12 +1046,15 @@ impl ConfigBuilder {
             vimeo: self.vimeo,
             flysight: self.flysight,
             gopro: self.gopro,
+            ptp: self.ptp,
             local_backup: self.local_backup,
             mass_storage: self.mass_storage,
             sendgrid: self.sendgrid,
             pushover: self.pushover,
             web_notifications: self.web_notifications,
-        })
+            daemon: None,
+            devices: self.devices,
+        }, registry)
     }
 }
 
@@ -575,6 +1072,7 @@ mod tests {
             ArchiverConfig {
                 api_token: Some("ARCHIVER_TOKEN_GOES_HERE".into()),
                 api_base: Some("https://test-api.base".into()),
+                staging_concurrency: None,
             }
         );
 
@@ -582,6 +1080,7 @@ mod tests {
             config.staging,
             StagingConfig {
                 location: MountableDeviceLocation::Mountpoint("/test/staging/dir".into()),
+                mounter: None,
             }
         );
 
@@ -594,19 +1093,12 @@ mod tests {
 
         assert_eq!(
             config.flysight,
-            Some(vec![FlysightConfig {
-                name: "data".into(),
-                location: MountableDeviceLocation::from_mountpoint("/mnt/archiver/flysight".into()),
-            }])
+            Some(vec![FlysightConfig::test_config("data", "/mnt/archiver/flysight")])
         );
 
         assert_eq!(
             config.mass_storage,
-            Some(vec![MassStorageConfig {
-                name: "video".into(),
-                location: MountableDeviceLocation::from_mountpoint("/mnt/archiver/mass_storage".into()),
-                extensions: vec!["mp4".into()],
-            }])
+            Some(vec![MassStorageConfig::test_config("video", "/mnt/archiver/mass_storage")])
         );
 
         assert_eq!(
@@ -673,7 +1165,8 @@ token = "TOKEN"
         .unwrap();
         assert_eq!(cfg.staging,
                    StagingConfig {
-                       location: MountableDeviceLocation::Mountpoint("/mnt/staging".into())
+                       location: MountableDeviceLocation::Mountpoint("/mnt/staging".into()),
+                       mounter: None,
                    });
     }
 
@@ -693,7 +1186,158 @@ token = "TOKEN"
         assert_eq!(cfg.staging,
                    StagingConfig {
                        location: MountableDeviceLocation::Label("STAGING".into()),
+                       mounter: None,
+                   });
+    }
+
+    #[test]
+    fn test_staging_mounter() {
+        let cfg = Config::from_str(
+            r#"
+[archiver]
+[staging]
+mountpoint="/mnt/staging"
+mounter="mount"
+
+[dropbox]
+token = "TOKEN"
+"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.staging,
+                   StagingConfig {
+                       location: MountableDeviceLocation::Mountpoint("/mnt/staging".into()),
+                       mounter: Some(Mounter::Mount),
                    });
+
+        let cfg = Config::from_str(
+            r#"
+[archiver]
+[staging]
+label="STAGING"
+mounter="none"
+
+[dropbox]
+token = "TOKEN"
+"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.staging.mounter, Some(Mounter::External));
+    }
+
+    #[test]
+    fn test_staging_uuid() {
+        let cfg = Config::from_str(
+            r#"
+[archiver]
+[staging]
+uuid="5C8A-1F2B"
+
+[dropbox]
+token = "TOKEN"
+"#,
+        )
+        .unwrap();
+        assert_eq!(cfg.staging.location, MountableDeviceLocation::Uuid("5C8A-1F2B".into()));
+    }
+
+    #[test]
+    fn test_daemon_schedules() {
+        let config = |daemon: &str| Config::from_str(&format!(
+            r#"
+[archiver]
+[staging]
+mountpoint="/test/staging/dir"
+
+[dropbox]
+token = "TOKEN"
+
+[daemon]
+{}
+"#, daemon));
+
+        assert!(config("").unwrap().daemon_schedule().unwrap().is_none());
+        match config(r#"interval = "6h""#).unwrap().daemon_schedule().unwrap() {
+            Some(Schedule::Interval(interval)) => assert_eq!(interval.as_secs(), 6 * 60 * 60),
+            other => panic!("Unexpected schedule: {:?}", other),
+        }
+        assert!(config(r#"cron = "0 4 * * *""#).unwrap().daemon_schedule().unwrap().is_some());
+
+        match config("interval = \"6h\"\ncron = \"0 4 * * *\"") {
+            Err(ConfigError::ConflictingSchedules) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+        match config(r#"cron = "at teatime""#) {
+            Err(ConfigError::InvalidSchedule(..)) => (),
+            other => panic!("Unexpected result: {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_other_device_kinds() {
+        #[derive(Deserialize, Debug, PartialEq)]
+        struct AltimeterConfig {
+            name: String,
+            port: String,
+        }
+
+        let cfg = Config::from_str_with_registry(
+            r#"
+[archiver]
+[staging]
+mountpoint="/test/staging/dir"
+
+[dropbox]
+token = "TOKEN"
+
+[[altimeter]]
+name = "wrist"
+port = "/dev/ttyUSB0"
+"#,
+            &DeviceRegistry::default().register(test_helpers::AltimeterKind),
+        )
+        .unwrap();
+        assert_eq!(cfg.other_device_kinds().collect::<Vec<_>>(), vec!["altimeter"]);
+        assert_eq!(cfg.devices_of::<AltimeterConfig>("altimeter").unwrap(), vec![AltimeterConfig {
+            name: "wrist".into(),
+            port: "/dev/ttyUSB0".into(),
+        }]);
+        assert_eq!(cfg.devices_of::<AltimeterConfig>("barometer").unwrap(), vec![]);
+    }
+
+    #[test]
+    fn test_misspelled_sections_are_rejected() {
+        let err = Config::from_str(
+            r#"
+[archiver]
+[staging]
+mountpoint="/test/staging/dir"
+
+[dropbox]
+token = "TOKEN"
+
+[dropbx]
+token = "TOKEN"
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(err, ConfigError::UnknownDeviceKind("dropbx".into()));
+    }
+
+    #[test]
+    fn test_locations_from_identifiers() {
+        assert_eq!(MountableDeviceLocation::from_identifier("UUID=5C8A-1F2B"),
+                   MountableDeviceLocation::Uuid("5C8A-1F2B".into()));
+        assert_eq!(MountableDeviceLocation::from_identifier("PARTUUID=b3d6e1c4-01"),
+                   MountableDeviceLocation::PartUuid("b3d6e1c4-01".into()));
+        assert_eq!(MountableDeviceLocation::from_identifier("LABEL=NO NAME"),
+                   MountableDeviceLocation::Label("NO NAME".into()));
+        assert_eq!(MountableDeviceLocation::from_identifier("FRONT"),
+                   MountableDeviceLocation::Label("FRONT".into()));
+
+        for identifier in &["UUID=5C8A-1F2B", "PARTUUID=b3d6e1c4-01", "LABEL=NO NAME"] {
+            assert_eq!(&MountableDeviceLocation::from_identifier(identifier).to_identifier(), identifier);
+        }
     }
 
     #[test]
@@ -833,15 +1477,22 @@ token="DROPBOX_TOKEN_GOES_HERE"
         assert_eq!(
             cfg.mass_storages(),
             &vec![
+                MassStorageConfig::test_config("front", "/mnt/archiver/front"),
                 MassStorageConfig {
-                    name: "front".into(),
-                    location: MountableDeviceLocation::Mountpoint("/mnt/archiver/front".into()),
-                    extensions: vec!["mp4".into()],
-                },
-                MassStorageConfig {
-                    name: "back".into(),
                     location: MountableDeviceLocation::Label("back_mass_storage".into()),
                     extensions: vec!["mov".into()],
+                    post_staging: vec![DeviceAction::Eject],
+                    filters: MassStorageFilters {
+                        exclude: vec!["**/*_proxy.mov".into()],
+                        exclude_dirs: vec!["MISC".into(), ".Trashes".into()],
+                        min_size: Some(1024),
+                        older_than_minutes: Some(5),
+                        ..Default::default()
+                    },
+                    after_staging: AfterStaging::Archive {
+                        min_free_space: Some(1024 * 1024 * 1024),
+                    },
+                    ..MassStorageConfig::test_config("back", "")
                 }
             ]
         )
@@ -854,10 +1505,16 @@ token="DROPBOX_TOKEN_GOES_HERE"
                 GoproConfig {
                     name: "gopro4".into(),
                     serial: "C3131127500000".into(),
+                    photos: false,
+                    proxies: false,
+                    post_staging: vec![],
                 },
                 GoproConfig {
                     name: "gopro5".into(),
                     serial: "C3131127500001".into(),
+                    photos: true,
+                    proxies: true,
+                    post_staging: vec![DeviceAction::PowerDown, DeviceAction::SyncClock],
                 }
             ]
         )
@@ -867,13 +1524,14 @@ token="DROPBOX_TOKEN_GOES_HERE"
         assert_eq!(
             cfg.flysights(),
             &vec![
+                FlysightConfig::test_config("training", "/mnt/archiver/training"),
                 FlysightConfig {
-                    name: "training".into(),
-                    location: MountableDeviceLocation::Mountpoint("/mnt/archiver/training".into()),
-                },
-                FlysightConfig {
-                    name: "comp".into(),
                     location: MountableDeviceLocation::Label("COMP_FLYSIGHT".into()),
+                    post_staging: vec![DeviceAction::PushConfig],
+                    exports: vec![TrackFormat::Gpx, TrackFormat::Kml],
+                    config_template: Some("Model:     7 ; Dynamic model\nRate:    200 ; Measurement rate (ms)\n".into()),
+                    after_staging: AfterStaging::KeepRecent { days: 7 },
+                    ..FlysightConfig::test_config("comp", "")
                 }
             ]
         )
@@ -898,6 +1556,12 @@ extensions = ["mp4"]
 name = "back"
 label = "back_mass_storage"
 extensions = ["mov"]
+post_staging = ["eject"]
+exclude_dirs = ["MISC", ".Trashes"]
+exclude = ["**/*_proxy.mov"]
+min_size = 1024
+older_than_minutes = 5
+after_staging = { policy = "archive", min_free_space = 1073741824 }
 "#,
         )
         .unwrap();
@@ -922,6 +1586,9 @@ serial = "C3131127500000"
 [[gopro]]
 name = "gopro5"
 serial = "C3131127500001"
+photos = true
+proxies = true
+post_staging = ["power_down", "sync_clock"]
 "#,
         )
         .unwrap();
@@ -930,6 +1597,140 @@ serial = "C3131127500001"
         assert_no_flysights(&config);
     }
 
+    #[test]
+    fn test_ptps() {
+        let config = Config::from_str(
+            r#"
+[archiver]
+[staging]
+mountpoint = "/test"
+[dropbox]
+token="DROPBOX_TOKEN_GOES_HERE"
+
+[[ptp]]
+name = "a7"
+vendor_id = 0x054c
+product_id = 0x0c34
+
+[[ptp]]
+name = "5d"
+serial = "083024001234"
+"#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.ptps(),
+            &vec![
+                PtpConfig {
+                    name: "a7".into(),
+                    vendor_id: Some(0x054c),
+                    product_id: Some(0x0c34),
+                    serial: None,
+                    post_staging: vec![],
+                },
+                PtpConfig {
+                    name: "5d".into(),
+                    vendor_id: None,
+                    product_id: None,
+                    serial: Some("083024001234".into()),
+                    post_staging: vec![],
+                },
+            ]
+        );
+        assert_no_mass_storages(&config);
+        assert_no_flysights(&config);
+    }
+
+    #[test]
+    fn test_ptp_matching() {
+        let sony = PtpConfig {
+            name: "a7".into(),
+            vendor_id: Some(0x054c),
+            product_id: None,
+            serial: Some("1234".into()),
+            post_staging: vec![],
+        };
+        assert!(sony.matches_usb_ids(0x054c, 0x0c34));
+        assert!(!sony.matches_usb_ids(0x04a9, 0x0c34));
+        assert!(sony.matches(0x054c, 0x0c34, "1234"));
+        assert!(!sony.matches(0x054c, 0x0c34, "4321"));
+
+        let serial_only = PtpConfig {
+            name: "5d".into(),
+            vendor_id: None,
+            product_id: None,
+            serial: Some("1234".into()),
+            post_staging: vec![],
+        };
+        assert!(!serial_only.matches_usb_ids(0x04a9, 0x3234));
+        assert!(serial_only.matches(0x04a9, 0x3234, "1234"));
+    }
+
+    #[test]
+    fn test_ptps_must_be_matchable() {
+        let err = Config::from_str(
+            r#"
+[archiver]
+[staging]
+mountpoint = "/test"
+[dropbox]
+token="DROPBOX_TOKEN_GOES_HERE"
+
+[[ptp]]
+name = "anything"
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(err, ConfigError::UnmatchablePtpDevice("anything".into()));
+    }
+
+    #[test]
+    fn test_mass_storage_patterns_must_be_valid() {
+        let err = Config::from_str(
+            r#"
+[archiver]
+[staging]
+mountpoint = "/test"
+[dropbox]
+token="DROPBOX_TOKEN_GOES_HERE"
+
+[[mass_storage]]
+name = "front"
+mountpoint="/mnt/archiver/front"
+extensions = ["mp4"]
+include = ["DCIM/***"]
+"#,
+        )
+        .unwrap_err();
+        match err {
+            ConfigError::InvalidPattern(name, pattern, _) => {
+                assert_eq!(name, "front");
+                assert_eq!(pattern, "DCIM/***");
+            },
+            err => panic!("Unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn test_pushing_config_requires_a_template() {
+        let err = Config::from_str(
+            r#"
+[archiver]
+[staging]
+mountpoint = "/test"
+[dropbox]
+token="DROPBOX_TOKEN_GOES_HERE"
+
+[[flysight]]
+name = "training"
+mountpoint="/mnt/archiver/training"
+post_staging = ["push_config"]
+"#,
+        )
+        .unwrap_err();
+        assert_eq!(err, ConfigError::MissingConfigTemplate("training".into()));
+    }
+
     #[test]
     fn test_flysights() {
         let config = Config::from_str(
@@ -947,6 +1748,13 @@ mountpoint="/mnt/archiver/training"
 [[flysight]]
 name = "comp"
 label="COMP_FLYSIGHT"
+exports = ["gpx", "kml"]
+after_staging = { policy = "keep_recent", days = 7 }
+post_staging = ["push_config"]
+config_template = '''
+Model:     7 ; Dynamic model
+Rate:    200 ; Measurement rate (ms)
+'''
 "#,
         )
         .unwrap();
@@ -973,6 +1781,12 @@ extensions = ["mp4"]
 name = "back"
 label="back_mass_storage"
 extensions = ["mov"]
+post_staging = ["eject"]
+exclude_dirs = ["MISC", ".Trashes"]
+exclude = ["**/*_proxy.mov"]
+min_size = 1024
+older_than_minutes = 5
+after_staging = { policy = "archive", min_free_space = 1073741824 }
 
 [[flysight]]
 name = "training"
@@ -981,6 +1795,13 @@ mountpoint="/mnt/archiver/training"
 [[flysight]]
 name = "comp"
 label="COMP_FLYSIGHT"
+exports = ["gpx", "kml"]
+after_staging = { policy = "keep_recent", days = 7 }
+post_staging = ["push_config"]
+config_template = '''
+Model:     7 ; Dynamic model
+Rate:    200 ; Measurement rate (ms)
+'''
 "#,
         )
         .unwrap();
@@ -1028,4 +1849,28 @@ label="COMP_FLYSIGHT"
             "Didn't get the correct error"
         );
     }
+
+    #[test]
+    fn test_config_search_path() {
+        let home = Some(PathBuf::from("/home/archiver"));
+
+        let defaults = config_search_path_with(|_| None, home.clone());
+        assert_eq!(defaults, vec![
+            PathBuf::from("archiver.toml"),
+            PathBuf::from("/home/archiver/.config/archiver/archiver.toml"),
+            PathBuf::from("/etc/xdg/archiver/archiver.toml"),
+        ]);
+
+        let xdg = config_search_path_with(|var| match var {
+            "XDG_CONFIG_HOME" => Some("/srv/config".into()),
+            "XDG_CONFIG_DIRS" => Some("/usr/local/etc:relative:/etc".into()),
+            _ => None,
+        }, home);
+        assert_eq!(xdg, vec![
+            PathBuf::from("archiver.toml"),
+            PathBuf::from("/srv/config/archiver/archiver.toml"),
+            PathBuf::from("/usr/local/etc/archiver/archiver.toml"),
+            PathBuf::from("/etc/archiver/archiver.toml"),
+        ]);
+    }
 }
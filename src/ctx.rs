@@ -3,11 +3,9 @@ use std::fmt;
 use failure::Error;
 use libusb;
 
-// This is used, I guess maybe I should make it a phantomdata or whatever?
-#[allow(unused_imports)]
-use lockfile::Lockfile;
-
 use crate::config;
+use crate::device::DeviceRegistry;
+use crate::lock::{self, Lock};
 use crate::mailer;
 use crate::pushover_notifier::Notify;
 
@@ -20,9 +18,11 @@ pub struct Ctx {
     notifier: Option<Box<dyn Notify>>,
     /// An optional mailer that will be used to send reports when uploads finish or fail.
     pub mailer: Option<mailer::SendgridMailer>,
+    /// The kinds of device to look for.
+    registry: DeviceRegistry,
     // This lock is optional, since we can opt into building it without, but by making the lock
     // part of this API we can't accidentally end up not having one.
-    _lock: Option<lockfile::Lockfile>,
+    _lock: Option<Lock>,
 }
 
 impl fmt::Debug for Ctx {
@@ -32,35 +32,47 @@ impl fmt::Debug for Ctx {
             .field("cfg", &self.cfg)
             .field("notifier", &self.notifier)
             .field("mailer", &self.mailer)
+            .field("registry", &self.registry)
             .finish()
     }
 }
 
-fn acquire_lock() -> Result<lockfile::Lockfile, Error> {
-    let home = config::get_home()?;
-    let lock_path = home.as_ref().join(".archiver.lock");
+/// Take the archiver lock. Most things should get it by creating a `Ctx`, this is for the ones that
+/// need to hold onto it across more than one.
+pub fn acquire_lock() -> Result<Lock, Error> {
+    let lock_path = lock::lock_path()?;
     info!("Acquiring the archiver lock at {:?}", &lock_path);
-    Ok(lockfile::Lockfile::create(lock_path)?)
+    lock::acquire(&lock_path)
 }
 
 impl Ctx {
-    /// Create a new context object.
+    /// Create a new context object that looks for the kinds of device in `registry`. Most things
+    /// want `DeviceRegistry::default()`, the kinds that are built in to archiver.
     ///
     /// This method has many side effects, creating a libusb context, creating the staging
     /// direectory if it does not exist, etc.
-    pub fn create(cfg: config::Config) -> Result<Ctx, Error> {
-        Self::create_ctx(cfg, true)
+    pub fn create(cfg: config::Config, registry: DeviceRegistry) -> Result<Ctx, Error> {
+        Self::create_ctx(cfg, registry, true)
+    }
+
+    /// Create a new context object that holds a lock that's already been acquired, eg after
+    /// waiting for it.
+    pub fn create_with_lock(cfg: config::Config, registry: DeviceRegistry, lock: Lock) -> Result<Ctx, Error> {
+        let mut ctx = Self::create_ctx(cfg, registry, false)?;
+        ctx._lock = Some(lock);
+        Ok(ctx)
     }
 
     /// Create a new context object without acquiring the archiver lock.
     ///
     /// Holding an unlocked Ctx allows you to perform destructive operations with no
     /// synchronisation, it is the consumers responsibility to ensure this does not occur.
-    pub fn create_without_lock(cfg: config::Config) -> Result<Ctx, Error> {
-        Self::create_ctx(cfg, false)
+    pub fn create_without_lock(cfg: config::Config, registry: DeviceRegistry) -> Result<Ctx, Error> {
+        Self::create_ctx(cfg, registry, false)
     }
 
-    fn create_ctx(cfg: config::Config, should_lock: bool) -> Result<Ctx, Error> {
+    fn create_ctx(cfg: config::Config, registry: DeviceRegistry, should_lock: bool) -> Result<Ctx, Error> {
+        registry.check_config(&cfg)?;
         let notifier = cfg.notifier();
         let mailer = cfg.mailer();
 
@@ -75,6 +87,7 @@ impl Ctx {
             cfg,
             notifier,
             mailer,
+            registry,
             _lock,
         })
     }
@@ -86,6 +99,10 @@ impl Ctx {
         self.cfg.staging()
     }
 
+    pub fn registry(&self) -> &DeviceRegistry {
+        &self.registry
+    }
+
     pub fn notify(&self, msg: &str) -> Result<(), Error> {
         if let Some(notifier) = &self.notifier {
             return notifier.notify(msg)
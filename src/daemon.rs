@@ -0,0 +1,623 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::process;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, Sender};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use chrono::prelude::*;
+use crossbeam_utils::thread::{self, Scope};
+use failure::Error;
+use serde_json;
+use signal_hook;
+
+use crate::cancel::Cancel;
+use crate::config;
+use crate::ctx::Ctx;
+use crate::device::{self, StagingOutcome};
+use crate::hotplug::UeventSocket;
+use crate::mailer::MailReport;
+use crate::mountable::Mountable;
+use crate::progress::{self, EventKind, ProgressEvent, ProgressSink};
+use crate::reporting::{ActionEntry, UploadReport};
+use crate::schedule::Schedule;
+use crate::staging::MountedStaging;
+use crate::storage;
+use crate::systemd;
+
+/// How long to keep listening after a device shows up before looking for it. Plugging a card in
+/// produces a burst of events, and the filesystem isn't ready until the end of it.
+const SETTLE_TIME: Duration = Duration::from_secs(2);
+/// The longest we'll wait for things to settle down, in case something is being very chatty.
+const MAX_SETTLE_TIME: Duration = Duration::from_secs(10);
+/// How often to look for devices even if nothing told us to, for the devices that don't generate
+/// events and the platforms where we can't listen for them.
+const RESCAN_INTERVAL: Duration = Duration::from_secs(30);
+/// How long to wait for other devices to finish staging before starting an upload.
+const UPLOAD_DELAY: Duration = Duration::from_secs(5);
+/// How long to wait for events at a time, which is how quickly we notice signals and the schedule.
+const TICK: Duration = Duration::from_secs(1);
+
+/// Where a device that has been attached during this session is up to.
+#[derive(Debug, Clone, Eq, PartialEq, Serialize)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum DeviceState {
+    /// The device is being staged.
+    Processing,
+    /// The device was staged, and is still attached.
+    Complete { num_files: usize },
+    /// Staging the device failed. It'll be tried again when it's next attached.
+    Failed { error: String },
+    /// The device was detached.
+    Disconnected,
+}
+
+/// Tracks the devices that have been attached during this session, and decides when they need to
+/// be staged.
+#[derive(Debug, Default, Serialize)]
+pub struct DeviceTracker {
+    devices: BTreeMap<String, DeviceState>,
+}
+
+impl DeviceTracker {
+    /// Update the tracker with the names of the devices that are attached right now, returning
+    /// the ones that have just shown up and need staging. They're marked as processing.
+    ///
+    /// A device that has been staged won't be staged again until it has been detached.
+    pub fn update(&mut self, attached: &[String]) -> Vec<String> {
+        self.mark(attached, false)
+    }
+
+    /// Like `update`, except that devices which are still attached after being staged are staged
+    /// again too, so that scheduled runs pick up anything new on them.
+    pub fn restage(&mut self, attached: &[String]) -> Vec<String> {
+        self.mark(attached, true)
+    }
+
+    fn mark(&mut self, attached: &[String], again: bool) -> Vec<String> {
+        for (name, state) in self.devices.iter_mut() {
+            // A device that's being processed can appear to go away while it's being mounted or
+            // reset, so leave it to its worker to say how it went.
+            if !attached.contains(name) && *state != DeviceState::Processing {
+                *state = DeviceState::Disconnected;
+            }
+        }
+
+        let mut new = vec![];
+        for name in attached {
+            let state = self.devices.entry(name.clone()).or_insert(DeviceState::Disconnected);
+            if *state == DeviceState::Disconnected || (again && *state != DeviceState::Processing) {
+                *state = DeviceState::Processing;
+                new.push(name.clone());
+            }
+        }
+        new
+    }
+
+    /// Record how staging `name` went.
+    pub fn finish(&mut self, name: &str, outcome: &Result<StagingOutcome, Error>) {
+        let state = match outcome {
+            Ok(outcome) => DeviceState::Complete { num_files: outcome.num_files },
+            Err(e) => DeviceState::Failed { error: e.to_string() },
+        };
+        self.devices.insert(name.to_string(), state);
+    }
+
+    /// Forget about `name`, so that it's staged again the next time it's seen. This is for
+    /// devices whose staging was stopped partway through.
+    pub fn forget(&mut self, name: &str) {
+        self.devices.remove(name);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&DeviceState> {
+        self.devices.get(name)
+    }
+
+    /// Whether any device is still being staged.
+    pub fn is_processing(&self) -> bool {
+        self.devices.values().any(|state| *state == DeviceState::Processing)
+    }
+}
+
+/// Everything the daemon has to say about itself.
+#[derive(Debug, Default, Serialize)]
+struct DaemonState {
+    devices: DeviceTracker,
+    uploading: bool,
+    last_upload: Option<DateTime<Local>>,
+    next_run: Option<DateTime<Local>>,
+    /// The latest progress of each transfer that's underway, by phase and device or backend.
+    transfers: BTreeMap<String, ProgressEvent>,
+}
+
+/// Write out the state of the daemon for anyone who wants to know what it's up to.
+///
+/// The file is replaced rather than rewritten so that nobody ever reads half of it.
+fn publish(state: &DaemonState) {
+    let publish = || -> Result<(), Error> {
+        let path = config::get_home()?.as_ref().join(".archiver-daemon.json");
+        let tmp = path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(state)?)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    };
+    debug!("Daemon state: {:?}", state);
+    if let Err(e) = publish() {
+        error!("Couldn't publish daemon state: {:?}", e);
+    }
+}
+
+/// Keeps the transfers in the published state up to date, for anyone watching the daemon.
+struct StateProgress(Arc<Mutex<DaemonState>>);
+
+impl ProgressSink for StateProgress {
+    fn event(&self, event: &ProgressEvent) {
+        let mut state = self.0.lock().expect("Recording progress");
+        let key = format!("{} {}", event.phase, event.source);
+        match event.event {
+            EventKind::Started | EventKind::Progress => {
+                state.transfers.insert(key, event.clone());
+            },
+            EventKind::Finished | EventKind::Failed => {
+                state.transfers.remove(&key);
+            },
+        }
+        publish(&state);
+    }
+}
+
+/// The signals the daemon acts on, noted down by their handlers for the main loop to pick up.
+struct Signals {
+    reload: Arc<AtomicBool>,
+    terminate: Arc<AtomicBool>,
+}
+
+impl Signals {
+    fn register() -> Result<Signals, Error> {
+        let signals = Signals {
+            reload: Default::default(),
+            terminate: Default::default(),
+        };
+        signal_hook::flag::register(signal_hook::SIGHUP, Arc::clone(&signals.reload))?;
+        for &signal in &[signal_hook::SIGTERM, signal_hook::SIGINT] {
+            signal_hook::flag::register(signal, Arc::clone(&signals.terminate))?;
+        }
+        Ok(signals)
+    }
+}
+
+/// Keeps systemd's watchdog happy, if it's watching.
+struct Watchdog {
+    interval: Option<Duration>,
+    last_ping: Option<Instant>,
+}
+
+impl Watchdog {
+    fn new() -> Watchdog {
+        Watchdog {
+            // Pinging twice as often as we have to leaves room for a slow loop.
+            interval: systemd::watchdog_interval().map(|interval| interval / 2),
+            last_ping: None,
+        }
+    }
+
+    fn ping(&mut self) {
+        if let Some(interval) = self.interval {
+            if self.last_ping.map(|last| last.elapsed() >= interval).unwrap_or(true) {
+                systemd::notify_or_log("WATCHDOG=1");
+                self.last_ping = Some(Instant::now());
+            }
+        }
+    }
+}
+
+/// Why the daemon stopped serving.
+enum Exit {
+    /// The config was reloaded, and the daemon should carry on with this context instead.
+    Reload(Ctx),
+    Shutdown,
+}
+
+/// Run the daemon, staging devices as they're attached and uploading from staging in the
+/// background, with full runs on the configured schedule.
+///
+/// `load` creates the context, and is called again to reload it on SIGHUP, which takes effect once
+/// the devices being staged and any upload underway have finished. On SIGTERM or SIGINT the files
+/// being staged or uploaded are finished, and everything is cleaned up before this returns.
+pub fn run<F>(load: F) -> Result<(), Error>
+where F: Fn() -> Result<Ctx, Error>,
+{
+    let socket = UeventSocket::open()?;
+    let signals = Signals::register()?;
+    let state: Arc<Mutex<DaemonState>> = Default::default();
+    progress::install(StateProgress(Arc::clone(&state)));
+    let mut watchdog = Watchdog::new();
+    let mut ctx = load()?;
+
+    loop {
+        let staging = ctx.staging().mount()?;
+        info!("Staging to {:?}", &staging);
+
+        let exit = thread::scope(|scope| {
+            let (uploads, pending) = mpsc::channel();
+            let session = Session {
+                ctx: &ctx,
+                staging: &staging,
+                state: &*state,
+                schedule: schedule(&ctx),
+                uploads,
+                cancel: Cancel::new(),
+            };
+            {
+                let (ctx, staging, state, cancel) = (session.ctx, session.staging, session.state, session.cancel.clone());
+                scope.spawn(move |_| upload_worker(ctx, staging, state, pending, &cancel));
+            }
+
+            match session.serve(scope, &socket, &signals, &load, &mut watchdog) {
+                Ok(exit) => Ok(session.drain(exit, &signals, &mut watchdog)),
+                Err(e) => {
+                    session.drain(Exit::Shutdown, &signals, &mut watchdog);
+                    Err(e)
+                },
+            }
+        }).map_err(|_| format_err!("A daemon worker panicked"))??;
+
+        match exit {
+            Exit::Reload(new) => {
+                info!("Reloaded config");
+                ctx = new;
+            },
+            Exit::Shutdown => {
+                info!("Shut down cleanly");
+                return Ok(());
+            },
+        }
+    }
+}
+
+fn schedule(ctx: &Ctx) -> Option<Schedule> {
+    match ctx.cfg.daemon_schedule() {
+        Ok(schedule) => schedule,
+        // Checked when the config was loaded, so this shouldn't happen.
+        Err(e) => {
+            error!("Couldn't read the daemon schedule: {:?}", e);
+            None
+        },
+    }
+}
+
+/// Everything the daemon works with for as long as one context is loaded.
+struct Session<'env> {
+    ctx: &'env Ctx,
+    staging: &'env MountedStaging,
+    state: &'env Mutex<DaemonState>,
+    schedule: Option<Schedule>,
+    uploads: Sender<Vec<ActionEntry>>,
+    cancel: Cancel,
+}
+
+impl<'env> Session<'env> {
+    /// Wait for devices, signals and scheduled runs until we're asked to reload or shut down.
+    fn serve<F>(
+        &self,
+        scope: &Scope<'env>,
+        socket: &UeventSocket,
+        signals: &Signals,
+        load: &F,
+        watchdog: &mut Watchdog,
+    ) -> Result<Exit, Error>
+    where F: Fn() -> Result<Ctx, Error>,
+    {
+        info!("Waiting for devices");
+        self.schedule_next_run();
+        self.rescan(scope, false);
+        let mut last_scan = Instant::now();
+        systemd::notify_or_log("READY=1");
+
+        loop {
+            watchdog.ping();
+
+            if signals.terminate.swap(false, Ordering::SeqCst) {
+                info!("Shutting down once the current files are done");
+                systemd::notify_or_log("STOPPING=1");
+                return Ok(Exit::Shutdown);
+            }
+
+            if signals.reload.swap(false, Ordering::SeqCst) {
+                info!("Reloading config");
+                systemd::notify_or_log("RELOADING=1");
+                match load() {
+                    Ok(ctx) => return Ok(Exit::Reload(ctx)),
+                    Err(e) => {
+                        error!("Couldn't reload config, carrying on with the old one: {:?}", e);
+                        systemd::notify_or_log("READY=1");
+                    },
+                }
+            }
+
+            if self.run_is_due() {
+                info!("Starting scheduled run");
+                self.rescan(scope, true);
+                last_scan = Instant::now();
+                // The uploader only goes away with the session
+                let _ = self.uploads.send(vec![]);
+                self.schedule_next_run();
+            }
+
+            match socket.recv_timeout(TICK) {
+                Ok(Some(event)) => {
+                    if event.is_interesting() {
+                        info!("{} {} ({})", event.action, event.devpath, event.subsystem);
+                        settle(socket);
+                        self.rescan(scope, false);
+                        last_scan = Instant::now();
+                    }
+                },
+                Ok(None) => {},
+                // Most likely ENOBUFS, from a burst of events overflowing the socket, so we may
+                // have missed something and have to go and look.
+                Err(e) => {
+                    warn!("Couldn't receive device events, looking for devices instead: {:?}", e);
+                    self.rescan(scope, false);
+                    last_scan = Instant::now();
+                    // Don't spin if the socket keeps failing.
+                    std::thread::sleep(TICK);
+                },
+            }
+
+            if last_scan.elapsed() >= RESCAN_INTERVAL {
+                self.rescan(scope, false);
+                last_scan = Instant::now();
+            }
+        }
+    }
+
+    fn run_is_due(&self) -> bool {
+        let state = self.state.lock().expect("Checking schedule");
+        state.next_run.map(|next| next <= Local::now()).unwrap_or(false)
+    }
+
+    fn schedule_next_run(&self) {
+        let mut state = self.state.lock().expect("Scheduling next run");
+        state.next_run = self.schedule.as_ref().and_then(|schedule| schedule.next_after(Local::now()));
+        if let Some(next) = state.next_run {
+            info!("Next scheduled run at {}", next);
+        }
+        publish(&state);
+    }
+
+    /// Wait for the workers to finish, and clean up after themselves. When shutting down they're
+    /// stopped once they're done with their current files, but when reloading they're left to
+    /// finish everything they started under the old config.
+    ///
+    /// Being told to terminate while we wait stops them if they weren't already, and shuts down
+    /// instead of reloading. Being told again aborts the files they're working on, and being told
+    /// once more gives up on cleaning up and exits. Returns how to carry on.
+    fn drain(self, mut exit: Exit, signals: &Signals, watchdog: &mut Watchdog) -> Exit {
+        if let Exit::Shutdown = exit {
+            self.cancel.stop();
+        } else {
+            info!("Reloading once the current devices and uploads are done");
+        }
+        // Hanging up on the uploader lets it finish once the last of the staging workers has.
+        drop(self.uploads);
+        loop {
+            {
+                let state = self.state.lock().expect("Draining workers");
+                if !state.uploading && !state.devices.is_processing() {
+                    break;
+                }
+            }
+            if signals.terminate.swap(false, Ordering::SeqCst) {
+                if self.cancel.is_aborting() {
+                    error!("Exiting without waiting for the current transfers to clean up");
+                    process::exit(1);
+                } else if self.cancel.is_stopping() {
+                    info!("Aborting the current transfers");
+                    self.cancel.abort();
+                } else {
+                    info!("Shutting down once the current files are done");
+                    systemd::notify_or_log("STOPPING=1");
+                    self.cancel.stop();
+                    exit = Exit::Shutdown;
+                }
+            }
+            watchdog.ping();
+            std::thread::sleep(TICK);
+        }
+        exit
+    }
+
+    /// Look at what's attached, and set off a worker for each device that needs staging. If
+    /// `restage` is set, devices that have already been staged are staged again.
+    fn rescan(&self, scope: &Scope<'env>, restage: bool) {
+        let devices = device::attached_devices(self.ctx);
+        let attached: Vec<String> = devices.iter().map(|d| d.name().to_string()).collect();
+
+        let new = {
+            let mut state = self.state.lock().expect("Updating device state");
+            let new = if restage {
+                state.devices.restage(&attached)
+            } else {
+                state.devices.update(&attached)
+            };
+            publish(&state);
+            new
+        };
+
+        for device in devices.into_iter().filter(|d| new.iter().any(|n| n == d.name())) {
+            let name = device.name().to_string();
+            info!("Dispatching worker for {}", &name);
+            let (ctx, staging, state) = (self.ctx, self.staging, self.state);
+            let uploads = self.uploads.clone();
+            let cancel = self.cancel.clone();
+            scope.spawn(move |_| {
+                let outcome = device.stage_files(staging, &cancel);
+
+                match outcome {
+                    Ok(ref outcome) => info!("Staged {} files from {}", outcome.num_files, &name),
+                    Err(ref e) => error!("Couldn't stage {}: {:?}", &name, e),
+                }
+                {
+                    let mut state = state.lock().expect("Finishing device");
+                    if cancel.is_stopping() {
+                        // Pick up where we left off once we're running again.
+                        state.devices.forget(&name);
+                    } else {
+                        state.devices.finish(&name, &outcome);
+                    }
+                    publish(&state);
+                }
+
+                if let Ok(outcome) = outcome {
+                    if outcome.num_files > 0 {
+                        if let Err(e) = ctx.notify(&format!("Finished staging: {}", &name)) {
+                            error!("Failed to send push notification: {:?}", e);
+                        }
+                    }
+                    if outcome.num_files > 0 || !outcome.actions.is_empty() {
+                        // The uploader only goes away with the session
+                        let _ = uploads.send(outcome.actions);
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// Wait until events stop arriving. If they can't be received, we stop waiting and let the rescan
+/// find whatever turned up.
+fn settle(socket: &UeventSocket) {
+    let start = Instant::now();
+    while start.elapsed() < MAX_SETTLE_TIME {
+        match socket.recv_timeout(SETTLE_TIME) {
+            Ok(Some(_)) => {},
+            Ok(None) => break,
+            Err(e) => {
+                warn!("Couldn't receive device events while settling: {:?}", e);
+                break;
+            },
+        }
+    }
+}
+
+/// Upload whatever has been staged each time a device finishes staging, or a scheduled run starts.
+fn upload_worker(ctx: &Ctx, staging: &MountedStaging, state: &Mutex<DaemonState>, pending: Receiver<Vec<ActionEntry>>, cancel: &Cancel) {
+    while let Ok(mut device_actions) = pending.recv() {
+        // Give anything else that was plugged in at the same time a chance to finish, so that
+        // they all end up in the one report.
+        std::thread::sleep(UPLOAD_DELAY);
+        while let Ok(actions) = pending.try_recv() {
+            device_actions.extend(actions);
+        }
+        if cancel.is_stopping() {
+            break;
+        }
+
+        {
+            let mut state = state.lock().expect("Starting upload");
+            state.uploading = true;
+            publish(&state);
+        }
+
+        // Devices can carry on staging while we upload. Files only show up for uploading once
+        // they're staged in full, and anything staged after we start is left for the next upload.
+        let mut report = UploadReport::default();
+        let result = storage::upload_staged(staging, &ctx.cfg.backends(), &mut report, cancel);
+
+        {
+            let mut state = state.lock().expect("Finishing upload");
+            state.uploading = false;
+            state.last_upload = Some(Local::now());
+            publish(&state);
+        }
+
+        if let Err(e) = result {
+            error!("Couldn't upload staged files: {:?}", e);
+            continue;
+        }
+        for action in device_actions {
+            report.record_device_action(action);
+        }
+
+        if report.num_uploads() == 0 {
+            info!("Not mailing report as no work was scheduled");
+            continue;
+        }
+
+        if let Err(e) = ctx.notify("Finished uploading media") {
+            error!("Failed to send push notification: {:?}", e);
+        }
+        match report.to_plaintext() {
+            Ok(plaintext) => {
+                if let Err(e) = ctx.mailer.send_report(&plaintext) {
+                    error!("Failed to send upload report: {:?}", e);
+                }
+            },
+            Err(e) => error!("Couldn't render upload report: {:?}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    fn staged(num_files: usize) -> Result<StagingOutcome, Error> {
+        Ok(StagingOutcome { num_files, actions: vec![], cancelled: false })
+    }
+
+    #[test]
+    fn test_stages_devices_once_per_attachment() {
+        let mut tracker = DeviceTracker::default();
+        assert_eq!(tracker.update(&names(&["front", "back"])), names(&["front", "back"]));
+        assert_eq!(tracker.update(&names(&["front", "back"])), names(&[]));
+
+        tracker.finish("front", &staged(3));
+        tracker.finish("back", &Err(format_err!("Card is corrupt")));
+        assert_eq!(tracker.update(&names(&["front", "back"])), names(&[]));
+        assert_eq!(tracker.get("front"), Some(&DeviceState::Complete { num_files: 3 }));
+
+        // Detaching and reattaching stages them again
+        assert_eq!(tracker.update(&names(&["front"])), names(&[]));
+        assert_eq!(tracker.get("back"), Some(&DeviceState::Disconnected));
+        assert_eq!(tracker.update(&names(&["front", "back"])), names(&["back"]));
+    }
+
+    #[test]
+    fn test_processing_devices_arent_disconnected() {
+        let mut tracker = DeviceTracker::default();
+        tracker.update(&names(&["gopro"]));
+        // Cameras drop off the bus when we put them into a different mode
+        assert_eq!(tracker.update(&names(&[])), names(&[]));
+        assert_eq!(tracker.get("gopro"), Some(&DeviceState::Processing));
+
+        tracker.finish("gopro", &staged(0));
+        tracker.update(&names(&[]));
+        assert_eq!(tracker.get("gopro"), Some(&DeviceState::Disconnected));
+    }
+
+    #[test]
+    fn test_scheduled_runs_restage_attached_devices() {
+        let mut tracker = DeviceTracker::default();
+        tracker.update(&names(&["front", "back", "flysight"]));
+        tracker.finish("front", &staged(3));
+        tracker.finish("back", &Err(format_err!("Card is corrupt")));
+
+        // Devices still being staged are left alone, and detached ones aren't looked for
+        assert_eq!(tracker.restage(&names(&["front", "back", "flysight"])), names(&["front", "back"]));
+        assert!(tracker.is_processing());
+
+        tracker.finish("front", &staged(0));
+        tracker.forget("back");
+        tracker.finish("flysight", &staged(1));
+        assert!(!tracker.is_processing());
+        assert_eq!(tracker.update(&names(&["front", "back"])), names(&["back"]));
+        assert_eq!(tracker.get("flysight"), Some(&DeviceState::Disconnected));
+    }
+}
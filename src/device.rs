@@ -1,130 +1,680 @@
-use std::collections::HashMap;
+use std::cmp;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::path::PathBuf;
+use std::sync::Mutex;
 
+use crossbeam_utils::thread;
 use failure::Error;
+use serde_json;
+use toml;
 
-use crate::config;
+use crate::cancel::Cancel;
+use crate::config::{
+    self, AfterStaging, ConfigBuilder, ConfigError, DeviceAction, FlysightConfig, GoproConfig, MassStorageConfig,
+    MassStorageFilters, MountableDeviceLocation,
+};
 use crate::ctx;
 use crate::ptp_device;
-use crate::staging::{Staging, StageableLocation};
-use crate::mountable::{Mountable, MountableFilesystem};
+use crate::reporting::ActionEntry;
+use crate::staging::{PlannedFile, Staging, StageableLocation};
+use crate::mountable::{Mountable, MountableFilesystem, MountableKind};
 
-#[derive(Eq, PartialEq, Debug, Hash)]
+#[derive(Eq, PartialEq, Debug, Hash, Clone)]
 pub struct DeviceDescription {
     pub name: String,
+    /// Actions to perform once this device has been staged.
+    pub post_staging: Vec<DeviceAction>,
 }
 
-#[derive(Eq, PartialEq, Debug, Hash)]
-// TODO(richo) if we implement the ptp connection stuff in terms of mount, suddenly we can unify
-// this whole thing behind a trait!, and I think the DeviceDescription is now pointless as well.
-pub enum Device<'a> {
-    Gopro(DeviceDescription, ptp_device::Gopro<'a>),
-    MassStorage(DeviceDescription, config::MassStorageConfig),
-    Flysight(DeviceDescription, config::FlysightConfig),
+/// What happened when we staged a device.
+#[derive(Debug)]
+pub struct StagingOutcome {
+    pub num_files: usize,
+    pub actions: Vec<ActionEntry>,
+    /// Whether staging was cancelled before everything on the device had been staged.
+    pub cancelled: bool,
 }
 
-impl Device<'_> {
-    pub fn stage_files<T>(self, destination: T) -> Result<usize, Error>
-    where T: StageableLocation {
-        match self {
-            Device::Gopro(desc, gopro) => {
-                Mountable::mount(gopro)?.stage_files(&desc.name, &destination)
-            },
-            Device::MassStorage(desc, mass_storage) => {
-                Mountable::mount(mass_storage)?.stage_files(&desc.name, &destination)
-            },
-            Device::Flysight(desc, flysight) => {
-                Mountable::mount(flysight)?.stage_files(&desc.name, &destination)
+impl StagingOutcome {
+    fn cancelled_after(num_files: usize) -> StagingOutcome {
+        StagingOutcome { num_files, actions: vec![], cancelled: true }
+    }
+}
+
+/// Stage everything on this device, and then perform each of its post staging actions in turn.
+/// The actions are skipped if we were asked to stop partway through, since they tend to assume the
+/// device was staged in full. Aborting isn't treated as an error, the outcome just says that
+/// staging was cancelled, and how many files had been staged by then.
+fn stage_and_perform_actions<S, T>(
+    desc: DeviceDescription,
+    mut device: S,
+    destination: &T,
+    cancel: &Cancel,
+) -> Result<StagingOutcome, Error>
+where S: Staging,
+      T: StageableLocation,
+{
+    let num_files = match device.stage_files(&desc.name, destination, cancel) {
+        Ok(num_files) => num_files,
+        Err(e) => {
+            if !cancel.is_aborting() {
+                return Err(e);
+            }
+            // Staging itself stops cleanly when it's aborted, so this was before the first file.
+            info!("Aborted staging {}: {}", &desc.name, e);
+            return Ok(StagingOutcome::cancelled_after(0));
+        },
+    };
+    if cancel.is_stopping() {
+        info!("Skipping post staging actions for {}", &desc.name);
+        return Ok(StagingOutcome::cancelled_after(num_files));
+    }
+
+    let actions = desc.post_staging.iter().map(|&action| {
+        info!("Performing {} on {}", action, &desc.name);
+        let result = device.perform_action(action);
+        if let Err(ref e) = result {
+            error!("Couldn't perform {} on {}: {:?}", action, &desc.name, e);
+        }
+        ActionEntry::new(desc.name.clone(), action, result)
+    }).collect();
+
+    Ok(StagingOutcome { num_files, actions, cancelled: false })
+}
+
+/// A device that has been found attached, and that we can stage files from.
+pub trait Device: fmt::Debug + Send {
+    /// The name this device was configured with.
+    fn name(&self) -> &str;
+
+    /// The name of the kind of device this is, as it was registered.
+    fn kind(&self) -> &'static str;
+
+    /// Mount the device, stage everything on it to `destination`, and then perform each of its
+    /// post staging actions in turn, stopping early if `cancel` is stopped.
+    fn stage_files(self: Box<Self>, destination: &dyn StageableLocation, cancel: &Cancel) -> Result<StagingOutcome, Error>;
+
+    /// Mount the device and work out what staging it would involve, without staging or erasing
+    /// anything. Files are only read to find their content hashes if `hash` is set.
+    fn plan(self: Box<Self>, _hash: bool) -> Result<Vec<PlannedFile>, Error> {
+        bail!("{} devices don't support planning", self.kind())
+    }
+}
+
+/// A kind of device that archiver knows how to find. Every kind is registered with a
+/// `DeviceRegistry`, which is how crates built on top of archiver can teach it about new devices.
+pub trait DeviceKind: fmt::Debug + Send + Sync {
+    /// The name of this kind, which is also the name of its section in the config, and how the web
+    /// service refers to it.
+    fn name(&self) -> &'static str;
+
+    /// Check this kind's section of the config. Kinds that aren't built in can find their entries
+    /// with `Config::devices_of`, while the built in sections are parsed into typed fields, eg
+    /// `Config::flysights`.
+    fn check_config(&self, _cfg: &config::Config) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Find the attached devices of this kind.
+    fn attached<'a>(&self, ctx: &'a ctx::Ctx) -> Result<Vec<Box<dyn Device + 'a>>, Error>;
+
+    /// Add a device of this kind that was registered with the web service to `config`, for the
+    /// configs that it hands out. By default the device becomes an entry in this kind's section
+    /// with its name and identifier, along with whatever is in its metadata.
+    fn add_registered(&self, config: ConfigBuilder, device: &RegisteredDevice<'_>) -> Result<ConfigBuilder, Error> {
+        let mut entry: toml::value::Table = match device.metadata {
+            serde_json::Value::Null => Default::default(),
+            metadata => toml::Value::try_from(metadata)?.try_into()?,
+        };
+        entry.insert("name".into(), device.name.into());
+        entry.insert("identifier".into(), device.identifier.into());
+        Ok(config.device(self.name(), toml::Value::Table(entry)))
+    }
+}
+
+/// A device as the web service keeps it, for its kind to turn into config.
+#[derive(Debug)]
+pub struct RegisteredDevice<'a> {
+    pub name: &'a str,
+    /// Whatever tells this device apart from others of its kind, eg a serial number or a label.
+    pub identifier: &'a str,
+    /// Settings specific to the kind of device.
+    pub metadata: &'a serde_json::Value,
+}
+
+/// The kinds of device that we'll look for.
+#[derive(Debug)]
+pub struct DeviceRegistry {
+    kinds: Vec<Box<dyn DeviceKind>>,
+}
+
+impl Default for DeviceRegistry {
+    /// A registry of the kinds of device that are built in to archiver.
+    fn default() -> Self {
+        DeviceRegistry::empty()
+            .register(PtpKind)
+            .register(FlysightKind)
+            .register(MassStorageKind)
+    }
+}
+
+impl DeviceRegistry {
+    /// A registry with no kinds in it at all.
+    pub fn empty() -> Self {
+        DeviceRegistry { kinds: vec![] }
+    }
+
+    /// Add a kind to this registry, replacing any kind already registered with the same name.
+    pub fn register<K>(mut self, kind: K) -> Self
+    where K: DeviceKind + 'static {
+        self.kinds.retain(|k| k.name() != kind.name());
+        self.kinds.push(Box::new(kind));
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn DeviceKind> {
+        self.kinds.iter().find(|k| k.name() == name).map(|k| &**k)
+    }
+
+    pub fn kinds(&self) -> impl Iterator<Item = &dyn DeviceKind> {
+        self.kinds.iter().map(|k| &**k)
+    }
+
+    /// Make sure that every device section in `cfg` belongs to a registered kind, and that each
+    /// kind is happy with its section.
+    pub fn check_config(&self, cfg: &config::Config) -> Result<(), Error> {
+        for kind in cfg.other_device_kinds() {
+            if self.get(kind).is_none() {
+                Err(ConfigError::UnknownDeviceKind(kind.to_string()))?;
+            }
+        }
+        for kind in self.kinds() {
+            kind.check_config(cfg)?;
+        }
+        Ok(())
+    }
+}
+
+/// A device that can be mounted to get at something we can stage from. This covers every kind
+/// that's built in, and is likely to be useful for new kinds as well.
+#[derive(Debug)]
+pub struct MountableDevice<T> {
+    kind: &'static str,
+    desc: DeviceDescription,
+    device: T,
+}
+
+impl<T> MountableDevice<T> {
+    pub fn new(kind: &'static str, desc: DeviceDescription, device: T) -> MountableDevice<T> {
+        MountableDevice { kind, desc, device }
+    }
+}
+
+impl<T> Device for MountableDevice<T>
+where T: Mountable + fmt::Debug + Send,
+      T::Target: Staging,
+{
+    fn name(&self) -> &str {
+        &self.desc.name
+    }
+
+    fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    fn stage_files(self: Box<Self>, destination: &dyn StageableLocation, cancel: &Cancel) -> Result<StagingOutcome, Error> {
+        let MountableDevice { desc, device, .. } = *self;
+        stage_and_perform_actions(desc, Mountable::mount(device)?, &destination, cancel)
+    }
+
+    fn plan(self: Box<Self>, hash: bool) -> Result<Vec<PlannedFile>, Error> {
+        let MountableDevice { desc, device, .. } = *self;
+        Mountable::mount(device)?.plan_files(&desc.name, hash)
+    }
+}
+
+/// A device that's a filesystem, like a card or a flysight. Cards get swapped between cameras, so
+/// one found where this device's card should be might say in its identity file that it belongs to
+/// another device. If that's one of `others`, it's staged as that device instead. A filesystem
+/// without an identity file is staged as this device.
+#[derive(Debug)]
+pub struct FilesystemDevice<T> {
+    kind: &'static str,
+    desc: DeviceDescription,
+    device: T,
+    others: Vec<(DeviceDescription, T)>,
+}
+
+impl<T> FilesystemDevice<T> {
+    /// `others` should be the devices of the same kind whose cards could have ended up here,
+    /// which are the ones that aren't attached themselves and the ones that share this device's
+    /// filesystem.
+    pub fn new(kind: &'static str, desc: DeviceDescription, device: T, others: Vec<(DeviceDescription, T)>) -> FilesystemDevice<T> {
+        FilesystemDevice { kind, desc, device, others }
+    }
+
+    /// Mount the filesystem, and work out which device it belongs to.
+    fn mount(self) -> Result<(DeviceDescription, T::Target), Error>
+    where T: MountableFilesystem,
+    {
+        let FilesystemDevice { desc, device, others, .. } = self;
+        let mount = device.mount_filesystem()?;
+        match mount.identity()? {
+            Some(ref identity) if identity.name != desc.name => {
+                match others.into_iter().find(|(other, _)| other.name == identity.name) {
+                    Some((other, device)) => {
+                        info!("{:?} belongs to {}, staging it as that rather than {}", mount.path(), &other.name, &desc.name);
+                        Ok((other, T::Target::from_mounted_parts(device, mount)))
+                    },
+                    None => bail!("{:?} belongs to {}, not {}", mount.path(), identity.name, desc.name),
+                }
             },
+            _ => Ok((desc, T::Target::from_mounted_parts(device, mount))),
         }
     }
+}
 
-    pub fn name(&self) -> &str {
-        match self {
-            Device::Gopro(ref desc, _)
-            | Device::MassStorage(ref desc, _)
-            | Device::Flysight(ref desc, _) => &desc.name[..],
+impl<T> Device for FilesystemDevice<T>
+where T: MountableFilesystem + fmt::Debug + Send,
+      T::Target: Staging,
+{
+    fn name(&self) -> &str {
+        &self.desc.name
+    }
+
+    fn kind(&self) -> &'static str {
+        self.kind
+    }
+
+    fn stage_files(self: Box<Self>, destination: &dyn StageableLocation, cancel: &Cancel) -> Result<StagingOutcome, Error> {
+        let (desc, mounted) = self.mount()?;
+        stage_and_perform_actions(desc, mounted, &destination, cancel)
+    }
+
+    fn plan(self: Box<Self>, hash: bool) -> Result<Vec<PlannedFile>, Error> {
+        let (desc, mounted) = self.mount()?;
+        mounted.plan_files(&desc.name, hash)
+    }
+}
+
+/// Find the filesystem devices in `configs` that are attached. Configs that name the same
+/// filesystem, like the cards of cameras that all give them the same label, make up a single
+/// device, so that it's only mounted once, and it's staged as whichever of them its identity file
+/// names. The configs that aren't attached are given to every device as well, since their cards
+/// could have ended up in any of them.
+fn attached_filesystems<'a, T, F>(kind: &'static str, configs: &'a [T], describe: F) -> Vec<FilesystemDevice<T>>
+where T: MountableFilesystem + Clone,
+      F: Fn(&'a T) -> DeviceDescription,
+{
+    let (attached, others): (Vec<_>, Vec<_>) = configs
+        .iter()
+        .map(|cfg| (describe(cfg), cfg.clone()))
+        .partition(|(_, cfg)| cfg.is_attached());
+
+    let mut filesystems: Vec<(Option<PathBuf>, Vec<(DeviceDescription, T)>)> = vec![];
+    for (desc, cfg) in attached {
+        let path = cfg.filesystem_path();
+        match filesystems.iter_mut().find(|(other, _)| path.is_some() && *other == path) {
+            Some((_, sharing)) => sharing.push((desc, cfg)),
+            None => filesystems.push((path, vec![(desc, cfg)])),
         }
     }
+
+    filesystems
+        .into_iter()
+        .map(|(path, mut sharing)| {
+            let (desc, device) = sharing.remove(0);
+            if !sharing.is_empty() {
+                let names: Vec<_> = sharing.iter().map(|(other, _)| &other.name[..]).collect();
+                info!("{} shares {:?} with {}", &desc.name, path, names.join(", "));
+            }
+            sharing.extend(others.iter().cloned());
+            FilesystemDevice::new(kind, desc, device, sharing)
+        })
+        .collect()
+}
+
+/// Cameras and other devices that we talk to over PTP, including gopros.
+#[derive(Debug)]
+struct PtpKind;
+
+impl DeviceKind for PtpKind {
+    fn name(&self) -> &'static str {
+        "ptp"
+    }
+
+    fn attached<'a>(&self, ctx: &'a ctx::Ctx) -> Result<Vec<Box<dyn Device + 'a>>, Error> {
+        Ok(locate_ptp_devices(ctx)?.collect())
+    }
+
+    /// Devices registered with the web service are all gopros, identified by their serials.
+    fn add_registered(&self, config: ConfigBuilder, device: &RegisteredDevice<'_>) -> Result<ConfigBuilder, Error> {
+        Ok(config.gopro(GoproConfig {
+            name: device.name.to_string(),
+            serial: device.identifier.to_string(),
+            photos: false,
+            proxies: false,
+            post_staging: vec![],
+        }))
+    }
+}
+
+#[derive(Debug)]
+struct FlysightKind;
+
+impl DeviceKind for FlysightKind {
+    fn name(&self) -> &'static str {
+        "flysight"
+    }
+
+    fn attached<'a>(&self, ctx: &'a ctx::Ctx) -> Result<Vec<Box<dyn Device + 'a>>, Error> {
+        Ok(locate_flysights(&ctx.cfg)?.collect())
+    }
+
+    fn add_registered(&self, config: ConfigBuilder, device: &RegisteredDevice<'_>) -> Result<ConfigBuilder, Error> {
+        Ok(config.flysight(FlysightConfig {
+            name: device.name.to_string(),
+            location: MountableDeviceLocation::from_identifier(device.identifier),
+            post_staging: vec![],
+            exports: vec![],
+            config_template: None,
+            after_staging: AfterStaging::Delete,
+            mounter: None,
+        }))
+    }
+}
+
+/// The settings kept in the metadata of a mass storage device registered with the web service.
+#[derive(Deserialize, Debug, Default)]
+struct MassStorageMetadata {
+    #[serde(default)]
+    extensions: Option<Vec<String>>,
+    #[serde(flatten)]
+    filters: MassStorageFilters,
+}
+
+#[derive(Debug)]
+struct MassStorageKind;
+
+impl DeviceKind for MassStorageKind {
+    fn name(&self) -> &'static str {
+        "mass_storage"
+    }
+
+    fn attached<'a>(&self, ctx: &'a ctx::Ctx) -> Result<Vec<Box<dyn Device + 'a>>, Error> {
+        Ok(locate_mass_storages(&ctx.cfg)?.collect())
+    }
+
+    fn add_registered(&self, config: ConfigBuilder, device: &RegisteredDevice<'_>) -> Result<ConfigBuilder, Error> {
+        let metadata: MassStorageMetadata = serde_json::from_value(device.metadata.clone())
+            .unwrap_or_else(|e| {
+                warn!("Ignoring invalid metadata for {}: {:?}", device.name, e);
+                Default::default()
+            });
+        Ok(config.mass_storage(MassStorageConfig {
+            name: device.name.to_string(),
+            extensions: metadata.extensions.unwrap_or_else(|| vec!["mp4".into()]),
+            location: MountableDeviceLocation::from_identifier(device.identifier),
+            post_staging: vec![],
+            filters: metadata.filters,
+            after_staging: AfterStaging::Delete,
+            mounter: None,
+        }))
+    }
 }
 
-pub fn attached_devices(ctx: &ctx::Ctx) -> Result<Vec<Device<'_>>, Error> {
+/// Find the attached devices of every kind registered with `ctx`. A kind that we can't look for
+/// devices of is logged and skipped, rather than hiding the devices of every other kind.
+pub fn attached_devices(ctx: &ctx::Ctx) -> Vec<Box<dyn Device + '_>> {
     let mut devices = vec![];
 
-    // Should errors actually stop us finding other devices?
-    devices.extend(locate_gopros(&ctx)?);
-    devices.extend(locate_flysights(&ctx.cfg)?);
-    devices.extend(locate_mass_storages(&ctx.cfg)?);
+    for kind in ctx.registry().kinds() {
+        match kind.attached(ctx) {
+            Ok(attached) => devices.extend(attached),
+            Err(e) => error!("Couldn't look for {} devices: {:?}", kind.name(), e),
+        }
+    }
 
-    Ok(devices)
+    devices
 }
 
-fn locate_gopros(ctx: &ctx::Ctx) -> Result<impl Iterator<Item = Device<'_>>, Error> {
+/// Stage each of `devices` to `destination`, with up to `concurrency` of them staging at once.
+///
+/// A device failing doesn't stop the others, its error is returned along with the outcomes for the
+/// rest, in the same order as `devices`. Once `cancel` is stopped no more devices are started, and
+/// the ones that weren't are reported as cancelled.
+pub fn stage_concurrently<'a>(
+    devices: Vec<Box<dyn Device + 'a>>,
+    destination: &dyn StageableLocation,
+    concurrency: usize,
+    cancel: &Cancel,
+) -> Vec<(String, Result<StagingOutcome, Error>)> {
+    let total = devices.len();
+    let queue: Mutex<VecDeque<_>> = Mutex::new(devices.into_iter().enumerate().collect());
+    let outcomes = Mutex::new(Vec::with_capacity(total));
+
+    let workers = cmp::min(cmp::max(concurrency, 1), total);
+    let result = thread::scope(|scope| {
+        for _ in 0..workers {
+            scope.spawn(|_| loop {
+                if cancel.is_stopping() {
+                    break;
+                }
+                let (i, device) = match queue.lock().expect("Staging queue").pop_front() {
+                    Some(next) => next,
+                    None => break,
+                };
+                let name = device.name().to_string();
+                info!("Staging {} ({}/{})", &name, i + 1, total);
+                let outcome = device.stage_files(destination, cancel);
+                match outcome {
+                    Ok(ref outcome) => info!("Staged {} files from {}", outcome.num_files, &name),
+                    Err(ref e) => error!("Couldn't stage {}: {:?}", &name, e),
+                }
+                outcomes.lock().expect("Staging outcomes").push((i, name, outcome));
+            });
+        }
+    });
+    // Whatever was staging on a worker that panicked is lost, but the others carry on with the
+    // rest of the queue.
+    if result.is_err() {
+        error!("A staging worker panicked");
+    }
+
+    let mut outcomes = outcomes.into_inner().expect("Staging outcomes");
+    for (i, device) in queue.into_inner().expect("Staging queue") {
+        let name = device.name().to_string();
+        outcomes.push((i, name, Ok(StagingOutcome::cancelled_after(0))));
+    }
+    outcomes.sort_by_key(|&(i, _, _)| i);
+    outcomes.into_iter().map(|(_, name, outcome)| (name, outcome)).collect()
+}
+
+fn locate_ptp_devices(ctx: &ctx::Ctx) -> Result<impl Iterator<Item = Box<dyn Device + '_>>, Error> {
     let gopro_serials: HashMap<_, _> = ctx
         .cfg
         .gopros()
         .iter()
-        .map(|x| (x.serial.clone(), x.name.clone()))
+        .map(|x| (x.serial.clone(), x.clone()))
         .collect();
+    let ptps = ctx.cfg.ptps();
 
-    Ok(ptp_device::locate_gopros(ctx)?
+    Ok(ptp_device::locate_ptp_devices(ctx)?
         .into_iter()
-        .filter_map(move |gopro| {
-            gopro_serials.get(&gopro.serial).map(|name| {
-                Device::Gopro(
+        .filter_map(move |device| {
+            if let Some(cfg) = gopro_serials.get(&device.serial) {
+                return Some(Box::new(MountableDevice::new(
+                    "ptp",
                     DeviceDescription {
-                        name: name.to_string(),
+                        name: cfg.name.to_string(),
+                        post_staging: cfg.post_staging.clone(),
                     },
-                    gopro,
-                )
-            })
+                    device.configure(cfg),
+                )) as Box<dyn Device + '_>);
+            }
+
+            ptps.iter()
+                .find(|cfg| cfg.matches(device.vendor_id, device.product_id, &device.serial))
+                .map(|cfg| {
+                    Box::new(MountableDevice::new(
+                        "ptp",
+                        DeviceDescription {
+                            name: cfg.name.to_string(),
+                            post_staging: cfg.post_staging.clone(),
+                        },
+                        device,
+                    )) as Box<dyn Device + '_>
+                })
         }))
 }
 
 fn locate_flysights(
     cfg: &config::Config,
-) -> Result<impl Iterator<Item = Device<'_>>, Error> {
-    Ok(cfg.flysights().iter().filter_map(|cfg| {
-        cfg.clone().get().map(|fs| {
-            Device::Flysight(
-                DeviceDescription {
-                    name: cfg.name().to_string(),
-                },
-                fs,
-            )
-        })
-    }))
+) -> Result<impl Iterator<Item = Box<dyn Device + '_>>, Error> {
+    let flysights = attached_filesystems("flysight", cfg.flysights(), |cfg| DeviceDescription {
+        name: cfg.name().to_string(),
+        post_staging: cfg.post_staging.clone(),
+    });
+    Ok(flysights.into_iter().map(|device| Box::new(device) as Box<dyn Device + '_>))
 }
 
 fn locate_mass_storages(
     cfg: &config::Config,
-) -> Result<impl Iterator<Item = Device<'_>>, Error> {
-    Ok(cfg.mass_storages().iter().filter_map(|cfg| {
-        cfg.clone().get().map(|ms| {
-            Device::MassStorage(
-                DeviceDescription {
-                    name: cfg.name.clone(),
-                },
-                ms,
-            )
-        })
-    }))
+) -> Result<impl Iterator<Item = Box<dyn Device + '_>>, Error> {
+    let mass_storages = attached_filesystems("mass_storage", cfg.mass_storages(), |cfg| DeviceDescription {
+        name: cfg.name.clone(),
+        post_staging: cfg.post_staging.clone(),
+    });
+    Ok(mass_storages.into_iter().map(|device| Box::new(device) as Box<dyn Device + '_>))
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::Config;
+    use crate::config::{Config, StagingConfig};
+    use crate::mountable::{DeviceIdentity, MOUNTABLE_DEVICE_FOLDER};
+    use crate::test_helpers::{self, AltimeterKind};
     use super::*;
 
+    use std::fs;
+
+    #[derive(Debug)]
+    struct FakeDevice {
+        name: &'static str,
+        files: Result<usize, &'static str>,
+    }
+
+    impl Device for FakeDevice {
+        fn name(&self) -> &str {
+            self.name
+        }
+
+        fn kind(&self) -> &'static str {
+            "fake"
+        }
+
+        fn stage_files(self: Box<Self>, _destination: &dyn StageableLocation, _cancel: &Cancel) -> Result<StagingOutcome, Error> {
+            match self.files {
+                Ok(num_files) => Ok(StagingOutcome { num_files, actions: vec![], cancelled: false }),
+                Err(e) => bail!("{}", e),
+            }
+        }
+    }
+
+    #[test]
+    fn test_stages_devices_concurrently() {
+        let staging = test_helpers::tempdir();
+        let devices: Vec<Box<dyn Device>> = vec![
+            Box::new(FakeDevice { name: "front", files: Ok(3) }),
+            Box::new(FakeDevice { name: "back", files: Err("Card went away") }),
+            Box::new(FakeDevice { name: "flysight", files: Ok(1) }),
+        ];
+
+        let outcomes = stage_concurrently(devices, &staging, 2, &Cancel::new());
+        let outcomes: Vec<_> = outcomes.iter()
+            .map(|(name, outcome)| (&name[..], outcome.as_ref().map(|o| o.num_files).map_err(|e| e.to_string())))
+            .collect();
+        assert_eq!(outcomes, vec![
+            ("front", Ok(3)),
+            ("back", Err("Card went away".to_string())),
+            ("flysight", Ok(1)),
+        ]);
+    }
+
+    #[test]
+    fn test_stopping_leaves_devices_unstaged() {
+        let staging = test_helpers::tempdir();
+        let devices: Vec<Box<dyn Device>> = vec![
+            Box::new(FakeDevice { name: "front", files: Ok(3) }),
+            Box::new(FakeDevice { name: "back", files: Ok(1) }),
+        ];
+        let cancel = Cancel::new();
+        cancel.stop();
+
+        let outcomes = stage_concurrently(devices, &staging, 2, &cancel);
+        let outcomes: Vec<_> = outcomes.iter()
+            .map(|(name, outcome)| (&name[..], outcome.as_ref().map(|o| (o.num_files, o.cancelled)).ok()))
+            .collect();
+        assert_eq!(outcomes, vec![
+            ("front", Some((0, true))),
+            ("back", Some((0, true))),
+        ]);
+    }
+
+    #[test]
+    fn test_stages_swapped_cards_as_the_device_they_belong_to() {
+        let card = test_helpers::test_data("mass_storage");
+        let folder = card.path().join(MOUNTABLE_DEVICE_FOLDER);
+        fs::create_dir(&folder).unwrap();
+        DeviceIdentity::new("back".into()).write(&folder).unwrap();
+
+        let describe = |name: &str| DeviceDescription { name: name.into(), post_staging: vec![] };
+        let front = MassStorageConfig::test_config("front", card.path());
+        let back = MassStorageConfig::test_config("back", "test-data/nonexistant_ms");
+
+        let device = Box::new(FilesystemDevice::new("mass_storage", describe("front"), front.clone(), vec![(describe("back"), back)]));
+        let planned = device.plan(false).unwrap();
+        assert_eq!(planned.len(), 2);
+        assert!(planned.iter().all(|file| file.descriptor.device_name == "back"));
+
+        // A card belonging to something we don't know about is left alone
+        let device = Box::new(FilesystemDevice::new("mass_storage", describe("front"), front, vec![]));
+        assert!(device.plan(false).is_err());
+    }
+
+    #[test]
+    fn test_mounts_shared_filesystems_once() {
+        let card = test_helpers::test_data("mass_storage");
+        let folder = card.path().join(MOUNTABLE_DEVICE_FOLDER);
+        fs::create_dir(&folder).unwrap();
+        DeviceIdentity::new("back".into()).write(&folder).unwrap();
+
+        let configs = vec![
+            MassStorageConfig::test_config("front", card.path()),
+            MassStorageConfig::test_config("back", card.path()),
+            MassStorageConfig::test_config("side", "test-data/nonexistant_ms"),
+        ];
+        let devices = attached_filesystems("mass_storage", &configs, |cfg| DeviceDescription {
+            name: cfg.name.clone(),
+            post_staging: vec![],
+        });
+        assert_eq!(devices.len(), 1);
+        let others: Vec<_> = devices[0].others.iter().map(|(desc, _)| &desc.name[..]).collect();
+        assert_eq!(others, vec!["back", "side"]);
+
+        let device = Box::new(devices.into_iter().next().unwrap());
+        let planned = device.plan(false).unwrap();
+        assert_eq!(planned.len(), 2);
+        assert!(planned.iter().all(|file| file.descriptor.device_name == "back"));
+    }
+
     #[test]
     fn test_locates_flysights() {
         let cfg = Config::from_file("test-data/archiver.toml").unwrap();
         let flysights: Vec<_> = locate_flysights(&cfg).unwrap().collect();
         assert_eq!(flysights.len(), 1);
-        if let Device::Flysight(ref _desc, ref flysight) = flysights[0] {
-            assert_eq!(&flysight.name()[..], "data");
-        } else {
-            panic!("Unsure what we ended up with: {:?}", flysights);
-        }
+        assert_eq!(flysights[0].kind(), "flysight");
+        assert_eq!(flysights[0].name(), "data");
     }
 
     #[test]
@@ -132,10 +682,91 @@ mod tests {
         let cfg = Config::from_file("test-data/archiver.toml").unwrap();
         let flysights: Vec<_> = locate_flysights(&cfg).unwrap().collect();
         assert_eq!(flysights.len(), 1);
-        if let Device::Flysight(ref _desc, ref flysight) = flysights[0] {
-            assert_eq!(&flysight.name()[..], "data");
-        } else {
-            panic!("Unsure what we ended up with: {:?}", flysights);
-        }
+        assert_eq!(flysights[0].kind(), "flysight");
+        assert_eq!(flysights[0].name(), "data");
+    }
+
+    #[test]
+    fn test_registers_device_kinds() {
+        let registry = DeviceRegistry::default();
+        let names: Vec<_> = registry.kinds().map(|k| k.name()).collect();
+        assert_eq!(names, vec!["ptp", "flysight", "mass_storage"]);
+        assert!(registry.get("altimeter").is_none());
+
+        let registry = registry.register(AltimeterKind);
+        assert!(registry.get("altimeter").is_some());
+        // Registering a kind twice replaces it
+        let registry = registry.register(AltimeterKind);
+        assert_eq!(registry.kinds().count(), 4);
+    }
+
+    fn registered(kind: &dyn DeviceKind, metadata: serde_json::Value) -> Config {
+        let device = RegisteredDevice {
+            name: "front",
+            identifier: "FRONT",
+            metadata: &metadata,
+        };
+        let config = Config::build()
+            .staging(StagingConfig {
+                location: MountableDeviceLocation::from_mountpoint("/test/staging/dir".into()),
+                mounter: None,
+            })
+            .dropbox("TOKEN".into());
+        let registry = DeviceRegistry::default().register(AltimeterKind);
+        kind.add_registered(config, &device).unwrap().finish_with_registry(&registry).unwrap()
+    }
+
+    #[test]
+    fn test_mass_storage_filters_from_metadata() {
+        let cfg = registered(&MassStorageKind, json!({
+            "extensions": ["mov"],
+            "exclude_dirs": ["MISC"],
+            "older_than_minutes": 5,
+        }));
+        let cfg = &cfg.mass_storages()[0];
+        assert_eq!(cfg.extensions, vec!["mov".to_string()]);
+        assert_eq!(cfg.filters, MassStorageFilters {
+            exclude_dirs: vec!["MISC".into()],
+            older_than_minutes: Some(5),
+            ..Default::default()
+        });
+
+        let cfg = registered(&MassStorageKind, json!({}));
+        let cfg = &cfg.mass_storages()[0];
+        assert_eq!(cfg.extensions, vec!["mp4".to_string()]);
+        assert_eq!(cfg.filters, Default::default());
+    }
+
+    #[test]
+    fn test_other_kinds_add_registered_devices_to_their_own_section() {
+        let cfg = registered(&AltimeterKind, json!({"units": "feet"}));
+        let entries: Vec<toml::Value> = cfg.devices_of("altimeter").unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0]["name"].as_str(), Some("front"));
+        assert_eq!(entries[0]["identifier"].as_str(), Some("FRONT"));
+        assert_eq!(entries[0]["units"].as_str(), Some("feet"));
+    }
+
+    #[test]
+    fn test_rejects_unregistered_device_kinds() {
+        let body = r#"
+[archiver]
+[staging]
+mountpoint="/test/staging/dir"
+
+[dropbox]
+token = "TOKEN"
+
+[[altimeter]]
+name = "wrist"
+"#;
+
+        // Loading the config is enough to find sections that nothing is registered for
+        assert_eq!(body.parse::<Config>().unwrap_err(), ConfigError::UnknownDeviceKind("altimeter".into()));
+
+        let registry = DeviceRegistry::default().register(AltimeterKind);
+        let cfg = Config::from_str_with_registry(body, &registry).unwrap();
+        assert!(DeviceRegistry::default().check_config(&cfg).is_err());
+        registry.check_config(&cfg).unwrap();
     }
 }
@@ -0,0 +1,194 @@
+use std::fs::{self, File};
+use std::io::{Cursor, Read, Write};
+use std::path::{Path, PathBuf};
+
+use digest::Digest;
+use dropbox_content_hasher::DropboxContentHasher;
+use failure::Error;
+use hex;
+
+use crate::staging::{Disposal, RemotePathDescriptor, UploadableFile};
+
+const CONFIG_FILE: &str = "config.txt";
+
+/// Find the flysight's `config.txt`. FlySight 2 spells it `CONFIG.TXT`, and whether that matters
+/// depends on how the device was mounted.
+fn find_config(root: &Path) -> Result<Option<PathBuf>, Error> {
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_file() &&
+            entry.file_name().to_string_lossy().eq_ignore_ascii_case(CONFIG_FILE) {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+/// A backup of a flysight's `config.txt`.
+///
+/// Backups are named for the hash of their content, so a config that hasn't changed since we last
+/// saw it is already uploaded, and the copy on the device is never removed.
+#[derive(Debug)]
+pub struct FlysightConfigFile {
+    hash: String,
+    data: Cursor<Vec<u8>>,
+}
+
+impl FlysightConfigFile {
+    /// Read the config from the flysight mounted at `root`, if it has one.
+    ///
+    /// A flysight without a config uses its defaults, so there's nothing to back up.
+    pub fn from_root(root: &Path) -> Result<Option<FlysightConfigFile>, Error> {
+        let path = match find_config(root)? {
+            Some(path) => path,
+            None => return Ok(None),
+        };
+
+        let mut data = vec![];
+        File::open(&path)?.read_to_end(&mut data)?;
+        if data.is_empty() {
+            return Ok(None);
+        }
+
+        let hash = hex::encode(DropboxContentHasher::digest(&data));
+        Ok(Some(FlysightConfigFile {
+            hash,
+            data: Cursor::new(data),
+        }))
+    }
+}
+
+impl UploadableFile for FlysightConfigFile {
+    type Reader = Cursor<Vec<u8>>;
+
+    fn remote_path(&self) -> Result<RemotePathDescriptor, Error> {
+        Ok(RemotePathDescriptor::SpecifiedPath {
+            path: format!("config/{}.txt", &self.hash[..16]).into(),
+        })
+    }
+
+    fn delete(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn disposal(&self) -> Result<Disposal, Error> {
+        Ok(Disposal::Keep)
+    }
+
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.data.get_ref().len() as u64)
+    }
+
+    fn reader(&mut self) -> &mut Cursor<Vec<u8>> {
+        &mut self.data
+    }
+}
+
+/// Write `template` over the config of the flysight mounted at `root`.
+///
+/// Returns a description of what changed, for the report.
+pub fn push_template(root: &Path, template: &str) -> Result<String, Error> {
+    let path = find_config(root)?.unwrap_or_else(|| root.join(CONFIG_FILE));
+    let current = if path.exists() {
+        fs::read_to_string(&path)?
+    } else {
+        String::new()
+    };
+
+    let changes = diff(&current, template);
+    if changes.is_empty() {
+        return Ok(format!("{} already matches the template", CONFIG_FILE));
+    }
+
+    // The flysight is happiest with DOS line endings, so that's what we give it.
+    let mut file = File::create(&path)?;
+    for line in template.lines() {
+        write!(file, "{}\r\n", line.trim_end_matches('\r'))?;
+    }
+
+    Ok(format!("Updated {}\n{}", CONFIG_FILE, changes.join("\n")))
+}
+
+/// A line by line diff of two configs, showing only the lines that were removed or added.
+pub fn diff(old: &str, new: &str) -> Vec<String> {
+    let old: Vec<_> = old.lines().map(|l| l.trim_end_matches('\r')).collect();
+    let new: Vec<_> = new.lines().map(|l| l.trim_end_matches('\r')).collect();
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0; new.len() + 1]; old.len() + 1];
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = vec![];
+    let (mut i, mut j) = (0, 0);
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            i += 1;
+            j += 1;
+        } else if j == new.len() || (i < old.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+            out.push(format!("-{}", old[i]));
+            i += 1;
+        } else {
+            out.push(format!("+{}", new[j]));
+            j += 1;
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_diffs_configs() {
+        let old = "Model: 6\r\nRate: 200\r\nVolume: 6\r\n";
+        let new = "Model: 7\nRate: 200\nVolume: 6\nAlarm_Elev: 1000\n";
+        assert_eq!(diff(old, new), vec![
+            "-Model: 6",
+            "+Model: 7",
+            "+Alarm_Elev: 1000",
+        ]);
+        assert!(diff(old, "Model: 6\nRate: 200\nVolume: 6").is_empty());
+    }
+
+    #[test]
+    fn test_names_backups_by_content() {
+        let config = FlysightConfigFile::from_root(Path::new("test-data/flysight"))
+            .unwrap()
+            .expect("Didn't find a config");
+        let again = FlysightConfigFile::from_root(Path::new("test-data/flysight"))
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.remote_path().unwrap(), again.remote_path().unwrap());
+
+        // An empty config isn't worth keeping
+        assert!(FlysightConfigFile::from_root(Path::new("test-data/flysight2")).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_pushes_templates() {
+        let source = test_helpers::test_data("flysight");
+        let template = fs::read_to_string("test-data/flysight/config.txt")
+            .unwrap()
+            .replace("Alarm_Elev: 1000", "Alarm_Elev: 1200");
+
+        let result = push_template(source.path(), &template).unwrap();
+        assert_eq!(result, "\
+Updated config.txt
+-Alarm_Elev: 1000 ; Alarm elevation (m above ground level)
++Alarm_Elev: 1200 ; Alarm elevation (m above ground level)");
+        assert_eq!(fs::read_to_string(source.path().join("config.txt")).unwrap(), template);
+
+        let result = push_template(source.path(), &template).unwrap();
+        assert_eq!(result, "config.txt already matches the template");
+    }
+}
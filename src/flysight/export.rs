@@ -0,0 +1,193 @@
+use std::fmt::Write;
+use std::io::Cursor;
+
+use chrono;
+use chrono::prelude::*;
+use failure::Error;
+
+use crate::config::TrackFormat;
+use crate::staging::{DateTimeUploadable, Disposal};
+
+use super::track::{Track, TrackPoint};
+
+/// A track converted into another format, staged alongside the CSV it was made from.
+///
+/// The CSV remains the source of truth, so deleting an export is a no-op, and it takes the capture
+/// time of its original so that the two end up side by side.
+#[derive(Debug)]
+pub struct ExportedTrack {
+    capturetime: DateTime<Local>,
+    format: TrackFormat,
+    data: Cursor<Vec<u8>>,
+}
+
+impl ExportedTrack {
+    pub fn new(track: &Track, name: &str, capturetime: DateTime<Local>, format: TrackFormat) -> ExportedTrack {
+        let data = match format {
+            TrackFormat::Gpx => to_gpx(track, name),
+            TrackFormat::Kml => to_kml(track, name),
+        };
+        ExportedTrack {
+            capturetime,
+            format,
+            data: Cursor::new(data.into_bytes()),
+        }
+    }
+}
+
+impl DateTimeUploadable for ExportedTrack {
+    type Reader = Cursor<Vec<u8>>;
+
+    fn extension(&self) -> &str {
+        self.format.extension()
+    }
+
+    fn capture_datetime(&self) -> Result<DateTime<Local>, chrono::ParseError> {
+        Ok(self.capturetime)
+    }
+
+    fn reader(&mut self) -> &mut Cursor<Vec<u8>> {
+        &mut self.data
+    }
+
+    fn delete(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn disposal(&self) -> Result<Disposal, Error> {
+        Ok(Disposal::Keep)
+    }
+
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.data.get_ref().len() as u64)
+    }
+}
+
+fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Only export points with a 3D fix, the rest are just noise on the map.
+fn exportable(track: &Track) -> impl Iterator<Item = &TrackPoint> {
+    track.points.iter().filter(|p| p.gps_fix >= 3)
+}
+
+pub fn to_gpx(track: &Track, name: &str) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<gpx version=\"1.1\" creator=\"archiver\" xmlns=\"http://www.topografix.com/GPX/1/1\">\n");
+    out.push_str("  <trk>\n");
+    let _ = writeln!(out, "    <name>{}</name>", escape(name));
+    out.push_str("    <trkseg>\n");
+    for point in exportable(track) {
+        let _ = writeln!(
+            out,
+            "      <trkpt lat=\"{:.7}\" lon=\"{:.7}\"><ele>{:.3}</ele><time>{}</time></trkpt>",
+            point.latitude,
+            point.longitude,
+            point.altitude,
+            point.time.with_timezone(&Utc).format("%Y-%m-%dT%H:%M:%S%.3fZ"),
+        );
+    }
+    out.push_str("    </trkseg>\n");
+    out.push_str("  </trk>\n");
+    out.push_str("</gpx>\n");
+    out
+}
+
+pub fn to_kml(track: &Track, name: &str) -> String {
+    let mut out = String::new();
+    out.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    out.push_str("<kml xmlns=\"http://www.opengis.net/kml/2.2\">\n");
+    out.push_str("  <Placemark>\n");
+    let _ = writeln!(out, "    <name>{}</name>", escape(name));
+    out.push_str("    <LineString>\n");
+    out.push_str("      <altitudeMode>absolute</altitudeMode>\n");
+    out.push_str("      <coordinates>\n");
+    for point in exportable(track) {
+        let _ = writeln!(
+            out,
+            "        {:.7},{:.7},{:.3}",
+            point.longitude,
+            point.latitude,
+            point.altitude,
+        );
+    }
+    out.push_str("      </coordinates>\n");
+    out.push_str("    </LineString>\n");
+    out.push_str("  </Placemark>\n");
+    out.push_str("</kml>\n");
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn point(second: u32, gps_fix: u8) -> TrackPoint {
+        TrackPoint {
+            time: Utc.ymd(2018, 3, 10).and_hms(18, 40, second).with_timezone(&Local),
+            latitude: 37.726932,
+            longitude: -121.3289665,
+            altitude: 1564.964,
+            vel_north: 40.02,
+            vel_east: -33.2,
+            vel_down: -4.68,
+            horizontal_accuracy: 5.0,
+            vertical_accuracy: 5.0,
+            gps_fix,
+            satellites: 9,
+        }
+    }
+
+    fn track() -> Track {
+        Track {
+            points: vec![point(0, 0), point(1, 3)],
+        }
+    }
+
+    #[test]
+    fn test_exports_gpx() {
+        assert_eq!(to_gpx(&track(), "data <18-08-24>"), "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<gpx version=\"1.1\" creator=\"archiver\" xmlns=\"http://www.topografix.com/GPX/1/1\">
+  <trk>
+    <name>data &lt;18-08-24&gt;</name>
+    <trkseg>
+      <trkpt lat=\"37.7269320\" lon=\"-121.3289665\"><ele>1564.964</ele><time>2018-03-10T18:40:01.000Z</time></trkpt>
+    </trkseg>
+  </trk>
+</gpx>
+");
+    }
+
+    #[test]
+    fn test_exports_kml() {
+        assert_eq!(to_kml(&track(), "data"), "\
+<?xml version=\"1.0\" encoding=\"UTF-8\"?>
+<kml xmlns=\"http://www.opengis.net/kml/2.2\">
+  <Placemark>
+    <name>data</name>
+    <LineString>
+      <altitudeMode>absolute</altitudeMode>
+      <coordinates>
+        -121.3289665,37.7269320,1564.964
+      </coordinates>
+    </LineString>
+  </Placemark>
+</kml>
+");
+    }
+
+    #[test]
+    fn test_exports_share_the_original_capture_time() {
+        let capturetime = Local.ymd(2018, 8, 24).and_hms(9, 55, 30);
+        let export = ExportedTrack::new(&track(), "data", capturetime, TrackFormat::Kml);
+        assert_eq!(export.capture_datetime().unwrap(), capturetime);
+        assert_eq!(export.extension(), "kml");
+        assert_eq!(export.size().unwrap(), to_kml(&track(), "data").len() as u64);
+    }
+}
@@ -0,0 +1,174 @@
+use std::fs::{self, File};
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+
+use failure::Error;
+use regex;
+
+use super::FlysightFile;
+use crate::retention::Retention;
+
+lazy_static! {
+    static ref DATE: regex::bytes::Regex =
+        regex::bytes::Regex::new(r"(?P<year>\d{2})-(?P<month>\d{2})-(?P<day>\d{2})")
+            .expect("Failed to compile regex");
+    static ref ENTRY: regex::bytes::Regex = regex::bytes::Regex::new(
+        r"(?P<hour>\d{2})-(?P<min>\d{2})-(?P<second>\d{2}).[cC][sS][vV]"
+    )
+    .expect("Failed to compile regex");
+    static ref SESSION: regex::bytes::Regex =
+        regex::bytes::Regex::new(r"^(?P<hour>\d{2})-(?P<min>\d{2})-(?P<second>\d{2})$")
+            .expect("Failed to compile regex");
+}
+
+/// The directory FlySight 2 keeps its tracks under.
+const FLYSIGHT2_TRACKS: &str = "TRACKS";
+
+/// The hardware generations of FlySight, which each lay their files out differently.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+pub enum FlysightGeneration {
+    /// The original FlySight, which writes a single `YY-MM-DD/HH-MM-SS.CSV` per session.
+    Classic,
+    /// FlySight 2, which writes a directory per session under `TRACKS/YY-MM-DD/HH-MM-SS/`,
+    /// containing `TRACK.CSV`, `SENSOR.CSV` and `RAW.UBX`.
+    Flysight2,
+}
+
+impl FlysightGeneration {
+    /// Work out which generation of FlySight is mounted at `root`.
+    pub fn detect(root: &Path) -> Result<FlysightGeneration, Error> {
+        match find_tracks_dir(root)? {
+            Some(_) => Ok(FlysightGeneration::Flysight2),
+            None => Ok(FlysightGeneration::Classic),
+        }
+    }
+
+    /// List the files from every session on the FlySight mounted at `retention`'s root, which
+    /// will be dealt with according to `retention` once they're staged.
+    pub fn files(&self, retention: &Retention) -> Result<Vec<FlysightFile>, Error> {
+        match self {
+            FlysightGeneration::Classic => classic_files(retention),
+            FlysightGeneration::Flysight2 => match find_tracks_dir(retention.root())? {
+                Some(tracks) => flysight2_files(&tracks, retention),
+                None => Ok(vec![]),
+            },
+        }
+    }
+}
+
+/// FAT is case insensitive, but whether we are depends on how the device was mounted.
+fn find_tracks_dir(root: &Path) -> Result<Option<PathBuf>, Error> {
+    for entry in fs::read_dir(root)? {
+        let entry = entry?;
+        if entry.file_type()?.is_dir() &&
+            entry.file_name().to_string_lossy().eq_ignore_ascii_case(FLYSIGHT2_TRACKS) {
+            return Ok(Some(entry.path()));
+        }
+    }
+    Ok(None)
+}
+
+fn classic_files(retention: &Retention) -> Result<Vec<FlysightFile>, Error> {
+    let mut out = vec![];
+    for entry in fs::read_dir(retention.root())? {
+        let entry = entry?;
+        // Enter into directories that are named appropriately
+        if entry.file_type()?.is_dir() && DATE.is_match(&entry.file_name().as_bytes()) {
+            for file in fs::read_dir(entry.path())? {
+                let file = file?;
+                if file.file_type()?.is_file() && ENTRY.is_match(&file.file_name().as_bytes()) {
+                    // Trim the .csv from the end
+                    let mut filename = file.file_name().into_string().unwrap();
+                    let len = filename.len();
+                    filename.truncate(len - 4);
+                    out.push(FlysightFile {
+                        // TODO(richo) There's actually the very real chance that people will
+                        // end up with non utf8 garbage.
+                        capturedate: entry.file_name().into_string().unwrap(),
+                        capturetime: filename,
+                        extension: "csv".to_string(),
+                        is_track: true,
+                        file: File::open(file.path())?,
+                        source_path: file.path().to_path_buf(),
+                        retention: retention.clone(),
+                    });
+                }
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// Every file in a session is staged, named after the session with the original filename as its
+/// extension, so that `TRACK.CSV` is staged as `HH-MM-SS.track.csv` alongside its sensor data.
+fn flysight2_files(tracks: &Path, retention: &Retention) -> Result<Vec<FlysightFile>, Error> {
+    let mut out = vec![];
+    for date in fs::read_dir(tracks)? {
+        let date = date?;
+        if !(date.file_type()?.is_dir() && DATE.is_match(&date.file_name().as_bytes())) {
+            continue;
+        }
+        for session in fs::read_dir(date.path())? {
+            let session = session?;
+            if !(session.file_type()?.is_dir() && SESSION.is_match(&session.file_name().as_bytes())) {
+                continue;
+            }
+            for file in fs::read_dir(session.path())? {
+                let file = file?;
+                if !file.file_type()?.is_file() {
+                    continue;
+                }
+                let filename = file.file_name().to_string_lossy().to_lowercase();
+                out.push(FlysightFile {
+                    capturedate: date.file_name().into_string().unwrap(),
+                    capturetime: session.file_name().into_string().unwrap(),
+                    is_track: filename == "track.csv",
+                    extension: filename,
+                    file: File::open(file.path())?,
+                    source_path: file.path().to_path_buf(),
+                    retention: retention.clone(),
+                });
+            }
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::AfterStaging;
+
+    #[test]
+    fn test_detects_generations() {
+        assert_eq!(
+            FlysightGeneration::detect(Path::new("test-data/flysight")).unwrap(),
+            FlysightGeneration::Classic
+        );
+        assert_eq!(
+            FlysightGeneration::detect(Path::new("test-data/flysight2")).unwrap(),
+            FlysightGeneration::Flysight2
+        );
+    }
+
+    #[test]
+    fn test_lists_flysight2_sessions() {
+        let root = Path::new("test-data/flysight2");
+        let retention = Retention::new(&AfterStaging::Delete, root, &root.join("archiver"));
+        let mut files = FlysightGeneration::Flysight2.files(&retention).unwrap();
+        files.sort_unstable();
+
+        let names: Vec<_> = files
+            .iter()
+            .map(|f| format!("{}/{}.{}", f.capturedate, f.capturetime, f.extension))
+            .collect();
+        assert_eq!(names, vec![
+            "18-03-10/17-55-30.track.csv",
+            "18-03-10/18-39-58.raw.ubx",
+            "18-03-10/18-39-58.sensor.csv",
+            "18-03-10/18-39-58.track.csv",
+        ]);
+        let tracks: Vec<_> = files.iter().map(|f| f.is_track).collect();
+        assert_eq!(tracks, vec![true, false, false, true]);
+    }
+}
@@ -0,0 +1,412 @@
+use std::cmp::Ordering;
+use std::fs::File;
+use std::path::PathBuf;
+
+use crate::cancel::Cancel;
+use crate::config::{DeviceAction, FlysightConfig, MountableDeviceLocation, Mounter};
+use crate::retention::Retention;
+use crate::mountable::{MountedFilesystem, MountableFilesystem, MountableKind};
+use crate::staging::{plan_file, stage_file_unless_aborted, Disposal, PlannedFile, Staging, StageableLocation, DateTimeUploadable};
+
+use chrono;
+use chrono::prelude::*;
+use failure::Error;
+
+mod config_file;
+mod export;
+mod generation;
+mod track;
+
+pub use self::config_file::FlysightConfigFile;
+pub use self::export::ExportedTrack;
+pub use self::generation::FlysightGeneration;
+pub use self::track::{JumpSummary, Track, TrackPoint};
+
+#[derive(Debug)]
+pub struct MountedFlysight {
+    flysight: FlysightConfig,
+    mount: MountedFilesystem,
+}
+
+/// A file from a single session on a FlySight.
+#[derive(Debug)]
+pub struct FlysightFile {
+    capturedate: String,
+    capturetime: String,
+    extension: String,
+    /// Whether this is the session's GPS track, as opposed to its other sensor data.
+    is_track: bool,
+    file: File,
+    source_path: PathBuf,
+    retention: Retention,
+}
+
+impl Ord for FlysightFile {
+    fn cmp(&self, other: &FlysightFile) -> Ordering {
+        use std::cmp::Ordering::*;
+        match self.capturedate.cmp(&other.capturedate) {
+            Less => Less,
+            Greater => Greater,
+            Equal => match self.capturetime.cmp(&other.capturetime) {
+                Equal => self.extension.cmp(&other.extension),
+                ordering => ordering,
+            },
+        }
+    }
+}
+
+impl PartialOrd for FlysightFile {
+    fn partial_cmp(&self, other: &FlysightFile) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for FlysightFile {
+    fn eq(&self, other: &FlysightFile) -> bool {
+        self.capturedate == other.capturedate &&
+            self.capturetime == other.capturetime &&
+            self.extension == other.extension
+    }
+}
+
+impl Eq for FlysightFile {}
+
+impl FlysightFile {
+    /// Parse the track in this file.
+    ///
+    /// This reads from its own handle, so that we don't disturb the one we stage from.
+    pub fn track(&self) -> Result<Track, Error> {
+        Track::from_reader(File::open(&self.source_path)?)
+    }
+}
+
+impl DateTimeUploadable for FlysightFile {
+    type Reader = File;
+
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    fn capture_datetime(&self) -> Result<DateTime<Local>, chrono::ParseError> {
+        // Adding time is hard, we'll just allocate our faces off
+        let mut datetime = self.capturedate.clone();
+        datetime.push_str("/");
+        datetime.push_str(&self.capturetime);
+        Local.datetime_from_str(&datetime, "%y-%m-%d/%H-%M-%S")
+    }
+
+    fn reader(&mut self) -> &mut File {
+        &mut self.file
+    }
+
+    fn delete(&mut self) -> Result<(), Error> {
+        self.retention.dispose(&self.source_path)
+    }
+
+    fn disposal(&self) -> Result<Disposal, Error> {
+        self.retention.disposal(&self.source_path)
+    }
+
+    fn size(&self) -> Result<u64, Error> {
+        Ok(self.file.metadata()?.len())
+    }
+
+    fn jump_summary(&self) -> Option<JumpSummary> {
+        if !self.is_track {
+            return None;
+        }
+        match self.track() {
+            Ok(track) => track.jump_summary(),
+            Err(e) => {
+                warn!("Couldn't read a track from {:?}: {:?}", &self.source_path, e);
+                None
+            },
+        }
+    }
+}
+
+impl MountableFilesystem for FlysightConfig {
+    type Target = MountedFlysight;
+
+    fn location(&self) -> &MountableDeviceLocation {
+        &self.location
+    }
+
+    fn mounter(&self) -> Option<Mounter> {
+        self.mounter
+    }
+
+    fn identity(&self) -> Option<&str> {
+        Some(&self.name)
+    }
+}
+
+impl MountableKind for MountedFlysight {
+    type This = FlysightConfig;
+
+    fn from_mounted_parts(this: Self::This, mount: MountedFilesystem) -> Self {
+        MountedFlysight {
+            flysight: this,
+            mount,
+        }
+    }
+}
+
+impl MountedFlysight {
+    fn retention(&self) -> Retention {
+        Retention::new(&self.flysight.after_staging, self.mount.path(), self.mount.archiver_folder())
+    }
+}
+
+impl Staging for MountedFlysight {
+    type FileType = FlysightFile;
+
+    fn files(&self) -> Result<Vec<FlysightFile>, Error> {
+        let mount_path = self.mount.path();
+        let generation = FlysightGeneration::detect(mount_path)?;
+        info!("Found a {:?} flysight at {:?}", generation, mount_path);
+
+        let retention = self.retention();
+        let kept = retention.kept()?;
+        let mut out = generation.files(&retention)?;
+        out.retain(|file| match file.file.metadata() {
+            Ok(metadata) => !kept.contains(&file.source_path, &metadata),
+            Err(_) => true,
+        });
+        out.sort_unstable();
+        Ok(out)
+    }
+
+    /// Stage every track on this flysight, along with any exports configured for it and a backup
+    /// of its config.
+    ///
+    /// Exports are staged before their original, since staging the original removes it from the
+    /// device. Failing to convert a track isn't fatal, we'll still have the CSV.
+    fn stage_files<T>(&self, name: &str, destination: &T, cancel: &Cancel) -> Result<usize, Error>
+    where
+        T: StageableLocation,
+    {
+        let mut i = 0;
+
+        if let Some(config) = FlysightConfigFile::from_root(self.mount.path())? {
+            if !stage_file_unless_aborted(config, destination, name, cancel)? {
+                return Ok(i);
+            }
+            i += 1;
+        }
+
+        for file in self.files()? {
+            if cancel.is_stopping() {
+                info!("Stopping with {} files staged from {}", i, name);
+                return Ok(i);
+            }
+            if file.is_track && !self.flysight.exports.is_empty() {
+                match (file.track(), file.capture_datetime()) {
+                    (Ok(track), Ok(capturetime)) => {
+                        for format in &self.flysight.exports {
+                            let export = ExportedTrack::new(&track, name, capturetime, *format);
+                            if !stage_file_unless_aborted(export, destination, name, cancel)? {
+                                return Ok(i);
+                            }
+                            i += 1;
+                        }
+                    },
+                    (Err(e), _) => warn!("Couldn't read a track from {:?}, not exporting it: {:?}", &file.source_path, e),
+                    (_, Err(e)) => warn!("Couldn't parse a capture time for {:?}, not exporting it: {:?}", &file.source_path, e),
+                }
+            }
+
+            if !stage_file_unless_aborted(file, destination, name, cancel)? {
+                return Ok(i);
+            }
+            i += 1;
+        }
+        self.tidy_up()?;
+
+        Ok(i)
+    }
+
+    fn plan_files(&self, name: &str, hash: bool) -> Result<Vec<PlannedFile>, Error> {
+        let mut out = vec![];
+
+        if let Some(config) = FlysightConfigFile::from_root(self.mount.path())? {
+            out.push(plan_file(config, name, hash)?);
+        }
+
+        for file in self.files()? {
+            if file.is_track && !self.flysight.exports.is_empty() {
+                if let (Ok(track), Ok(capturetime)) = (file.track(), file.capture_datetime()) {
+                    for format in &self.flysight.exports {
+                        out.push(plan_file(ExportedTrack::new(&track, name, capturetime, *format), name, hash)?);
+                    }
+                }
+            }
+
+            out.push(plan_file(file, name, hash)?);
+        }
+
+        Ok(out)
+    }
+
+    fn tidy_up(&self) -> Result<(), Error> {
+        let removed = self.retention().tidy()?;
+        if removed > 0 {
+            info!("Removed {} old files from {}", removed, &self.flysight.name);
+        }
+        Ok(())
+    }
+
+    fn perform_action(&mut self, action: DeviceAction) -> Result<String, Error> {
+        match action {
+            DeviceAction::Eject => {
+                self.mount.eject()?;
+                Ok("Ejected".to_string())
+            },
+            DeviceAction::PushConfig => match &self.flysight.config_template {
+                Some(template) => config_file::push_template(self.mount.path(), template),
+                None => bail!("No config template is set for {}", &self.flysight.name),
+            },
+            action => bail!("{} is not supported by this device", action),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::{AfterStaging, TrackFormat};
+    use std::fs;
+    use crate::staging::UploadableFile;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_flysight_loads_files() {
+        let flysight = FlysightConfig::test_config("data", "test-data/flysight");
+        let mounted = flysight.mount_for_test();
+
+        let files = mounted.files().expect("Couldn't load test files");
+        assert_eq!(files.len(), 3);
+    }
+
+    #[test]
+    fn test_flysight_parses_dates() {
+        let flysight = FlysightConfig::test_config("data", "test-data/flysight");
+        let mounted = flysight.mount_for_test();
+
+        let files = mounted.files().expect("Couldn't load test files");
+        assert_eq!(
+            files[0].capture_datetime().unwrap(),
+            Local.ymd(2018, 8, 24).and_hms(9, 55, 30)
+        );
+        assert_eq!(
+            files[1].capture_datetime().unwrap(),
+            Local.ymd(2018, 8, 24).and_hms(10, 39, 58)
+        );
+        assert_eq!(
+            files[2].capture_datetime().unwrap(),
+            Local.ymd(2018, 8, 24).and_hms(11, 0, 28)
+        );
+    }
+
+    #[test]
+    fn test_flysight_summarises_jumps() {
+        let flysight = FlysightConfig::test_config("data", "test-data/flysight");
+        let mounted = flysight.mount_for_test();
+
+        let files = mounted.files().expect("Couldn't load test files");
+        assert_eq!(DateTimeUploadable::jump_summary(&files[0]), None);
+
+        let summary = DateTimeUploadable::jump_summary(&files[1]).expect("Didn't find a jump");
+        assert_eq!(summary.exit_time, Utc.ymd(2018, 3, 10).and_hms_milli(18, 40, 14, 200));
+
+        let desc = files[2].descriptor("data").unwrap();
+        assert!(desc.jump_summary.is_some());
+    }
+
+    #[test]
+    fn test_staging_works() {
+        let dest = test_helpers::tempdir();
+        let source = test_helpers::test_data("flysight");
+
+        let flysight = FlysightConfig::test_config("data", source.path());
+        let mounted = flysight.mount_for_test();
+
+
+        mounted.stage_files("data", &dest, &Cancel::new()).unwrap();
+        // TODO(richo) test harder
+        let iter = fs::read_dir(&dest.path()).unwrap();
+        let files: Vec<_> = iter.collect();
+
+        // Three tracks and a backup of the config, each with a manifest
+        assert_eq!(files.len(), 8);
+        // The config stays on the device
+        assert!(source.path().join("config.txt").exists());
+    }
+
+    #[test]
+    fn test_keeps_recent_tracks() {
+        let dest = test_helpers::tempdir();
+        let source = test_helpers::test_data("flysight");
+
+        let flysight = FlysightConfig {
+            after_staging: AfterStaging::KeepRecent { days: 7 },
+            ..FlysightConfig::test_config("data", source.path())
+        };
+        let mounted = flysight.mount_for_test();
+
+        // We've only just copied them, so they all stay put, but they won't be staged again
+        assert_eq!(mounted.stage_files("data", &dest, &Cancel::new()).unwrap(), 4);
+        assert!(source.path().join("18-08-24/09-55-30.CSV").exists());
+        assert_eq!(mounted.files().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_flysight2_stages_whole_sessions() {
+        let dest = test_helpers::tempdir();
+        let source = test_helpers::test_data("flysight2");
+
+        let flysight = FlysightConfig {
+            exports: vec![TrackFormat::Gpx],
+            ..FlysightConfig::test_config("data", source.path())
+        };
+        let mounted = flysight.mount_for_test();
+
+        let files = mounted.files().expect("Couldn't load test files");
+        assert_eq!(files.len(), 4);
+        assert_eq!(
+            files[3].capture_datetime().unwrap(),
+            Local.ymd(2018, 3, 10).and_hms(18, 39, 58)
+        );
+        assert_eq!(files[3].extension(), "track.csv");
+        assert!(DateTimeUploadable::jump_summary(&files[3]).is_some());
+        assert_eq!(DateTimeUploadable::jump_summary(&files[2]), None);
+        drop(files);
+
+        // Every file in each session, and a gpx for each of the two tracks
+        assert_eq!(mounted.stage_files("data", &dest, &Cancel::new()).unwrap(), 6);
+        assert!(!source.path().join("TRACKS/18-03-10/18-39-58/RAW.UBX").exists());
+    }
+
+    #[test]
+    fn test_staging_exports_tracks() {
+        let dest = test_helpers::tempdir();
+        let source = test_helpers::test_data("flysight");
+
+        let flysight = FlysightConfig {
+            exports: vec![TrackFormat::Gpx, TrackFormat::Kml],
+            ..FlysightConfig::test_config("data", source.path())
+        };
+        let mounted = flysight.mount_for_test();
+
+        assert_eq!(mounted.stage_files("data", &dest, &Cancel::new()).unwrap(), 10);
+
+        let mut names: Vec<_> = fs::read_dir(&dest.path())
+            .unwrap()
+            .map(|e| e.unwrap().file_name().into_string().unwrap())
+            .filter(|name| !name.ends_with(".manifest"))
+            .collect();
+        names.sort();
+        let extensions: Vec<_> = names.iter().map(|n| n.rsplit('.').next().unwrap()).collect();
+        assert_eq!(extensions, vec!["csv", "gpx", "kml", "csv", "gpx", "kml", "csv", "gpx", "kml", "txt"]);
+    }
+}
@@ -0,0 +1,307 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{BufRead, BufReader, Read};
+
+use chrono::prelude::*;
+use failure::Error;
+
+/// Fixes worse than this are usually the FlySight still finding satellites, and report wildly
+/// wrong altitudes.
+const MAX_VERTICAL_ACCURACY: f64 = 15.0;
+
+/// Jumpers leave the aircraft while it's flying more or less level, at a decent clip.
+const EXIT_MAX_VERTICAL_SPEED: f64 = 5.0;
+const EXIT_MIN_HORIZONTAL_SPEED: f64 = 20.0;
+/// How hard we need to be accelerating downwards for it to be an exit, in m/s/s.
+const EXIT_MIN_ACCELERATION: f64 = 3.0;
+/// How fast we need to be falling within `FREEFALL_CONFIRMATION_SECS` of exit for it to count
+/// as freefall. Canopy spirals can accelerate as hard as an exit, but start from a descent.
+const FREEFALL_MIN_VERTICAL_SPEED: f64 = 20.0;
+const FREEFALL_CONFIRMATION_SECS: f64 = 10.0;
+/// How hard we need to be slowing down for it to be a deployment, in m/s/s.
+const DEPLOY_MIN_DECELERATION: f64 = 5.0;
+
+/// A single fix from a FlySight track.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TrackPoint {
+    pub time: DateTime<Local>,
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Height above mean sea level, in metres.
+    pub altitude: f64,
+    /// Velocities in m/s. Down is positive.
+    pub vel_north: f64,
+    pub vel_east: f64,
+    pub vel_down: f64,
+    /// Estimated accuracy of this fix, in metres.
+    pub horizontal_accuracy: f64,
+    pub vertical_accuracy: f64,
+    pub gps_fix: u8,
+    pub satellites: u8,
+}
+
+impl TrackPoint {
+    pub fn horizontal_speed(&self) -> f64 {
+        self.vel_north.hypot(self.vel_east)
+    }
+
+    fn is_usable(&self) -> bool {
+        self.gps_fix >= 3 && self.vertical_accuracy <= MAX_VERTICAL_ACCURACY
+    }
+}
+
+/// A FlySight track, as read from its CSV.
+#[derive(Debug)]
+pub struct Track {
+    pub points: Vec<TrackPoint>,
+}
+
+/// The interesting parts of a jump, as derived from its track.
+///
+/// Altitudes are above the lowest point of the track, which is normally where the jumper landed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct JumpSummary {
+    pub exit_time: DateTime<Local>,
+    pub exit_altitude: f64,
+    /// Time from exit to deployment, in seconds.
+    pub freefall_duration: f64,
+    /// The fastest we were moving during freefall, in m/s.
+    pub max_vertical_speed: f64,
+    pub max_horizontal_speed: f64,
+    pub deploy_altitude: f64,
+}
+
+impl fmt::Display for JumpSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "exit at {} from {:.0}m, {:.1}s freefall, max {:.1}m/s vertical {:.1}m/s horizontal, deployed at {:.0}m",
+            self.exit_time.format("%H:%M:%S"),
+            self.exit_altitude,
+            self.freefall_duration,
+            self.max_vertical_speed,
+            self.max_horizontal_speed,
+            self.deploy_altitude,
+        )
+    }
+}
+
+/// Pull a named column out of a row, using the header to find it.
+fn column<'a>(columns: &HashMap<&str, usize>, row: &[&'a str], name: &str) -> Result<&'a str, Error> {
+    match columns.get(name).and_then(|&idx| row.get(idx)) {
+        Some(value) => Ok(value.trim()),
+        None => bail!("Missing column {}", name),
+    }
+}
+
+/// Map each column's name to its index.
+fn columns<'a, I: Iterator<Item = &'a str>>(header: I) -> HashMap<&'a str, usize> {
+    header
+        .enumerate()
+        .map(|(i, name)| (name.trim(), i))
+        .collect()
+}
+
+fn parse_point(columns: &HashMap<&str, usize>, row: &[&str]) -> Result<TrackPoint, Error> {
+    let time = DateTime::parse_from_rfc3339(column(columns, row, "time")?)?;
+    // FlySight 2 only logs once it has a fix, and so doesn't bother with this column.
+    let gps_fix = match columns.get("gpsFix") {
+        Some(_) => column(columns, row, "gpsFix")?.parse()?,
+        None => 3,
+    };
+    Ok(TrackPoint {
+        time: time.with_timezone(&Local),
+        latitude: column(columns, row, "lat")?.parse()?,
+        longitude: column(columns, row, "lon")?.parse()?,
+        altitude: column(columns, row, "hMSL")?.parse()?,
+        vel_north: column(columns, row, "velN")?.parse()?,
+        vel_east: column(columns, row, "velE")?.parse()?,
+        vel_down: column(columns, row, "velD")?.parse()?,
+        horizontal_accuracy: column(columns, row, "hAcc")?.parse()?,
+        vertical_accuracy: column(columns, row, "vAcc")?.parse()?,
+        gps_fix,
+        satellites: column(columns, row, "numSV")?.parse()?,
+    })
+}
+
+impl Track {
+    /// Parse a track from either generation of FlySight's CSV.
+    ///
+    /// The original FlySight writes a header row naming each column, followed by a row of units.
+    /// FlySight 2 writes a `$FLYS` preamble describing the columns of each kind of row, and then
+    /// tags every row with its kind. Only the `$GNSS` rows are of interest to us.
+    pub fn from_reader<R: Read>(reader: R) -> Result<Track, Error> {
+        let mut lines = BufReader::new(reader).lines();
+
+        let header = match lines.next() {
+            Some(header) => header?,
+            None => bail!("Track is empty"),
+        };
+
+        let mut points = vec![];
+        if header.starts_with("$FLYS") {
+            let lines = lines.collect::<Result<Vec<_>, _>>()?;
+            let header = match lines.iter().find(|line| line.starts_with("$COL,GNSS,")) {
+                Some(header) => header,
+                None => bail!("Track has no GNSS columns"),
+            };
+            // Skip the $COL, so that the indices line up with the $GNSS rows
+            let columns = columns(header.split(',').skip(1));
+            for line in lines.iter().filter(|line| line.starts_with("$GNSS,")) {
+                let row: Vec<&str> = line.split(',').collect();
+                points.push(parse_point(&columns, &row)?);
+            }
+        } else {
+            let columns = columns(header.split(','));
+            for line in lines {
+                let line = line?;
+                let row: Vec<&str> = line.split(',').collect();
+                // Skip the units, and anything else without a timestamp
+                if row.is_empty() || row[0].trim().is_empty() {
+                    continue;
+                }
+                points.push(parse_point(&columns, &row)?);
+            }
+        }
+
+        Ok(Track { points })
+    }
+
+    /// Work out where this track's exit and deployment happened, returning None if it doesn't
+    /// look like it contains a jump.
+    pub fn jump_summary(&self) -> Option<JumpSummary> {
+        let points: Vec<&TrackPoint> = self.points.iter().filter(|p| p.is_usable()).collect();
+
+        let ground = points
+            .iter()
+            .map(|p| p.altitude)
+            .fold(None, |min: Option<f64>, alt| Some(min.map_or(alt, |min| min.min(alt))))?;
+
+        let exit = (0..points.len()).find(|&i| {
+            let point = points[i];
+            if point.vel_down >= EXIT_MAX_VERTICAL_SPEED ||
+                point.horizontal_speed() < EXIT_MIN_HORIZONTAL_SPEED {
+                return false;
+            }
+            match acceleration(&points, i) {
+                Some(accel) if accel >= EXIT_MIN_ACCELERATION => {},
+                _ => return false,
+            }
+            points[i..]
+                .iter()
+                .take_while(|p| seconds_between(point, p) <= FREEFALL_CONFIRMATION_SECS)
+                .any(|p| p.vel_down >= FREEFALL_MIN_VERTICAL_SPEED)
+        })?;
+
+        let deploy = (exit..points.len()).find(|&i| {
+            points[i].vel_down >= FREEFALL_MIN_VERTICAL_SPEED &&
+                acceleration(&points, i).map_or(false, |accel| accel <= -DEPLOY_MIN_DECELERATION)
+        })?;
+
+        let freefall = &points[exit..=deploy];
+        Some(JumpSummary {
+            exit_time: points[exit].time,
+            exit_altitude: points[exit].altitude - ground,
+            freefall_duration: seconds_between(points[exit], points[deploy]),
+            max_vertical_speed: freefall.iter().map(|p| p.vel_down).fold(0.0, f64::max),
+            max_horizontal_speed: freefall.iter().map(|p| p.horizontal_speed()).fold(0.0, f64::max),
+            deploy_altitude: points[deploy].altitude - ground,
+        })
+    }
+}
+
+fn seconds_between(from: &TrackPoint, to: &TrackPoint) -> f64 {
+    (to.time - from.time).num_milliseconds() as f64 / 1000.0
+}
+
+/// The average vertical acceleration over the second following `idx`, in m/s/s.
+fn acceleration(points: &[&TrackPoint], idx: usize) -> Option<f64> {
+    let from = points[idx];
+    let to = points[idx..]
+        .iter()
+        .find(|p| seconds_between(from, p) >= 1.0)?;
+    Some((to.vel_down - from.vel_down) / seconds_between(from, to))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+
+    fn track(name: &str) -> Track {
+        let file = File::open(format!("test-data/flysight/18-08-24/{}", name)).unwrap();
+        Track::from_reader(file).unwrap()
+    }
+
+    fn assert_close(actual: f64, expected: f64) {
+        assert!((actual - expected).abs() < 0.01, "{} != {}", actual, expected);
+    }
+
+    #[test]
+    fn test_parses_tracks() {
+        let track = track("10-39-58.CSV");
+        assert_eq!(track.points.len(), 678);
+
+        let first = &track.points[0];
+        assert_eq!(first.time, Utc.ymd(2018, 3, 10).and_hms_milli(18, 39, 58, 800));
+        assert_close(first.latitude, 37.7269320);
+        assert_close(first.longitude, -121.3289665);
+        assert_close(first.altitude, 1564.964);
+        assert_close(first.vel_down, -4.68);
+        assert_eq!(first.gps_fix, 3);
+        assert_eq!(first.satellites, 4);
+    }
+
+    #[test]
+    fn test_summarises_jumps() {
+        let summary = track("10-39-58.CSV").jump_summary().expect("Didn't find a jump");
+        assert_eq!(summary.exit_time, Utc.ymd(2018, 3, 10).and_hms_milli(18, 40, 14, 200));
+        assert_close(summary.exit_altitude, 1589.212);
+        assert_close(summary.freefall_duration, 9.4);
+        assert_close(summary.max_vertical_speed, 49.67);
+        assert_close(summary.deploy_altitude, 1296.315);
+
+        let summary = track("11-00-28.CSV").jump_summary().expect("Didn't find a jump");
+        assert_eq!(summary.exit_time, Utc.ymd(2018, 3, 10).and_hms_milli(19, 0, 39, 800));
+        assert_close(summary.freefall_duration, 11.4);
+        assert_close(summary.max_vertical_speed, 52.3);
+    }
+
+    #[test]
+    fn test_parses_flysight2_tracks() {
+        let file = File::open("test-data/flysight2/TRACKS/18-03-10/18-39-58/TRACK.CSV").unwrap();
+        let track = Track::from_reader(file).unwrap();
+
+        let first = &track.points[0];
+        assert_eq!(first.time, Utc.ymd(2018, 3, 10).and_hms_milli(18, 39, 58, 800));
+        assert_close(first.altitude, 1564.964);
+        assert_eq!(first.gps_fix, 3);
+        assert_eq!(first.satellites, 4);
+
+        // It's the same jump as the classic fixture, so should summarise the same way
+        let summary = track.jump_summary().expect("Didn't find a jump");
+        assert_eq!(summary, self::track("10-39-58.CSV").jump_summary().unwrap());
+    }
+
+    #[test]
+    fn test_ignores_canopy_only_tracks() {
+        // This one starts under canopy, and includes a couple of spirals
+        assert_eq!(track("09-55-30.CSV").jump_summary(), None);
+    }
+
+    #[test]
+    fn test_formats_summaries() {
+        let summary = JumpSummary {
+            exit_time: Local.ymd(2018, 8, 24).and_hms(9, 55, 30),
+            exit_altitude: 1589.2,
+            freefall_duration: 9.4,
+            max_vertical_speed: 49.67,
+            max_horizontal_speed: 48.17,
+            deploy_altitude: 1296.3,
+        };
+        assert_eq!(
+            summary.to_string(),
+            "exit at 09:55:30 from 1589m, 9.4s freefall, max 49.7m/s vertical 48.2m/s horizontal, deployed at 1296m"
+        );
+    }
+}
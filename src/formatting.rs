@@ -54,6 +54,22 @@ pub fn human_readable_time(time: Duration) -> String {
     format!("{}{}s", out, secs)
 }
 
+/// Format the rate at which `bytes` were moved over `time` as something a human might reasonably
+/// interpret.
+/// ```rust
+/// # use archiver::formatting::human_readable_throughput;
+/// use time::Duration;
+///
+/// assert_eq!(human_readable_throughput(36700244, Duration::seconds(2)), "18m/s".to_string());
+/// assert_eq!(human_readable_throughput(2055, Duration::seconds(1)), "2k/s".to_string());
+/// ```
+pub fn human_readable_throughput(bytes: usize, time: Duration) -> String {
+    // Anything faster than a millisecond may as well have taken one.
+    let millis = std::cmp::max(time.num_milliseconds(), 1) as f64;
+    let per_second = (bytes as f64 * 1000.0 / millis) as usize;
+    format!("{}/s", human_readable_size(per_second))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,232 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+use failure::Error;
+
+/// A uevent from the kernel, telling us that a device was added, removed or changed.
+#[derive(Debug, Eq, PartialEq, Clone)]
+pub struct Uevent {
+    pub action: String,
+    pub devpath: String,
+    pub subsystem: String,
+    pub devtype: Option<String>,
+    pub devname: Option<String>,
+}
+
+impl Uevent {
+    /// Parse a uevent as the kernel sends it over netlink: an `action@devpath` header followed by
+    /// `KEY=value` pairs, all separated by NULs.
+    pub fn parse(buf: &[u8]) -> Option<Uevent> {
+        let mut fields = buf.split(|b| *b == 0).map(String::from_utf8_lossy);
+        let header = fields.next()?;
+        // udev rebroadcasts events in its own binary format, which we don't want anything to do
+        // with.
+        if !header.contains('@') {
+            return None;
+        }
+
+        let mut env: HashMap<_, _> = fields
+            .filter_map(|field| {
+                let mut parts = field.splitn(2, '=');
+                match (parts.next(), parts.next()) {
+                    (Some(k), Some(v)) => Some((k.to_string(), v.to_string())),
+                    _ => None,
+                }
+            })
+            .collect();
+
+        Some(Uevent {
+            action: env.remove("ACTION")?,
+            devpath: env.remove("DEVPATH")?,
+            subsystem: env.remove("SUBSYSTEM")?,
+            devtype: env.remove("DEVTYPE"),
+            devname: env.remove("DEVNAME"),
+        })
+    }
+
+    /// Whether this event might mean that a device we care about has come or gone.
+    ///
+    /// Card readers don't add a disk when a card goes in, they send a change for the disk that was
+    /// already there.
+    pub fn is_interesting(&self) -> bool {
+        let action = match &self.action[..] {
+            "add" | "remove" | "change" => true,
+            _ => false,
+        };
+        let device = match (&self.subsystem[..], self.devtype.as_ref().map(|t| &t[..])) {
+            ("block", Some("disk")) | ("block", Some("partition")) => true,
+            ("usb", Some("usb_device")) => true,
+            _ => false,
+        };
+        action && device
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub use self::netlink::UeventSocket;
+
+#[cfg(target_os = "linux")]
+mod netlink {
+    use std::io;
+    use std::mem;
+    use std::os::unix::io::RawFd;
+    use std::time::Duration;
+
+    use failure::Error;
+    use libc;
+
+    use super::Uevent;
+
+    /// The multicast group that the kernel sends uevents to. udev rebroadcasts them to group 2 once
+    /// it's done with them, but we don't want to depend on udev being around.
+    const KERNEL_UEVENTS: u32 = 1;
+
+    /// A netlink socket listening for uevents from the kernel.
+    #[derive(Debug)]
+    pub struct UeventSocket {
+        fd: RawFd,
+    }
+
+    impl UeventSocket {
+        pub fn open() -> Result<UeventSocket, Error> {
+            let fd = unsafe {
+                libc::socket(libc::AF_NETLINK, libc::SOCK_DGRAM | libc::SOCK_CLOEXEC, libc::NETLINK_KOBJECT_UEVENT)
+            };
+            if fd < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            // Owning it now means that it gets closed if binding fails
+            let socket = UeventSocket { fd };
+
+            let mut addr: libc::sockaddr_nl = unsafe { mem::zeroed() };
+            addr.nl_family = libc::AF_NETLINK as libc::sa_family_t;
+            addr.nl_groups = KERNEL_UEVENTS;
+            let ret = unsafe {
+                libc::bind(
+                    socket.fd,
+                    &addr as *const libc::sockaddr_nl as *const libc::sockaddr,
+                    mem::size_of::<libc::sockaddr_nl>() as libc::socklen_t,
+                )
+            };
+            if ret < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(socket)
+        }
+
+        /// Wait up to `timeout` for the next uevent.
+        ///
+        /// Returns None if nothing happened in that time, or what did happen wasn't a uevent.
+        pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Uevent>, Error> {
+            let mut pfd = libc::pollfd {
+                fd: self.fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            let millis = timeout.as_secs() * 1000 + u64::from(timeout.subsec_millis());
+            let ret = unsafe { libc::poll(&mut pfd, 1, millis as libc::c_int) };
+            if ret < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    return Ok(None);
+                }
+                return Err(err.into());
+            }
+            if ret == 0 {
+                return Ok(None);
+            }
+
+            let mut buf = [0u8; 8192];
+            let len = unsafe {
+                libc::recv(self.fd, buf.as_mut_ptr() as *mut libc::c_void, buf.len(), 0)
+            };
+            if len < 0 {
+                return Err(io::Error::last_os_error().into());
+            }
+            Ok(Uevent::parse(&buf[..len as usize]))
+        }
+    }
+
+    impl Drop for UeventSocket {
+        fn drop(&mut self) {
+            unsafe {
+                libc::close(self.fd);
+            }
+        }
+    }
+}
+
+/// Everywhere else we don't hear about devices coming and going, so we just wait and let the
+/// daemon rescan.
+#[cfg(not(target_os = "linux"))]
+#[derive(Debug)]
+pub struct UeventSocket {
+}
+
+#[cfg(not(target_os = "linux"))]
+impl UeventSocket {
+    pub fn open() -> Result<UeventSocket, Error> {
+        warn!("Hotplug events aren't supported on this platform, falling back to polling");
+        Ok(UeventSocket {})
+    }
+
+    pub fn recv_timeout(&self, timeout: Duration) -> Result<Option<Uevent>, Error> {
+        ::std::thread::sleep(timeout);
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(fields: &[&str]) -> Vec<u8> {
+        fields.join("\0").into_bytes()
+    }
+
+    #[test]
+    fn test_parses_uevents() {
+        let buf = event(&[
+            "add@/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb/sdb1",
+            "ACTION=add",
+            "DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0/host6/target6:0:0/6:0:0:0/block/sdb/sdb1",
+            "SUBSYSTEM=block",
+            "MAJOR=8",
+            "MINOR=17",
+            "DEVNAME=sdb1",
+            "DEVTYPE=partition",
+            "PARTN=1",
+            "SEQNUM=4242",
+            "",
+        ]);
+        let uevent = Uevent::parse(&buf).expect("Didn't parse");
+        assert_eq!(uevent.action, "add");
+        assert_eq!(uevent.subsystem, "block");
+        assert_eq!(uevent.devname, Some("sdb1".into()));
+        assert!(uevent.is_interesting());
+    }
+
+    #[test]
+    fn test_ignores_uninteresting_events() {
+        let bind = Uevent::parse(&event(&[
+            "bind@/devices/pci0000:00/0000:00:14.0/usb1/1-1",
+            "ACTION=bind",
+            "DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-1",
+            "SUBSYSTEM=usb",
+            "DEVTYPE=usb_device",
+        ])).unwrap();
+        assert!(!bind.is_interesting());
+
+        let interface = Uevent::parse(&event(&[
+            "add@/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0",
+            "ACTION=add",
+            "DEVPATH=/devices/pci0000:00/0000:00:14.0/usb1/1-1/1-1:1.0",
+            "SUBSYSTEM=usb",
+            "DEVTYPE=usb_interface",
+        ])).unwrap();
+        assert!(!interface.is_interesting());
+
+        // udev's own broadcasts have a binary header
+        assert_eq!(Uevent::parse(b"libudev\0\xfe\xed\xca\xfe"), None);
+    }
+}
@@ -38,9 +38,17 @@ macro_rules! client_for_routes {
     };
 }
 
+/// Preparing new cards and devices for use with archiver, by giving them an archiver folder and an
+/// identity file, and adding them to the config.
+pub mod bootstrap;
+
 /// A client to the web interface.
 pub mod client;
 
+/// Asking staging and uploading to stop partway through, somewhere that leaves staging and devices
+/// in a consistent state.
+pub mod cancel;
+
 /// Details pertaining to parsing the configuration file, as well as constructing the internal
 /// objects specified by the configuration.
 pub mod config;
@@ -53,9 +61,13 @@ pub mod ctx;
 /// Some helpers associated with driving the clis that ship with archiver.
 pub mod cli;
 
-/// Some helpers to abstract over the various types of devices that we can interact with. Much of
-/// this will probably go away at some point, and Device will become a trait instead of the enum
-/// that it is today.
+/// A long running daemon that stages devices as soon as they're attached, and uploads what it
+/// staged in the background.
+pub mod daemon;
+
+/// The `Device` trait that abstracts over the various types of devices that we can interact with,
+/// and the `DeviceRegistry` of the kinds of device we know how to find. Kinds other than the built
+/// in ones can be registered by crates that build on archiver.
 ///
 /// This module also contains the logic for simply enumerating all currently attached devices as
 /// part of generating a plan for an upload run.
@@ -66,15 +78,23 @@ pub mod device;
 /// here.
 pub mod dropbox;
 
-/// Flysight specific code. This mostly relates to parsing out the filenames that flysights create.
+/// Flysight specific code. This mostly relates to finding the files that each generation of
+/// flysight creates, and parsing the tracks within them.
 mod flysight;
 
+/// Listening to the kernel for devices being attached and detached.
+pub mod hotplug;
+
 /// A module concerning itself with presenting information in a human readable format.
 pub mod formatting;
 
 /// A storage adaptor governing a local storage device to archive the data onto.
 pub mod local_backup;
 
+/// The lock that stops more than one archiver from running at once. It records who holds it, so
+/// that locks left behind by crashed runs can be reclaimed.
+pub mod lock;
+
 /// Contains the MailReport trait which all mailers must implement, as well as the archiver
 /// specific glue for the `SGClient` object we use from the `sendgrid` crate.
 pub mod mailer;
@@ -93,10 +113,14 @@ pub mod messages;
 /// Contains machinery relating to mounting and unmounting devices.
 pub mod mountable;
 
-/// Our bindings to the ptp crate, which we use to talk to devices like Gopros over USB, allowing
-/// us to avoid having to pull the SD card in order to upload footage.
+/// Our bindings to the ptp crate, which we use to talk to cameras like Gopros over USB, allowing
+/// us to avoid having to pull the SD card in order to upload footage. Vendor specific behaviour
+/// lives in submodules on top of the generic support.
 pub mod ptp_device;
 
+/// Working out what a run would do without doing any of it, for dry runs.
+pub mod plan;
+
 /// Contains the `Notify` trait, which all notifiers must implement. Contains impls, as well as a
 /// little local glue to bind `config` and `pushover` together.
 pub mod pushover_notifier;
@@ -105,6 +129,26 @@ pub mod pushover_notifier;
 /// object up in memory, as well as rendering it to something we can mail to a user.
 mod reporting;
 
+/// What becomes of the files left on a device once they've been staged, be that deleting them,
+/// archiving them on the device, or holding on to the recent ones for a while.
+mod retention;
+
+/// A single run of archiver, split into phases: discovering devices, staging from them, uploading
+/// what was staged and reporting on how it went. Failures in any phase are recorded in the report
+/// rather than ending the run.
+pub mod run;
+
+/// Inspecting and managing the files waiting in staging, for when something has gone wrong and
+/// they're stuck there.
+pub mod staged;
+
+/// Tracking how far through staging and uploading each file is, and showing it on the terminal or
+/// as a stream of JSON events.
+pub mod progress;
+
+/// Schedules for the daemon's full cycles, given as either an interval or a cron expression.
+pub mod schedule;
+
 /// Machinry for locally staging files from attached devices. It includes the `Staging` trait,
 /// which when implemented allows for not implementing some of the heavy lifting.
 pub mod staging;
@@ -114,6 +158,10 @@ pub mod staging;
 /// pointlessly uploading things that are already there) and cleaning up the local staging area.
 pub mod storage;
 
+/// Telling systemd how we're doing when we're run as one of its services, both that we're ready
+/// and that we're still alive.
+pub mod systemd;
+
 /// The vimeo upload backend.
 pub mod vimeo;
 
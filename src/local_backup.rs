@@ -1,4 +1,4 @@
-use crate::config::{LocalBackupConfig, MountableDeviceLocation};
+use crate::config::{LocalBackupConfig, MountableDeviceLocation, Mounter};
 use crate::mountable::{MountableFilesystem, MountableKind, MountedFilesystem};
 use crate::staging;
 use crate::storage::{StorageAdaptor, StorageStatus};
@@ -35,6 +35,10 @@ impl MountableFilesystem for LocalBackupConfig {
     fn location(&self) -> &MountableDeviceLocation {
         &self.location
     }
+
+    fn mounter(&self) -> Option<Mounter> {
+        self.mounter
+    }
 }
 
 impl MountableKind for MountedLocalBackup {
@@ -109,6 +113,7 @@ mod tests {
     fn test_containing_dir() {
         let backup_adaptor = LocalBackupConfig {
             location: MountableDeviceLocation::Mountpoint("/test/directory".into()),
+            mounter: None,
         }.mount_for_test();
         let manifest = UploadDescriptor::test_descriptor();
 
@@ -120,6 +125,7 @@ mod tests {
     fn test_local_path() {
         let backup_adaptor = LocalBackupConfig {
             location: MountableDeviceLocation::Mountpoint("/test/directory".into()),
+            mounter: None,
         }.mount_for_test();
         let manifest = UploadDescriptor::test_descriptor();
 
@@ -132,6 +138,7 @@ mod tests {
         let tmp = test_helpers::tempdir();
         let adaptor = LocalBackupConfig {
             location: MountableDeviceLocation::Mountpoint(tmp.path().to_path_buf()),
+            mounter: None,
         }.mount_for_test();
 
         let mut manifest = UploadDescriptor::test_descriptor();
@@ -0,0 +1,358 @@
+use std::env;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::thread;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use failure::Error;
+use libc;
+use serde::{Deserialize, Serialize};
+use serde_json;
+
+use crate::config;
+
+/// How often to check on a lock we're waiting for.
+const WAIT_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Who holds a lock, as recorded in the lock file.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub hostname: String,
+    pub started: DateTime<Local>,
+    pub command: String,
+    /// When the process started, in clock ticks since boot, to tell it apart from a later process
+    /// given the same pid. Locks taken by older archivers don't have it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub process_started: Option<u64>,
+}
+
+impl LockHolder {
+    /// Describe this process.
+    pub fn current() -> LockHolder {
+        LockHolder {
+            pid: process::id(),
+            hostname: hostname(),
+            started: Local::now(),
+            command: env::args().collect::<Vec<_>>().join(" "),
+            process_started: process_started(process::id()),
+        }
+    }
+
+    /// Whether the holder is definitely no longer running, either because its process has gone
+    /// away or because its pid now belongs to another process, eg after a reboot.
+    ///
+    /// This doesn't go by the time the holder started, since machines without a real time clock
+    /// can boot with theirs far in the past, or have it jump forward once they're up.
+    ///
+    /// Holders on other hosts, eg when home directories are shared, can't be checked and are
+    /// assumed to still be running.
+    pub fn is_stale(&self) -> bool {
+        if self.hostname != hostname() {
+            return false;
+        }
+        if !process_is_running(self.pid) {
+            return true;
+        }
+        match (self.process_started, process_started(self.pid)) {
+            (Some(recorded), Some(running)) => recorded != running,
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            fmt,
+            "`{}` (pid {} on {}), running since {}",
+            self.command,
+            self.pid,
+            self.hostname,
+            self.started.format("%Y-%m-%d %H:%M:%S"),
+        )
+    }
+}
+
+/// Whether a lock is held, and by whom.
+#[derive(Debug, PartialEq)]
+pub enum LockStatus {
+    Free,
+    Held(LockHolder),
+    /// The lock is held, but doesn't say by whom, eg because it was taken by an older archiver.
+    HeldByUnknown,
+}
+
+#[derive(Fail, Debug)]
+pub enum LockError {
+    #[fail(display = "The archiver lock at {:?} is held by {}.", _0, _1)]
+    Held(PathBuf, LockHolder),
+    #[fail(display = "The archiver lock at {:?} is held, but doesn't say by whom. If nothing else is running, remove it.", _0)]
+    HeldByUnknown(PathBuf),
+}
+
+/// A held lock, which is released when this is dropped.
+#[derive(Debug)]
+pub struct Lock {
+    path: PathBuf,
+}
+
+impl Lock {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            error!("Couldn't release the lock at {:?}: {:?}", &self.path, e);
+        }
+    }
+}
+
+/// Where the archiver lock lives.
+pub fn lock_path() -> Result<PathBuf, Error> {
+    Ok(config::get_home()?.as_ref().join(".archiver.lock"))
+}
+
+/// Find out whether the lock at `path` is held, and by whom.
+pub fn status(path: &Path) -> Result<LockStatus, Error> {
+    match fs::read(path) {
+        Ok(contents) => Ok(match serde_json::from_slice(&contents) {
+            Ok(holder) => LockStatus::Held(holder),
+            Err(_) => LockStatus::HeldByUnknown,
+        }),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(LockStatus::Free),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Take the lock at `path`, reclaiming it if it's held by something that's no longer running.
+pub fn acquire(path: &Path) -> Result<Lock, Error> {
+    let holder = LockHolder::current();
+    // Give up eventually, rather than spinning on a lock that others keep taking and releasing.
+    for _ in 0..3 {
+        if try_acquire(path, &holder)? {
+            return Ok(Lock { path: path.to_path_buf() });
+        }
+        match status(path)? {
+            // Released while we were looking
+            LockStatus::Free => {},
+            LockStatus::Held(ref stale) if stale.is_stale() => {
+                warn!("Reclaiming the archiver lock from {}, which is no longer running", stale);
+                reclaim(path, stale)?;
+            },
+            LockStatus::Held(holder) => Err(LockError::Held(path.to_path_buf(), holder))?,
+            LockStatus::HeldByUnknown => Err(LockError::HeldByUnknown(path.to_path_buf()))?,
+        }
+    }
+    bail!("Couldn't take the archiver lock at {:?}, something else keeps taking it", path)
+}
+
+/// Like `acquire`, except that if the lock is held we wait for it to be released rather than
+/// giving up.
+pub fn acquire_waiting(path: &Path) -> Result<Lock, Error> {
+    let mut waiting = false;
+    loop {
+        match acquire(path) {
+            Ok(lock) => return Ok(lock),
+            Err(e) => {
+                let e = e.downcast::<LockError>()?;
+                if !waiting {
+                    info!("Waiting for the lock to be released: {}", e);
+                    waiting = true;
+                }
+                thread::sleep(WAIT_INTERVAL);
+            },
+        }
+    }
+}
+
+/// Wait until nothing is holding the lock at `path`. A stale lock counts as released.
+pub fn wait_until_free(path: &Path) -> Result<(), Error> {
+    loop {
+        match status(path)? {
+            LockStatus::Free => return Ok(()),
+            LockStatus::Held(ref holder) if holder.is_stale() => return Ok(()),
+            _ => thread::sleep(WAIT_INTERVAL),
+        }
+    }
+}
+
+/// Try to take the lock, returning whether we got it.
+///
+/// The lock is written out in full beside `path` and then linked into place, so that nobody ever
+/// sees a lock without its holder, and only one of any racing processes can win.
+fn try_acquire(path: &Path, holder: &LockHolder) -> Result<bool, Error> {
+    let tmp = path.with_extension(format!("lock.{}", holder.pid));
+    fs::write(&tmp, serde_json::to_vec(holder)?)?;
+    let linked = fs::hard_link(&tmp, path);
+    fs::remove_file(&tmp)?;
+    match linked {
+        Ok(()) => Ok(true),
+        Err(ref e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// Move a stale lock out of the way. If another process reclaimed it first and has since taken
+/// the lock, what we moved is theirs, so it's put back.
+fn reclaim(path: &Path, stale: &LockHolder) -> Result<(), Error> {
+    let aside = path.with_extension(format!("lock.stale.{}", process::id()));
+    match fs::rename(path, &aside) {
+        Ok(()) => {},
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    }
+    if status(&aside)? != LockStatus::Held(stale.clone()) {
+        // Linking rather than renaming means we can't clobber a lock taken in the meantime.
+        if let Err(e) = fs::hard_link(&aside, path) {
+            warn!("Couldn't put back a lock taken while reclaiming {:?}: {:?}", path, e);
+        }
+    }
+    fs::remove_file(&aside)?;
+    Ok(())
+}
+
+fn hostname() -> String {
+    let mut buf = [0u8; 256];
+    let ret = unsafe { libc::gethostname(buf.as_mut_ptr() as *mut libc::c_char, buf.len()) };
+    if ret != 0 {
+        return "unknown".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or_else(|| buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
+
+fn process_is_running(pid: u32) -> bool {
+    // Signal 0 checks whether the process could be signalled without sending anything. Processes
+    // that belong to someone else exist, we just aren't allowed to signal them.
+    let ret = unsafe { libc::kill(pid as libc::pid_t, 0) };
+    ret == 0 || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// When the process `pid` started, in clock ticks since boot.
+#[cfg(target_os = "linux")]
+fn process_started(pid: u32) -> Option<u64> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    // The command name comes second, in parens, and can contain anything, including spaces and
+    // parens, so count the fields from the end of it. The start time is the 22nd field.
+    let fields = &stat[stat.rfind(')')? + 1..];
+    fields.split_whitespace().nth(19)?.parse().ok()
+}
+
+#[cfg(not(target_os = "linux"))]
+fn process_started(_pid: u32) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use crate::test_helpers;
+
+    fn write_holder(path: &Path, holder: &LockHolder) {
+        fs::write(path, serde_json::to_vec(holder).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_locks_record_their_holder() {
+        let dir = test_helpers::tempdir();
+        let path = dir.path().join(".archiver.lock");
+        assert_eq!(status(&path).unwrap(), LockStatus::Free);
+
+        let lock = acquire(&path).unwrap();
+        match status(&path).unwrap() {
+            LockStatus::Held(holder) => {
+                assert_eq!(holder.pid, process::id());
+                assert_eq!(holder.hostname, hostname());
+                assert!(!holder.is_stale());
+            },
+            status => panic!("Lock wasn't held: {:?}", status),
+        }
+        match acquire(&path).unwrap_err().downcast::<LockError>() {
+            Ok(LockError::Held(_, holder)) => assert_eq!(holder.pid, process::id()),
+            other => panic!("Took a held lock: {:?}", other),
+        }
+
+        drop(lock);
+        assert_eq!(status(&path).unwrap(), LockStatus::Free);
+    }
+
+    #[test]
+    fn test_reclaims_locks_from_dead_processes() {
+        let dir = test_helpers::tempdir();
+        let path = dir.path().join(".archiver.lock");
+
+        let mut child = Command::new("true").spawn().unwrap();
+        child.wait().unwrap();
+        let dead = LockHolder {
+            pid: child.id(),
+            ..LockHolder::current()
+        };
+        assert!(dead.is_stale());
+        write_holder(&path, &dead);
+
+        let _lock = acquire(&path).unwrap();
+        match status(&path).unwrap() {
+            LockStatus::Held(holder) => assert_eq!(holder.pid, process::id()),
+            status => panic!("Lock wasn't held: {:?}", status),
+        }
+    }
+
+    #[test]
+    fn test_leaves_locks_it_cant_check() {
+        let dir = test_helpers::tempdir();
+        let path = dir.path().join(".archiver.lock");
+
+        let elsewhere = LockHolder {
+            pid: 1,
+            hostname: format!("not-{}", hostname()),
+            ..LockHolder::current()
+        };
+        assert!(!elsewhere.is_stale());
+        write_holder(&path, &elsewhere);
+        assert!(acquire(&path).is_err());
+
+        fs::write(&path, b"").unwrap();
+        assert_eq!(status(&path).unwrap(), LockStatus::HeldByUnknown);
+        assert!(acquire(&path).is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_locks_from_reused_pids_are_stale() {
+        let current = LockHolder::current();
+        assert!(current.process_started.is_some());
+
+        let reused = LockHolder {
+            process_started: current.process_started.map(|started| started + 1),
+            ..current.clone()
+        };
+        // Even though our pid is definitely running
+        assert!(reused.is_stale());
+    }
+
+    #[test]
+    fn test_clocks_that_jump_dont_make_locks_stale() {
+        // As happens on boards without a real time clock, which boot thinking it's 2000
+        let before_the_clock_was_set = LockHolder {
+            started: Local.ymd(2000, 1, 1).and_hms(0, 0, 0),
+            ..LockHolder::current()
+        };
+        assert!(!before_the_clock_was_set.is_stale());
+
+        // Nor does a lock from an older archiver, which didn't record when its process started
+        let older = LockHolder {
+            process_started: None,
+            ..LockHolder::current()
+        };
+        assert!(!older.is_stale());
+    }
+}
@@ -104,6 +104,7 @@ mod tests {
     use super::*;
     use std::io::Write;
     use std::fs;
+    use crate::cancel::Cancel;
     use crate::staging;
     use crate::test_helpers;
 
@@ -118,7 +119,30 @@ mod tests {
 
         let fh = ManualFile::from_paths(path, PathBuf::from("test-file.ogv")).expect("Couldn't create manualfile");
         let desc = fh.descriptor("test-upload");
-        staging::stage_file(fh, &dest, "manual").expect("Didn't stage correct");
+        staging::stage_file(fh, &dest, "manual", &Cancel::new()).expect("Didn't stage correct");
+    }
+
+    #[test]
+    fn test_aborted_staging_leaves_nothing_behind() {
+        let dest = test_helpers::tempdir();
+        let source = test_helpers::tempdir();
+
+        let path = source.path().join("test-file.ogv");
+        let mut test_data = File::create(&path).expect("Test file create");
+        assert!(test_data.write_all(b"This is some test data").is_ok());
+
+        let fh = ManualFile::from_paths(path.clone(), PathBuf::from("test-file.ogv")).expect("Couldn't create manualfile");
+        let cancel = Cancel::new();
+        cancel.abort();
+        assert!(staging::stage_file(fh, &dest, "manual", &cancel).is_err());
+
+        assert_eq!(fs::read_dir(dest.path()).unwrap().count(), 0);
+        assert!(path.exists());
+
+        // Devices staging many files stop there rather than failing
+        let fh = ManualFile::from_paths(path.clone(), PathBuf::from("test-file.ogv")).expect("Couldn't create manualfile");
+        assert_eq!(staging::stage_file_unless_aborted(fh, &dest, "manual", &cancel).unwrap(), false);
+        assert_eq!(fs::read_dir(dest.path()).unwrap().count(), 0);
     }
 
     #[test]
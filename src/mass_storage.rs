@@ -1,14 +1,24 @@
-use std::fs::{self, File};
-use std::path::PathBuf;
+use std::fs::File;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
 
-use crate::config::{MassStorageConfig, MountableDeviceLocation};
+use crate::config::{DeviceAction, MassStorageConfig, MassStorageFilters, MountableDeviceLocation, Mounter};
 use crate::mountable::{MountableFilesystem, MountedFilesystem, MountableKind};
-use crate::staging::{Staging, DateTimeUploadable};
+use crate::retention::{self, Retention};
+use crate::staging::{Disposal, Staging, DateTimeUploadable};
 
 use chrono;
 use chrono::prelude::*;
 use failure::Error;
-use walkdir::WalkDir;
+use glob::{MatchOptions, Pattern};
+use walkdir::{DirEntry, WalkDir};
+
+/// Mass storage devices are almost always FAT, so we don't care about case.
+const MATCH_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: false,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
+};
 
 #[derive(Debug)]
 pub struct MountedMassStorage {
@@ -22,6 +32,7 @@ pub struct MassStorageFile {
     file: File,
     extension: String,
     source_path: PathBuf,
+    retention: Retention,
 }
 
 impl DateTimeUploadable for MassStorageFile {
@@ -40,8 +51,11 @@ impl DateTimeUploadable for MassStorageFile {
     }
 
     fn delete(&mut self) -> Result<(), Error> {
-        fs::remove_file(&self.source_path)?;
-        Ok(())
+        self.retention.dispose(&self.source_path)
+    }
+
+    fn disposal(&self) -> Result<Disposal, Error> {
+        self.retention.disposal(&self.source_path)
     }
 
     fn size(&self) -> Result<u64, Error> {
@@ -49,12 +63,101 @@ impl DateTimeUploadable for MassStorageFile {
     }
 }
 
+/// The compiled form of a device's `MassStorageFilters`.
+#[derive(Debug)]
+struct FileFilter {
+    include: Vec<Pattern>,
+    exclude: Vec<Pattern>,
+    exclude_dirs: Vec<Pattern>,
+    min_size: Option<u64>,
+    max_size: Option<u64>,
+    min_age: Option<Duration>,
+}
+
+fn compile(patterns: &[String]) -> Result<Vec<Pattern>, Error> {
+    patterns
+        .iter()
+        .map(|p| Pattern::new(p.trim_end_matches('/')).map_err(|e| format_err!("Invalid pattern {:?}: {}", p, e)))
+        .collect()
+}
+
+fn is_hidden(entry: &DirEntry) -> bool {
+    // This also catches the AppleDouble `._` files that macOS leaves all over FAT filesystems
+    entry.depth() > 0 && entry.file_name().to_string_lossy().starts_with('.')
+}
+
+impl FileFilter {
+    fn new(filters: &MassStorageFilters) -> Result<FileFilter, Error> {
+        Ok(FileFilter {
+            include: compile(&filters.include)?,
+            exclude: compile(&filters.exclude)?,
+            exclude_dirs: compile(&filters.exclude_dirs)?,
+            min_size: filters.min_size,
+            max_size: filters.max_size,
+            min_age: filters.older_than_minutes.map(|mins| Duration::from_secs(mins * 60)),
+        })
+    }
+
+    /// Whether to look inside the directory at `relative`.
+    fn wants_dir(&self, relative: &Path) -> bool {
+        !self.exclude_dirs.iter().any(|p| p.matches_path_with(relative, MATCH_OPTIONS))
+    }
+
+    /// Whether to stage the file at `relative`, which is `size` bytes and was last modified at
+    /// `modified`.
+    fn wants_file(&self, relative: &Path, size: u64, modified: SystemTime, now: SystemTime) -> bool {
+        if !self.include.is_empty() && !self.include.iter().any(|p| p.matches_path_with(relative, MATCH_OPTIONS)) {
+            return false;
+        }
+        if self.exclude.iter().any(|p| p.matches_path_with(relative, MATCH_OPTIONS)) {
+            return false;
+        }
+        if self.min_size.map_or(false, |min| size < min) || self.max_size.map_or(false, |max| size > max) {
+            return false;
+        }
+        if let Some(min_age) = self.min_age {
+            // A file from the future is as suspicious as one that's still being written
+            match now.duration_since(modified) {
+                Ok(age) if age >= min_age => {},
+                _ => {
+                    info!("Skipping {:?}, it was modified too recently", relative);
+                    return false;
+                },
+            }
+        }
+        true
+    }
+}
+
+impl MountedMassStorage {
+    fn retention(&self) -> Retention {
+        Retention::new(&self.mass_storage.after_staging, self.mount.path(), self.mount.archiver_folder())
+    }
+}
+
 impl Staging for MountedMassStorage {
     type FileType = MassStorageFile;
 
     fn files(&self) -> Result<Vec<MassStorageFile>, Error> {
+        let filter = FileFilter::new(&self.mass_storage.filters)?;
+        let root = self.mount.path();
+        let retention = self.retention();
+        let kept = retention.kept()?;
+        let now = SystemTime::now();
+
         let mut out = vec![];
-        for entry in WalkDir::new(&self.mount.path()) {
+        let walker = WalkDir::new(root).into_iter().filter_entry(|entry| {
+            if is_hidden(entry) {
+                return false;
+            }
+            match entry.path().strip_prefix(root) {
+                // Whatever we archived last time has already been staged
+                Ok(relative) if entry.file_type().is_dir() && entry.depth() == 1 && retention::is_archive_dir(relative) => false,
+                Ok(relative) if entry.file_type().is_dir() && entry.depth() > 0 => filter.wants_dir(relative),
+                _ => true,
+            }
+        });
+        for entry in walker {
             let entry = entry?;
             if entry.file_type().is_dir() {
                 continue;
@@ -67,16 +170,44 @@ impl Staging for MountedMassStorage {
                     continue;
                 }
 
+                let metadata = entry.metadata()?;
+                let relative = path.strip_prefix(root)?;
+                if !filter.wants_file(relative, metadata.len(), metadata.modified()?, now) {
+                    continue;
+                }
+                if kept.contains(path, &metadata) {
+                    continue;
+                }
+
                 out.push(MassStorageFile {
-                    capturedatetime: path.metadata()?.modified()?.into(),
+                    capturedatetime: metadata.modified()?.into(),
                     file: File::open(path)?,
                     source_path: path.to_path_buf(),
+                    retention: retention.clone(),
                     extension,
                 });
             }
         }
         Ok(out)
     }
+
+    fn tidy_up(&self) -> Result<(), Error> {
+        let removed = self.retention().tidy()?;
+        if removed > 0 {
+            info!("Removed {} old files from {}", removed, &self.mass_storage.name);
+        }
+        Ok(())
+    }
+
+    fn perform_action(&mut self, action: DeviceAction) -> Result<String, Error> {
+        match action {
+            DeviceAction::Eject => {
+                self.mount.eject()?;
+                Ok("Ejected".to_string())
+            },
+            action => bail!("{} is not supported by this device", action),
+        }
+    }
 }
 
 impl MountableFilesystem for MassStorageConfig {
@@ -85,6 +216,14 @@ impl MountableFilesystem for MassStorageConfig {
     fn location(&self) -> &MountableDeviceLocation {
         &self.location
     }
+
+    fn mounter(&self) -> Option<Mounter> {
+        self.mounter
+    }
+
+    fn identity(&self) -> Option<&str> {
+        Some(&self.name)
+    }
 }
 
 impl MountableKind for MountedMassStorage {
@@ -102,15 +241,14 @@ impl MountableKind for MountedMassStorage {
 mod tests {
     use super::*;
     use filetime::{self, FileTime};
+    use crate::cancel::Cancel;
+    use crate::config::AfterStaging;
     use crate::test_helpers;
     use walkdir;
 
+    use std::fs;
     use std::path::Path;
 
-    fn extensions() -> Vec<String> {
-        vec!["mp4".into()]
-    }
-
     /// Git checkouts will have mtimes super close together, which will break our algorithm.
     ///
     /// We probably want at some point to remove this test (And introduce the opposite- proving
@@ -133,11 +271,7 @@ mod tests {
 
     #[test]
     fn test_mass_storage_loads_files() {
-        let mass_storage = MassStorageConfig {
-            name: "data".into(),
-            location: MountableDeviceLocation::from_mountpoint("test-data/mass_storage".into()),
-            extensions: extensions(),
-        };
+        let mass_storage = MassStorageConfig::test_config("data", "test-data/mass_storage");
         let mounted = mass_storage.mount_for_test();
 
         let files = mounted.files().expect("Couldn't load test files");
@@ -153,15 +287,11 @@ mod tests {
         let source = test_helpers::test_data("mass_storage");
         fix_filetimes(&source.path()).unwrap();
 
-        let mass_storage = MassStorageConfig {
-            name: "data".into(),
-            location: MountableDeviceLocation::from_mountpoint(source.path().to_path_buf()),
-            extensions: extensions(),
-        };
+        let mass_storage = MassStorageConfig::test_config("data", source.path());
 
         let mounted = mass_storage.mount_for_test();
 
-        mounted.stage_files("data", &dest).unwrap();
+        mounted.stage_files("data", &dest, &Cancel::new()).unwrap();
         // TODO(richo) test harder
         let iter = fs::read_dir(&dest.path()).unwrap();
         let files: Vec<_> = iter.collect();
@@ -169,4 +299,128 @@ mod tests {
         // Two files for the two mp4 files, two files for the manifests
         assert_eq!(files.len(), 4);
     }
+
+    fn mount_with_filters(root: &Path, filters: MassStorageFilters) -> MountedMassStorage {
+        MassStorageConfig {
+            filters,
+            ..MassStorageConfig::test_config("data", root)
+        }.mount_for_test()
+    }
+
+    #[test]
+    fn test_filters_by_pattern_and_size() {
+        let filter = FileFilter::new(&MassStorageFilters {
+            include: vec!["DCIM/**".into()],
+            exclude: vec!["**/*_proxy.mp4".into()],
+            exclude_dirs: vec!["MISC/".into()],
+            min_size: Some(10),
+            max_size: Some(100),
+            older_than_minutes: None,
+        }).unwrap();
+        let now = SystemTime::now();
+
+        assert!(filter.wants_file(Path::new("DCIM/100GOPRO/GOPR0042.MP4"), 50, now, now));
+        assert!(filter.wants_file(Path::new("dcim/100gopro/gopr0042.mp4"), 50, now, now));
+        assert!(!filter.wants_file(Path::new("GOPR0042.MP4"), 50, now, now));
+        assert!(!filter.wants_file(Path::new("DCIM/100GOPRO/GOPR0042_PROXY.MP4"), 50, now, now));
+        assert!(!filter.wants_file(Path::new("DCIM/100GOPRO/GOPR0042.MP4"), 5, now, now));
+        assert!(!filter.wants_file(Path::new("DCIM/100GOPRO/GOPR0042.MP4"), 500, now, now));
+
+        assert!(filter.wants_dir(Path::new("DCIM")));
+        assert!(!filter.wants_dir(Path::new("MISC")));
+    }
+
+    #[test]
+    fn test_skips_recently_modified_files() {
+        let source = test_helpers::test_data("mass_storage");
+        let filters = MassStorageFilters {
+            older_than_minutes: Some(10),
+            ..Default::default()
+        };
+
+        // We've only just copied them, so they could still be being written
+        let mounted = mount_with_filters(source.path(), filters.clone());
+        assert_eq!(mounted.files().unwrap().len(), 0);
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        let an_hour_ago = FileTime::from_unix_time(now.as_secs() as i64 - 60 * 60, 0);
+        for entry in walkdir::WalkDir::new(source.path()) {
+            let entry = entry.unwrap();
+            if entry.file_type().is_file() {
+                filetime::set_file_times(entry.path(), an_hour_ago, an_hour_ago).unwrap();
+            }
+        }
+        let mounted = mount_with_filters(source.path(), filters);
+        assert_eq!(mounted.files().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_skips_hidden_files_and_excluded_dirs() {
+        let source = test_helpers::test_data("mass_storage");
+        let root = source.path();
+        File::create(root.join("DCIM/100GOPRO/._GOPR7022.MP4")).unwrap();
+        fs::create_dir(root.join(".Trashes")).unwrap();
+        File::create(root.join(".Trashes/GOPR0001.MP4")).unwrap();
+        fs::create_dir(root.join("MISC")).unwrap();
+        File::create(root.join("MISC/GOPR0002.MP4")).unwrap();
+
+        let mounted = mount_with_filters(root, Default::default());
+        assert_eq!(mounted.files().unwrap().len(), 3);
+
+        let mounted = mount_with_filters(root, MassStorageFilters {
+            exclude_dirs: vec!["MISC".into()],
+            ..Default::default()
+        });
+        let files = mounted.files().unwrap();
+        assert_eq!(files.len(), 2);
+        assert!(files.iter().all(|f| f.source_path.starts_with(root.join("DCIM"))));
+    }
+
+    #[test]
+    fn test_archives_staged_files() {
+        let dest = test_helpers::tempdir();
+        let source = test_helpers::test_data("mass_storage");
+        let root = source.path();
+        fix_filetimes(root).unwrap();
+
+        let mounted = MassStorageConfig {
+            after_staging: AfterStaging::Archive { min_free_space: None },
+            ..MassStorageConfig::test_config("data", root)
+        }.mount_for_test();
+
+        assert_eq!(mounted.stage_files("data", &dest, &Cancel::new()).unwrap(), 2);
+        let archived: Vec<_> = walkdir::WalkDir::new(root.join("archived"))
+            .into_iter()
+            .map(|e| e.unwrap())
+            .filter(|e| e.file_type().is_file())
+            .collect();
+        assert_eq!(archived.len(), 2);
+
+        // Archived files have already been staged, so we shouldn't find them again
+        assert_eq!(mounted.files().unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_plans_without_touching_files() {
+        let source = test_helpers::test_data("mass_storage");
+        let root = source.path();
+        fix_filetimes(root).unwrap();
+
+        let mounted = MassStorageConfig {
+            after_staging: AfterStaging::Archive { min_free_space: None },
+            ..MassStorageConfig::test_config("data", root)
+        }.mount_for_test();
+
+        let planned = mounted.plan_files("data", true).unwrap();
+        assert_eq!(planned.len(), 2);
+        assert!(planned.iter().all(|file| file.disposal == Disposal::Archive));
+        assert!(planned.iter().all(|file| file.hashed && file.descriptor.content_hash != [0; 32]));
+
+        // Without hashing, nothing is read
+        let planned = mounted.plan_files("data", false).unwrap();
+        assert!(planned.iter().all(|file| !file.hashed && file.descriptor.content_hash == [0; 32]));
+
+        assert_eq!(mounted.files().unwrap().len(), 2);
+        assert!(!root.join("archived").exists());
+    }
 }
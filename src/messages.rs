@@ -50,3 +50,21 @@ pub enum SendNotificationResp {
     NotConfigured,
     Error(String),
 }
+
+/// Register a device that was just bootstrapped, so that it shows up in the config.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct RegisterDevice {
+    pub name: String,
+    pub kind: String,
+    pub identifier: String,
+    /// Settings specific to the kind of device, like the extensions to archive from a mass storage
+    /// device.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+pub enum RegisterDeviceResp {
+    Registered,
+    Error(String),
+}
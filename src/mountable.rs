@@ -1,3 +1,7 @@
+use std::ffi::{CString, OsStr};
+use std::io;
+use std::mem;
+use std::os::unix::ffi::OsStrExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
 
@@ -7,34 +11,127 @@ use std::fmt::Debug;
 
 use failure::Error;
 use std::fs;
-use crate::config::MountableDeviceLocation;
+use toml;
+use crate::config::{MountableDeviceLocation, Mounter};
 
 pub const MOUNTABLE_DEVICE_FOLDER: &'static str = "archiver";
 
+/// The file in `MOUNTABLE_DEVICE_FOLDER` that says which device a filesystem belongs to.
+pub const IDENTITY_FILE: &'static str = "device.toml";
+
+/// What a filesystem says about itself in its identity file.
+///
+/// Cards get swapped between cameras, and will all have the same label if the cameras formatted
+/// them, so this lets the card itself say whose footage is on it.
+#[derive(Serialize, Deserialize, Debug, Eq, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DeviceIdentity {
+    /// The name of the device in the config.
+    pub name: String,
+}
+
+impl DeviceIdentity {
+    pub fn new(name: String) -> DeviceIdentity {
+        DeviceIdentity { name }
+    }
+
+    /// Read the identity file from the archiver folder at `path`, if there is one.
+    pub fn from_path(path: &Path) -> Result<Option<DeviceIdentity>, Error> {
+        let file = path.join(IDENTITY_FILE);
+        if !file.exists() {
+            return Ok(None);
+        }
+        let body = fs::read_to_string(&file)?;
+        let identity = toml::from_str(&body)
+            .map_err(|e| format_err!("Couldn't parse {:?}: {}", &file, e))?;
+        Ok(Some(identity))
+    }
+
+    /// Write this identity into the archiver folder at `path`.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        fs::write(path.join(IDENTITY_FILE), toml::to_string(self)?)?;
+        Ok(())
+    }
+}
+
 #[derive(Debug)]
 pub struct MountedFilesystem {
     mountpoint: PathBuf,
+    /// The archiver folder on this filesystem. Bootstrapped devices are used from inside it, in
+    /// which case it's the same as `mountpoint`.
+    folder: PathBuf,
     device: PathBuf,
     mounter: Box<dyn Unmounter>,
 }
 
 impl MountedFilesystem {
-    /// Creates a MountedFilesystem from an already mounted filesystem. No cleanup or validation
-    /// will take place to assert that this is actually what it's meant to be.
-    pub fn new_externally_mounted(mountpoint: PathBuf) -> MountedFilesystem {
+    fn new(mountpoint: PathBuf, device: PathBuf, mounter: Box<dyn Unmounter>) -> MountedFilesystem {
         MountedFilesystem {
+            folder: mountpoint.join(MOUNTABLE_DEVICE_FOLDER),
             mountpoint,
-            // TODO(richo) Should we look this up?
-            device: PathBuf::new(),
-            mounter: Box::new(ExternallyMounted{}),
+            device,
+            mounter,
         }
     }
+
+    /// Creates a MountedFilesystem from an already mounted filesystem. No cleanup or validation
+    /// will take place to assert that this is actually what it's meant to be.
+    pub fn new_externally_mounted(mountpoint: PathBuf) -> MountedFilesystem {
+        // TODO(richo) Should we look this up?
+        MountedFilesystem::new(mountpoint, PathBuf::new(), Box::new(ExternallyMounted{}))
+    }
+
+    /// Use this filesystem from inside its archiver folder, which must have been created when the
+    /// device was bootstrapped.
+    fn into_archiver_folder(mut self) -> Result<MountedFilesystem, Error> {
+        self.mountpoint = archiver_folder(&self.mountpoint)?;
+        self.folder = self.mountpoint.clone();
+        Ok(self)
+    }
 }
 
 impl MountedFilesystem {
     pub fn path(&self) -> &Path {
         &self.mountpoint
     }
+
+    /// The archiver folder on this filesystem, where it keeps its identity file. It may not exist
+    /// on filesystems that were never bootstrapped.
+    pub fn archiver_folder(&self) -> &Path {
+        &self.folder
+    }
+
+    /// Which device this filesystem says it belongs to, if it says.
+    pub fn identity(&self) -> Result<Option<DeviceIdentity>, Error> {
+        DeviceIdentity::from_path(&self.folder)
+    }
+
+    /// Unmount this filesystem now and power off the device it lives on, so that it can be
+    /// safely removed.
+    pub fn eject(&mut self) -> Result<(), Error> {
+        self.mounter.eject(&self.device)?;
+        // There's nothing left to unmount when we're dropped.
+        self.mounter = Box::new(Ejected{});
+        Ok(())
+    }
+
+    /// Unmount this filesystem now, rather than when it's dropped, so that we can find out
+    /// whether it worked.
+    pub fn unmount(mut self) -> Result<(), Error> {
+        let result = self.mounter.unmount(&self.device);
+        self.mounter = Box::new(Unmounted{});
+        result
+    }
+}
+
+/// How many bytes are available to us on the filesystem containing `path`.
+pub fn free_space(path: &Path) -> io::Result<u64> {
+    let c_path = CString::new(path.as_os_str().as_bytes())?;
+    let mut stat: libc::statvfs = unsafe { mem::zeroed() };
+    if unsafe { libc::statvfs(c_path.as_ptr(), &mut stat) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(stat.f_bavail as u64 * stat.f_frsize as u64)
 }
 
 #[derive(Debug)]
@@ -45,9 +142,57 @@ pub struct ExternallyMounted {
 pub struct UdisksMounter {
 }
 
+/// Mounts with plain old mount(8), relying on fstab to know where things go.
+#[derive(Debug)]
+pub struct SystemMounter {
+}
+
+#[derive(Debug)]
+struct Ejected {
+}
+
+#[derive(Debug)]
+struct Unmounted {
+}
+
+/// Flush everything we've written out to disk, so that unmounting doesn't have to.
+fn sync() {
+    info!("Syncing first");
+    match Command::new("sync")
+        .status()
+    {
+        Ok(status) => {
+            if status.success() {
+                info!("sync complete")
+            } else {
+                warn!("sync returned {:?}", status.code())
+            }
+        },
+        Err(e) => warn!("sync failed, continuing: {:?}", e),
+    }
+}
+
+/// Find the archiver folder on a freshly mounted device, which must have been created when the
+/// device was bootstrapped.
+fn archiver_folder(mountpoint: &Path) -> Result<PathBuf, Error> {
+    let folder = mountpoint.join(MOUNTABLE_DEVICE_FOLDER);
+    if !folder.exists() {
+        bail!("Directory {:?} does not exist, device probably needs to be bootstrapped with `bootstrap`", &folder);
+    }
+    Ok(folder)
+}
+
 impl UdisksMounter {
     pub fn mount<U>(device: U) -> Result<MountedFilesystem, Error>
     where U: AsRef<Path> + Debug
+    {
+        UdisksMounter::mount_root(device)?.into_archiver_folder()
+    }
+
+    /// Mount the device, without looking for an archiver folder on it. Only bootstrapping a
+    /// device should need this.
+    pub fn mount_root<U>(device: U) -> Result<MountedFilesystem, Error>
+    where U: AsRef<Path> + Debug
     {
         info!("Mounting {:?}", &device);
         let child = Command::new("udisksctl")
@@ -62,73 +207,145 @@ impl UdisksMounter {
 
         if child.status.success() {
             if let Some(matches) = regex.captures(&String::from_utf8_lossy(&child.stdout)) {
-                let mut mountpoint = PathBuf::from(matches.get(2).unwrap().as_str());
+                let mountpoint = PathBuf::from(matches.get(2).unwrap().as_str());
                 info!("Mounted at {:?}", &mountpoint);
 
-                mountpoint.push(MOUNTABLE_DEVICE_FOLDER);
-                if !mountpoint.exists() {
-                    bail!("Directory {:?} does not exist, device probably needs to be bootstrapped", &mountpoint);
-                }
-
-                return Ok(MountedFilesystem {
-                    mountpoint,
-                    device: device.as_ref().to_path_buf(),
-                    mounter: Box::new(UdisksMounter{}),
-                });
+                return Ok(MountedFilesystem::new(mountpoint, device.as_ref().to_path_buf(), Box::new(UdisksMounter{})));
             }
         }
         bail!("Failed to mount: {}", String::from_utf8_lossy(&child.stderr));
     }
 }
 
+impl SystemMounter {
+    /// Mount the device with the given label wherever fstab says it goes.
+    pub fn mount_device<U>(device: U) -> Result<MountedFilesystem, Error>
+    where U: AsRef<Path> + Debug
+    {
+        if let Some(mountpoint) = mountpoint_for_device(device.as_ref())? {
+            info!("{:?} is already mounted at {:?}", &device, &mountpoint);
+            return MountedFilesystem::new_externally_mounted(mountpoint).into_archiver_folder();
+        }
+
+        SystemMounter::run("mount", device.as_ref())?;
+        let mountpoint = match mountpoint_for_device(device.as_ref())? {
+            Some(mountpoint) => mountpoint,
+            None => bail!("Mounted {:?}, but couldn't find where", &device),
+        };
+        info!("Mounted at {:?}", &mountpoint);
+
+        MountedFilesystem::new(mountpoint, device.as_ref().to_path_buf(), Box::new(SystemMounter{}))
+            .into_archiver_folder()
+    }
+
+    /// Mount whatever fstab says belongs at `mountpoint`.
+    pub fn mount_path(mountpoint: &Path) -> Result<MountedFilesystem, Error> {
+        if is_mountpoint(mountpoint)? {
+            info!("{:?} is already mounted", &mountpoint);
+            return Ok(MountedFilesystem::new_externally_mounted(mountpoint.to_path_buf()));
+        }
+
+        SystemMounter::run("mount", mountpoint)?;
+        Ok(MountedFilesystem::new(mountpoint.to_path_buf(), mountpoint.to_path_buf(), Box::new(SystemMounter{})))
+    }
+
+    fn run(command: &str, target: &Path) -> Result<(), Error> {
+        info!("Running {} {:?}", command, target);
+        let child = Command::new(command)
+            .arg(target)
+            .output()?;
+
+        if !child.status.success() {
+            bail!("{} {:?} failed: {}", command, target, String::from_utf8_lossy(&child.stderr).trim());
+        }
+        Ok(())
+    }
+}
+
 trait Unmounter: Debug + Sync + Send {
-    fn unmount(&mut self, device: &Path);
+    fn unmount(&mut self, device: &Path) -> Result<(), Error>;
+
+    fn eject(&mut self, device: &Path) -> Result<(), Error>;
 }
 
 impl Unmounter for UdisksMounter {
-    fn unmount(&mut self, device: &Path) {
+    fn unmount(&mut self, device: &Path) -> Result<(), Error> {
         info!("Unmounting device at {:?}", &device);
-        info!("Syncing first");
-        match Command::new("sync")
-            .status()
-        {
-            Ok(status) => {
-                if status.success() {
-                    info!("sync complete")
-                } else {
-                    warn!("sync returned {:?}", status.code())
-                }
-            },
-            Err(e) => warn!("sync failed, continuing: {:?}", e),
-        }
+        sync();
 
-        match Command::new("udisksctl")
+        let child = Command::new("udisksctl")
             .arg("unmount")
             .arg("--no-user-interaction")
             .arg("-b")
             .arg(device)
-            .output()
-        {
-            Ok(child) => {
-                if !child.status.success() {
-                    error!("Couldn't unmount device: {}", String::from_utf8_lossy(&child.stderr));
-                } else {
-                    info!("Successfully umounted");
-                }
-            },
-            Err(e) => {
-                error!("Couldn't launch unmount: {:?}", e);
-                return;
-            }
+            .output()?;
+
+        if !child.status.success() {
+            bail!("Couldn't unmount device: {}", String::from_utf8_lossy(&child.stderr));
+        }
+        info!("Successfully umounted");
+        Ok(())
+    }
+
+    fn eject(&mut self, device: &Path) -> Result<(), Error> {
+        self.unmount(device)?;
+
+        info!("Powering off device at {:?}", &device);
+        let child = Command::new("udisksctl")
+            .arg("power-off")
+            .arg("--no-user-interaction")
+            .arg("-b")
+            .arg(device)
+            .output()?;
 
+        if !child.status.success() {
+            bail!("Couldn't power off device: {}", String::from_utf8_lossy(&child.stderr));
         }
+        Ok(())
+    }
+}
+
+impl Unmounter for SystemMounter {
+    fn unmount(&mut self, device: &Path) -> Result<(), Error> {
+        info!("Unmounting {:?}", &device);
+        sync();
+        SystemMounter::run("umount", device)
+    }
 
+    fn eject(&mut self, _: &Path) -> Result<(), Error> {
+        bail!("Ejecting is only supported for devices mounted with udisks");
     }
 }
 
 impl Unmounter for ExternallyMounted {
-    fn unmount(&mut self, _: &Path) {
+    fn unmount(&mut self, _: &Path) -> Result<(), Error> {
         info!("Doing nothing because this was mounted when we got here");
+        Ok(())
+    }
+
+    fn eject(&mut self, _: &Path) -> Result<(), Error> {
+        bail!("Not ejecting a device that was mounted when we got here");
+    }
+}
+
+impl Unmounter for Ejected {
+    fn unmount(&mut self, _: &Path) -> Result<(), Error> {
+        info!("Doing nothing because this device has already been ejected");
+        Ok(())
+    }
+
+    fn eject(&mut self, _: &Path) -> Result<(), Error> {
+        bail!("Device has already been ejected");
+    }
+}
+
+impl Unmounter for Unmounted {
+    fn unmount(&mut self, _: &Path) -> Result<(), Error> {
+        Ok(())
+    }
+
+    fn eject(&mut self, _: &Path) -> Result<(), Error> {
+        bail!("Device has already been unmounted");
     }
 }
 
@@ -139,29 +356,148 @@ impl Drop for MountedFilesystem {
             mounter,
             ..
         } = self;
-        mounter.unmount(device);
+        // Nobody is around to hear about this, use `unmount` to find out whether it worked.
+        if let Err(e) = mounter.unmount(device) {
+            error!("Couldn't unmount {:?}: {}", device, e);
+        }
+    }
+}
+
+/// Undo the octal escaping that /proc/mounts uses for spaces and the like.
+fn unescape_mount_field(field: &str) -> PathBuf {
+    let bytes = field.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'\\' && i + 3 < bytes.len() && bytes[i + 1..i + 4].iter().all(|b| (b'0'..=b'7').contains(b)) {
+            out.push((bytes[i + 1] - b'0') * 64 + (bytes[i + 2] - b'0') * 8 + (bytes[i + 3] - b'0'));
+            i += 4;
+        } else {
+            out.push(bytes[i]);
+            i += 1;
+        }
+    }
+    PathBuf::from(OsStr::from_bytes(&out))
+}
+
+/// Parse the contents of /proc/mounts or /etc/fstab into (device, mountpoint) pairs.
+fn parse_mounts(mounts: &str) -> Vec<(PathBuf, PathBuf)> {
+    mounts
+        .lines()
+        .filter(|line| !line.trim_start().starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace();
+            match (fields.next(), fields.next()) {
+                (Some(device), Some(mountpoint)) => Some((unescape_mount_field(device), unescape_mount_field(mountpoint))),
+                _ => None,
+            }
+        })
+        .collect()
+}
+
+fn mounts() -> Result<Vec<(PathBuf, PathBuf)>, Error> {
+    Ok(parse_mounts(&fs::read_to_string("/proc/mounts")?))
+}
+
+/// Where the device at `device` is mounted, if it is. `device` can be a symlink, like the ones in
+/// /dev/disk/by-label.
+fn mountpoint_for_device(device: &Path) -> Result<Option<PathBuf>, Error> {
+    let device = fs::canonicalize(device)?;
+    Ok(mounts()?
+        .into_iter()
+        .find(|(dev, _)| fs::canonicalize(dev).map(|dev| dev == device).unwrap_or(false))
+        .map(|(_, mountpoint)| mountpoint))
+}
+
+/// The device that fstab says belongs at `mountpoint`, if any.
+fn fstab_device(mountpoint: &Path) -> Option<PathBuf> {
+    let fstab = fs::read_to_string("/etc/fstab").ok()?;
+    let (spec, _) = parse_mounts(&fstab).into_iter().find(|(_, mp)| mp == mountpoint)?;
+    Some(resolve_fstab_spec(&spec.to_string_lossy()))
+}
+
+/// Turn the `LABEL=` and `UUID=` style of naming a device into a path to it.
+fn resolve_fstab_spec(spec: &str) -> PathBuf {
+    if spec.contains('=') {
+        if let Some(device) = device_for_location(&MountableDeviceLocation::from_identifier(spec)) {
+            return device;
+        }
     }
+    PathBuf::from(spec)
+}
+
+/// Whether something is mounted at `path`.
+fn is_mountpoint(path: &Path) -> Result<bool, Error> {
+    let path = fs::canonicalize(path)?;
+    Ok(mounts()?.iter().any(|(_, mountpoint)| mountpoint == &path))
+}
+
+/// The block device for a location that names one, rather than a path to use as is.
+#[cfg(target_os = "linux")]
+pub fn device_for_location(location: &MountableDeviceLocation) -> Option<PathBuf> {
+    let (dir, name) = match location {
+        MountableDeviceLocation::Label(lbl) => ("by-label", lbl),
+        MountableDeviceLocation::Uuid(uuid) => ("by-uuid", uuid),
+        MountableDeviceLocation::PartUuid(uuid) => ("by-partuuid", uuid),
+        MountableDeviceLocation::Mountpoint(_) => return None,
+    };
+    let mut pb = PathBuf::from("/dev/disk");
+    pb.push(dir);
+    pb.push(name);
+    Some(pb)
 }
 
+/// The block device for a location that names one, rather than a path to use as is.
+///
+/// macOS mounts everything under /Volumes by its label, and doesn't give us a way to find things
+/// by UUID without asking diskutil.
+#[cfg(target_os = "macos")]
+pub fn device_for_location(location: &MountableDeviceLocation) -> Option<PathBuf> {
+    match location {
+        MountableDeviceLocation::Label(lbl) => {
+            let mut pb = PathBuf::from("/Volumes");
+            pb.push(lbl);
+            Some(pb)
+        },
+        _ => None,
+    }
+}
 
+/// Find the archiver folder on a device that somebody else mounted.
 #[cfg(target_os = "linux")]
-fn device_for_label(lbl: &str) -> PathBuf {
-    let mut pb = PathBuf::from("/dev/disk/by-label");
-    pb.push(lbl);
-    pb
+fn externally_mounted(device: &Path) -> Result<MountedFilesystem, Error> {
+    match mountpoint_for_device(device)? {
+        Some(mountpoint) => MountedFilesystem::new_externally_mounted(mountpoint).into_archiver_folder(),
+        None => bail!("{:?} isn't mounted", device),
+    }
 }
 
+/// Find the archiver folder on a device that somebody else mounted.
 #[cfg(target_os = "macos")]
-fn device_for_label(lbl: &str) -> PathBuf {
-    let mut pb = PathBuf::from("/Volumes");
-    pb.push(lbl);
-    pb
+fn externally_mounted(device: &Path) -> Result<MountedFilesystem, Error> {
+    // The volume is the mountpoint
+    MountedFilesystem::new_externally_mounted(device.to_path_buf()).into_archiver_folder()
 }
 
-fn attached_by_label(lbl: &str) -> bool {
-    let pb = device_for_label(lbl);
-    info!("Checking if {:?} exists", &pb);
-    pb.exists()
+fn attached_by_device(location: &MountableDeviceLocation) -> bool {
+    match device_for_location(location) {
+        Some(pb) => {
+            info!("Checking if {:?} exists", &pb);
+            pb.exists()
+        },
+        None => false,
+    }
+}
+
+/// Make sure that the filesystem mounted at `mount` doesn't belong to some device other than
+/// `name`. Filesystems that don't say who they belong to are assumed to be ours.
+fn check_identity(mount: &MountedFilesystem, name: &str) -> Result<(), Error> {
+    match mount.identity()? {
+        Some(ref identity) if identity.name != name => {
+            bail!("{:?} belongs to {}, not {}", mount.path(), identity.name, name)
+        },
+        _ => Ok(()),
+    }
 }
 
 /// This trait is the core of mountable, however various blanket impls exist to make implementation
@@ -181,53 +517,75 @@ pub trait MountableFilesystem: Sized {
     type Target: MountableKind<This = Self>;
 
     fn mount(self) -> Result<Self::Target, Error> {
-        let mount = match self.location() {
-            MountableDeviceLocation::Label(lbl) => {
-                let device = device_for_label(&lbl);
-                UdisksMounter::mount(device)?
-            },
-            MountableDeviceLocation::Mountpoint(_) => unimplemented!(),
-        };
+        let mount = self.mount_filesystem()?;
+        if let Some(name) = self.identity() {
+            check_identity(&mount, name)?;
+        }
 
         Ok(Self::Target::from_mounted_parts(self, mount))
     }
 
+    /// Mount the filesystem, without checking who it belongs to.
+    fn mount_filesystem(&self) -> Result<MountedFilesystem, Error> {
+        let mounter = self.mounter().unwrap_or_else(|| self.location().default_mounter());
+        Ok(match self.location() {
+            MountableDeviceLocation::Mountpoint(path) => match mounter {
+                Mounter::Udisks => bail!("udisks can't mount a mountpoint, only a device ({:?})", path),
+                Mounter::Mount => SystemMounter::mount_path(path)?,
+                Mounter::External => {
+                    if !path.is_dir() {
+                        bail!("{:?} doesn't exist, or isn't a directory", path);
+                    }
+                    MountedFilesystem::new_externally_mounted(path.clone())
+                },
+            },
+            location => {
+                let device = match device_for_location(location) {
+                    Some(device) => device,
+                    None => bail!("Can't find a device for {} on this platform", location),
+                };
+                match mounter {
+                    Mounter::Udisks => UdisksMounter::mount(device)?,
+                    Mounter::Mount => SystemMounter::mount_device(device)?,
+                    Mounter::External => externally_mounted(&device)?,
+                }
+            },
+        })
+    }
+
     #[cfg(test)]
     fn mount_for_test(self) -> Self::Target {
         let loc = match self.location() {
-            MountableDeviceLocation::Label(_) => panic!("Labels not supported in tests"),
             MountableDeviceLocation::Mountpoint(mp) => mp.clone(),
+            location => panic!("{} not supported in tests", location),
         };
 
         let mount = MountedFilesystem::new_externally_mounted(loc);
         Self::Target::from_mounted_parts(self, mount)
     }
 
-    #[cfg(platform = "macos")]
-    fn mount_filesystem(&self) -> Result<MountedFilesystem, Error> {
-        match self.location() {
-            MountableDeviceLocation::Label(lbl) => {
-                MountedFilesystem::<ExternallyMounted>::new_externally_mounted(device_for_label(lbl))
-            },
-            MountableDeviceLocation::Mountpoint(path) => {
-                unimplemented!();
-            },
-        }
-    }
+    fn location(&self) -> &MountableDeviceLocation;
 
-    #[cfg(platform = "linux")]
-    fn mount_filesystem(&self) -> Result<MountedFilesystem<UdisksMounter>, Error> {
-        match self.location() {
-            MountableDeviceLocation::Label(lbl) => {
-                let dev = device_for_label(lbl);
-                UdisksMounter::mount(dev)
-            },
-            MountableDeviceLocation::Mountpoint(path) => {
-            },
-        }
+    /// How this filesystem should be mounted, if the config says. Otherwise it's up to the
+    /// location.
+    fn mounter(&self) -> Option<Mounter>;
+
+    /// The name this filesystem must have in its identity file, if it has one. Only devices that
+    /// we stage from need to check, since those are the ones that get swapped around.
+    fn identity(&self) -> Option<&str> {
+        None
     }
 
-    fn location(&self) -> &MountableDeviceLocation;
+    /// Where the filesystem this config names lives, following symlinks like the ones in
+    /// /dev/disk/by-label, so that configs naming the same filesystem can be told apart from ones
+    /// that don't. `None` if it can't be found.
+    fn filesystem_path(&self) -> Option<PathBuf> {
+        let path = match self.location() {
+            MountableDeviceLocation::Mountpoint(path) => path.clone(),
+            location => device_for_location(location)?,
+        };
+        fs::canonicalize(path).ok()
+    }
 
     fn get(self) -> Option<Self> {
         if self.is_attached() {
@@ -239,14 +597,16 @@ pub trait MountableFilesystem: Sized {
 
     fn is_attached(&self) -> bool {
         match self.location() {
-            MountableDeviceLocation::Label(lbl) => {
-                attached_by_label(&lbl[..])
-            },
             MountableDeviceLocation::Mountpoint(path) => {
                 // Hopefully empty means nothing was written there in the meantime
                 if !path.exists() {
                     return false;
                 }
+                // We'll be mounting it ourselves, so the question is whether what goes there has
+                // been plugged in.
+                if self.mounter() == Some(Mounter::Mount) && !is_mountpoint(path).unwrap_or(false) {
+                    return fstab_device(path).map_or(false, |device| device.exists());
+                }
                 let files: Vec<_> = fs::read_dir(path).unwrap().collect();
                 if files.is_empty() {
                     return false;
@@ -254,7 +614,6 @@ pub trait MountableFilesystem: Sized {
 
                 #[cfg(test)]
                 { // Only allow .gitkeep in tests
-                    use std::ffi::OsStr;
                     match files.as_slice() {
                         &[Ok(ref file)] if file.file_name() == OsStr::new(".gitkeep") => return false,
                         _ => {}
@@ -263,6 +622,7 @@ pub trait MountableFilesystem: Sized {
 
                 true
             },
+            location => attached_by_device(location),
         }
     }
 }
@@ -280,3 +640,90 @@ pub trait MountableKind: Sized {
 
     fn from_mounted_parts(this: Self::This, mount: MountedFilesystem) -> Self;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::StagingConfig;
+    use crate::staging::StageableLocation;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_parses_mounts() {
+        let mounts = parse_mounts("\
+sysfs /sys sysfs rw,nosuid,nodev,noexec,relatime 0 0
+/dev/sda1 / ext4 rw,relatime 0 0
+# Backups
+/dev/sdb1 /media/pi/My\\040Passport vfat rw,relatime 0 0
+");
+        assert_eq!(mounts, vec![
+            (PathBuf::from("sysfs"), PathBuf::from("/sys")),
+            (PathBuf::from("/dev/sda1"), PathBuf::from("/")),
+            (PathBuf::from("/dev/sdb1"), PathBuf::from("/media/pi/My Passport")),
+        ]);
+    }
+
+    #[test]
+    fn test_resolves_fstab_specs() {
+        assert_eq!(resolve_fstab_spec("LABEL=STAGING"), PathBuf::from("/dev/disk/by-label/STAGING"));
+        assert_eq!(resolve_fstab_spec("UUID=1234-ABCD"), PathBuf::from("/dev/disk/by-uuid/1234-ABCD"));
+        assert_eq!(resolve_fstab_spec("/dev/sdb1"), PathBuf::from("/dev/sdb1"));
+    }
+
+    #[test]
+    fn test_mounts_existing_mountpoints() {
+        let dir = test_helpers::tempdir();
+        let staging = |path: PathBuf, mounter| StagingConfig {
+            location: MountableDeviceLocation::from_mountpoint(path),
+            mounter,
+        };
+
+        let mounted = MountableFilesystem::mount(staging(dir.path().to_path_buf(), None)).unwrap();
+        assert_eq!(mounted.relative_path(Path::new("file")), dir.path().join("file"));
+
+        assert!(MountableFilesystem::mount(staging(dir.path().join("missing"), None)).is_err());
+        assert!(MountableFilesystem::mount(staging(dir.path().to_path_buf(), Some(Mounter::Udisks))).is_err());
+    }
+
+    #[test]
+    fn test_unmounting_externally_mounted() {
+        let dir = test_helpers::tempdir();
+        let mut mount = MountedFilesystem::new_externally_mounted(dir.path().to_path_buf());
+        assert!(mount.eject().is_err());
+        mount.unmount().unwrap();
+    }
+
+    #[test]
+    fn test_checks_identity() {
+        let dir = test_helpers::tempdir();
+        let folder = dir.path().join(MOUNTABLE_DEVICE_FOLDER);
+        fs::create_dir(&folder).unwrap();
+        let mount = MountedFilesystem::new_externally_mounted(dir.path().to_path_buf());
+        // Nothing to say that it isn't ours
+        check_identity(&mount, "front").unwrap();
+
+        DeviceIdentity::new("front".into()).write(&folder).unwrap();
+        assert_eq!(DeviceIdentity::from_path(&folder).unwrap(), Some(DeviceIdentity::new("front".into())));
+        check_identity(&mount, "front").unwrap();
+        assert!(check_identity(&mount, "back").is_err());
+
+        // Bootstrapped devices are used from inside the archiver folder, which is where they keep
+        // their identity too.
+        let mount = MountedFilesystem::new_externally_mounted(dir.path().to_path_buf()).into_archiver_folder().unwrap();
+        assert_eq!(mount.path(), folder.as_path());
+        check_identity(&mount, "front").unwrap();
+        assert!(check_identity(&mount, "back").is_err());
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_finds_devices_for_locations() {
+        assert_eq!(device_for_location(&MountableDeviceLocation::from_identifier("UUID=1234-ABCD")),
+                   Some(PathBuf::from("/dev/disk/by-uuid/1234-ABCD")));
+        assert_eq!(device_for_location(&MountableDeviceLocation::from_identifier("PARTUUID=0a3b-01")),
+                   Some(PathBuf::from("/dev/disk/by-partuuid/0a3b-01")));
+        assert_eq!(device_for_location(&MountableDeviceLocation::from_identifier("NO NAME")),
+                   Some(PathBuf::from("/dev/disk/by-label/NO NAME")));
+        assert_eq!(device_for_location(&MountableDeviceLocation::from_mountpoint("/mnt".into())), None);
+    }
+}
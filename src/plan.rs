@@ -0,0 +1,310 @@
+use std::fmt;
+use std::path::PathBuf;
+
+use failure::Error;
+use serde::Serialize;
+use serde_json;
+
+use crate::ctx::Ctx;
+use crate::formatting::human_readable_size;
+use crate::mountable::Mountable;
+use crate::staging::{Disposal, StageableLocation, UploadDescriptor};
+use crate::storage::MaybeStorageAdaptor;
+
+/// Everything a run would do, worked out without staging, uploading or deleting anything.
+#[derive(Debug, Default, Serialize)]
+pub struct Plan {
+    pub problems: Vec<PlanProblem>,
+    pub devices: Vec<DevicePlan>,
+    pub staged: Vec<PlannedUpload>,
+    pub backends: Vec<BackendPlan>,
+}
+
+/// Something that got in the way of working out part of the plan.
+#[derive(Debug, Serialize)]
+pub struct PlanProblem {
+    pub source: String,
+    pub problem: String,
+}
+
+/// What would be staged from an attached device.
+#[derive(Debug, Serialize)]
+pub struct DevicePlan {
+    pub name: String,
+    pub kind: &'static str,
+    pub files: Vec<PlannedUpload>,
+}
+
+/// A file that would be uploaded, and for files still on a device, what would become of the
+/// original once it's been staged.
+#[derive(Debug, Serialize)]
+pub struct PlannedUpload {
+    pub remote_path: PathBuf,
+    pub size: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub disposal: Option<Disposal>,
+}
+
+/// What would be uploaded to a backend.
+#[derive(Debug, Serialize)]
+pub struct BackendPlan {
+    pub name: String,
+    pub upload: Vec<PathBuf>,
+    pub already_uploaded: Vec<PathBuf>,
+    /// Files still on a device that weren't hashed, so we can't tell whether this backend has them.
+    pub unknown: Vec<PathBuf>,
+    pub bytes: u64,
+}
+
+impl PlannedUpload {
+    fn new(descriptor: &UploadDescriptor, disposal: Option<Disposal>) -> PlannedUpload {
+        PlannedUpload {
+            remote_path: descriptor.remote_path(),
+            size: descriptor.size,
+            disposal,
+        }
+    }
+}
+
+/// Work out what a run would do: what would be staged from each attached device, what's already
+/// waiting in staging, and what each backend would be sent.
+///
+/// Files on devices are only read to find their content hashes if `hash` is set. Otherwise whether
+/// a backend already has them is unknown.
+///
+/// Like a run, nothing that goes wrong stops us planning the rest, it's recorded as a problem.
+pub fn plan(ctx: &Ctx, hash: bool) -> Plan {
+    let mut plan = Plan::default();
+    let mut descriptors = vec![];
+    let mut unhashed = vec![];
+
+    for kind in ctx.registry().kinds() {
+        let attached = match kind.attached(ctx) {
+            Ok(attached) => attached,
+            Err(e) => {
+                plan.problem(format!("{} devices", kind.name()), format!("Couldn't look for devices: {}", e));
+                continue;
+            },
+        };
+        for device in attached {
+            let name = device.name().to_string();
+            let kind = device.kind();
+            match device.plan(hash) {
+                Ok(files) => {
+                    let files = files.into_iter().map(|file| {
+                        let upload = PlannedUpload::new(&file.descriptor, Some(file.disposal));
+                        if file.hashed {
+                            descriptors.push(file.descriptor);
+                        } else {
+                            unhashed.push(file.descriptor);
+                        }
+                        upload
+                    }).collect();
+                    plan.devices.push(DevicePlan { name, kind, files });
+                },
+                Err(e) => plan.problem(format!("device {}", name), format!("Couldn't plan: {}", e)),
+            }
+        }
+    }
+
+    match ctx.staging().mount() {
+        Ok(staging) => match plan_staged(&staging) {
+            Ok(staged) => {
+                plan.staged = staged.iter().map(|desc| PlannedUpload::new(desc, None)).collect();
+                descriptors.extend(staged);
+            },
+            Err(e) => plan.problem("staging".into(), format!("Couldn't read staged files: {}", e)),
+        },
+        Err(e) => plan.problem("staging".into(), format!("Couldn't mount: {}", e)),
+    }
+
+    plan_backends(&mut plan, &descriptors, &unhashed, &ctx.cfg.backends());
+
+    plan
+}
+
+/// The manifests of everything waiting in `staging`.
+fn plan_staged(staging: &dyn StageableLocation) -> Result<Vec<UploadDescriptor>, Error> {
+    Ok(staging.staged_files()?.into_iter().map(|(_, desc)| desc).collect())
+}
+
+/// Ask each of `adaptors` which of `descriptors` it already has. There's no asking about
+/// `unhashed`, as backends need the content hash to tell.
+fn plan_backends(
+    plan: &mut Plan,
+    descriptors: &[UploadDescriptor],
+    unhashed: &[UploadDescriptor],
+    adaptors: &[MaybeStorageAdaptor],
+) {
+    for ad in adaptors {
+        let adaptor = match ad.adaptor() {
+            Ok(adaptor) => adaptor,
+            Err(e) => {
+                plan.problem(format!("backend {}", ad.name()), format!("Couldn't set up: {}", e));
+                continue;
+            },
+        };
+
+        let mut backend = BackendPlan {
+            name: ad.name().to_string(),
+            upload: vec![],
+            already_uploaded: vec![],
+            unknown: unhashed.iter().map(|desc| desc.remote_path()).collect(),
+            bytes: 0,
+        };
+        for desc in descriptors {
+            if adaptor.already_uploaded(desc) {
+                backend.already_uploaded.push(desc.remote_path());
+            } else {
+                backend.upload.push(desc.remote_path());
+                backend.bytes += desc.size;
+            }
+        }
+        plan.backends.push(backend);
+    }
+}
+
+impl Plan {
+    fn problem(&mut self, source: String, problem: String) {
+        error!("{}: {}", &source, &problem);
+        self.problems.push(PlanProblem { source, problem });
+    }
+
+    pub fn to_json(&self) -> Result<String, Error> {
+        Ok(serde_json::to_string_pretty(self)?)
+    }
+}
+
+fn size(bytes: u64) -> String {
+    format!("{}b", human_readable_size(bytes as usize))
+}
+
+fn disposal(disposal: Disposal) -> &'static str {
+    match disposal {
+        Disposal::Delete => "delete",
+        Disposal::Archive => "archive",
+        Disposal::Keep => "keep",
+    }
+}
+
+impl fmt::Display for Plan {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Dry run, nothing will be staged, uploaded or deleted")?;
+
+        if !self.problems.is_empty() {
+            writeln!(f, "\nProblems:")?;
+            for problem in &self.problems {
+                writeln!(f, "  {}: {}", &problem.source, &problem.problem)?;
+            }
+        }
+
+        writeln!(f, "\nTo stage:")?;
+        if self.devices.is_empty() {
+            writeln!(f, "  No devices attached")?;
+        }
+        for device in &self.devices {
+            let bytes = device.files.iter().map(|file| file.size).sum();
+            writeln!(f, "  {} ({}): {} files, {}", &device.name, device.kind, device.files.len(), size(bytes))?;
+            for file in &device.files {
+                let then = file.disposal.map(disposal).unwrap_or("keep");
+                writeln!(f, "    {} ({}, then {})", file.remote_path.display(), size(file.size), then)?;
+            }
+        }
+
+        writeln!(f, "\nAlready staged: {} files", self.staged.len())?;
+        for file in &self.staged {
+            writeln!(f, "  {} ({})", file.remote_path.display(), size(file.size))?;
+        }
+
+        writeln!(f, "\nTo upload:")?;
+        if self.backends.is_empty() {
+            writeln!(f, "  No backends configured")?;
+        }
+        for backend in &self.backends {
+            writeln!(f, "  {}: {} files, {} ({} already uploaded)",
+                     &backend.name, backend.upload.len(), size(backend.bytes), backend.already_uploaded.len())?;
+            for path in &backend.upload {
+                writeln!(f, "    {}", path.display())?;
+            }
+            for path in &backend.unknown {
+                writeln!(f, "    {} (unknown, pass --hash to check)", path.display())?;
+            }
+        }
+
+        writeln!(f, "\nTo delete from devices:")?;
+        for device in &self.devices {
+            for file in &device.files {
+                if file.disposal == Some(Disposal::Delete) {
+                    writeln!(f, "  {}: {}", &device.name, file.remote_path.display())?;
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{StagedReader, StorageAdaptor, StorageStatus};
+    use crate::test_helpers;
+
+    /// A backend that already has everything from one device.
+    #[derive(Debug)]
+    struct PartialStorageAdaptor {
+        has_device: &'static str,
+    }
+
+    impl StorageAdaptor<StagedReader> for PartialStorageAdaptor {
+        fn upload(&self, _: StagedReader, _: &UploadDescriptor) -> Result<StorageStatus, Error> {
+            panic!("Planning shouldn't upload anything");
+        }
+
+        fn already_uploaded(&self, manifest: &UploadDescriptor) -> bool {
+            manifest.device_name == self.has_device
+        }
+
+        fn name(&self) -> String {
+            "partial".to_string()
+        }
+    }
+
+    #[test]
+    fn test_plans_uploads_per_backend() {
+        let staging = test_helpers::staged_data(1).expect("Couldn't create staging data");
+        let mut descriptors = plan_staged(&staging).unwrap();
+        assert_eq!(descriptors.len(), 1);
+        let mut other = UploadDescriptor::test_descriptor();
+        other.device_name = "other".into();
+        descriptors.push(other);
+
+        let adaptors = vec![
+            MaybeStorageAdaptor::Ok(PartialStorageAdaptor { has_device: "dummy" }),
+            MaybeStorageAdaptor::Err("broken".into(), format_err!("No credentials")),
+        ];
+        let mut unhashed = UploadDescriptor::test_descriptor();
+        unhashed.device_name = "unhashed".into();
+        let mut plan = Plan::default();
+        plan_backends(&mut plan, &descriptors, &[unhashed], &adaptors);
+
+        assert_eq!(plan.backends.len(), 1);
+        let backend = &plan.backends[0];
+        assert_eq!(backend.upload, vec![PathBuf::from("/18-08-26/other/14-30-00.mp4")]);
+        assert_eq!(backend.already_uploaded.len(), 1);
+        assert_eq!(backend.unknown, vec![PathBuf::from("/18-08-26/unhashed/14-30-00.mp4")]);
+        assert_eq!(backend.bytes, 1024);
+
+        assert_eq!(plan.problems.len(), 1);
+        assert_eq!(plan.problems[0].source, "backend broken");
+
+        // Planning must not have touched staging
+        assert_eq!(staging.staged_files().unwrap().len(), 1);
+
+        let text = plan.to_string();
+        assert!(text.contains("partial: 1 files, 1kb (1 already uploaded)"));
+        assert!(text.contains("/18-08-26/unhashed/14-30-00.mp4 (unknown, pass --hash to check)"));
+        assert!(text.contains("backend broken: Couldn't set up: No credentials"));
+        assert!(plan.to_json().unwrap().contains("\"already_uploaded\""));
+    }
+}
@@ -0,0 +1,533 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use chrono;
+use chrono::prelude::*;
+use libc;
+use log::{Log, Metadata, Record};
+use serde::Serialize;
+use serde_json;
+
+use crate::formatting::{human_readable_size, human_readable_time};
+
+/// The most often a single transfer will report how it's getting on.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(500);
+
+lazy_static! {
+    static ref SINKS: Sinks = Sinks::default();
+    /// What's drawn on the terminal, shared with the logger so that it can write above it.
+    static ref TTY: Mutex<TtyState> = Mutex::new(TtyState::default());
+}
+
+/// Whether progress events are being written to stdout, which leaves it for nothing else.
+static JSON_ON_STDOUT: AtomicBool = AtomicBool::new(false);
+
+/// Which half of a run a transfer belongs to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Staging,
+    Uploading,
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Phase::Staging => write!(fmt, "staging"),
+            Phase::Uploading => write!(fmt, "uploading"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventKind {
+    Started,
+    Progress,
+    Finished,
+    Failed,
+}
+
+/// Something that happened to a transfer. These are what sinks are given, and what the JSON
+/// event stream is made of, one per line.
+#[derive(Debug, Clone, Serialize)]
+pub struct ProgressEvent {
+    pub event: EventKind,
+    pub time: DateTime<Local>,
+    pub phase: Phase,
+    /// The device being staged from, or the backend being uploaded to.
+    pub source: String,
+    pub file: String,
+    pub transferred: u64,
+    pub size: u64,
+    pub bytes_per_second: Option<u64>,
+    pub eta_seconds: Option<u64>,
+}
+
+/// Somewhere for progress to go, eg a terminal or the daemon's state file.
+pub trait ProgressSink: Send + Sync {
+    fn event(&self, event: &ProgressEvent);
+}
+
+/// Everywhere that progress is going. There's one of these for the whole process, which is what
+/// `install` adds to, but tests keep their own so that they don't turn progress on for everything
+/// else.
+#[derive(Default)]
+struct Sinks(RwLock<Vec<Box<dyn ProgressSink>>>);
+
+impl fmt::Debug for Sinks {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_tuple("Sinks")
+            .field(&format!("{} sinks", self.0.read().expect("Progress sinks").len()))
+            .finish()
+    }
+}
+
+impl Sinks {
+    fn install<S: ProgressSink + 'static>(&self, sink: S) {
+        self.0.write().expect("Progress sinks").push(Box::new(sink));
+    }
+
+    fn is_enabled(&self) -> bool {
+        !self.0.read().expect("Progress sinks").is_empty()
+    }
+
+    fn emit(&self, event: &ProgressEvent) {
+        for sink in self.0.read().expect("Progress sinks").iter() {
+            sink.event(event);
+        }
+    }
+
+    fn start(&'static self, phase: Phase, source: &str, file: &str, size: u64) -> Transfer {
+        let now = Instant::now();
+        let transfer = Transfer {
+            sinks: self,
+            phase,
+            source: source.to_string(),
+            file: file.to_string(),
+            size,
+            transferred: 0,
+            started: now,
+            last_event: now,
+            finished: false,
+        };
+        self.emit(&transfer.event(EventKind::Started));
+        transfer
+    }
+}
+
+/// Send progress to `sink`, as well as anywhere it was already going. Like logging, this is set
+/// up once by whatever binary is running.
+pub fn install<S: ProgressSink + 'static>(sink: S) {
+    SINKS.install(sink);
+}
+
+/// Write progress to stdout as JSON events. Anything else meant for the person running us has to go
+/// to `report_output` instead, so that the event stream stays parseable.
+pub fn install_json_stdout() {
+    JSON_ON_STDOUT.store(true, Ordering::SeqCst);
+    install(JsonProgress::new(io::stdout()));
+}
+
+/// Where to write reports meant for the person running us: stdout, unless progress events are
+/// going there, in which case stderr.
+pub fn report_output() -> Box<dyn Write> {
+    if JSON_ON_STDOUT.load(Ordering::SeqCst) {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::stdout())
+    }
+}
+
+/// Whether anything is listening for progress, so that work that only exists to report it can be
+/// skipped.
+pub fn is_enabled() -> bool {
+    SINKS.is_enabled()
+}
+
+/// Start tracking the transfer of `file`, from or to `source`.
+pub fn start(phase: Phase, source: &str, file: &str, size: u64) -> Transfer {
+    SINKS.start(phase, source, file, size)
+}
+
+/// A single file being staged or uploaded. If it's dropped without being finished, it's reported
+/// as having failed.
+#[derive(Debug)]
+pub struct Transfer {
+    sinks: &'static Sinks,
+    phase: Phase,
+    source: String,
+    file: String,
+    size: u64,
+    transferred: u64,
+    started: Instant,
+    last_event: Instant,
+    finished: bool,
+}
+
+impl Transfer {
+    /// Wrap `reader` so that everything read from it counts towards this transfer.
+    pub fn reader<R: Read>(&mut self, reader: R) -> ProgressReader<'_, R> {
+        ProgressReader {
+            reader,
+            transfer: self,
+        }
+    }
+
+    pub fn add(&mut self, bytes: u64) {
+        let transferred = self.transferred + bytes;
+        self.update(transferred);
+    }
+
+    /// Record that `transferred` bytes have been moved so far.
+    pub fn update(&mut self, transferred: u64) {
+        self.transferred = transferred;
+        if self.last_event.elapsed() >= PROGRESS_INTERVAL {
+            self.last_event = Instant::now();
+            self.sinks.emit(&self.event(EventKind::Progress));
+        }
+    }
+
+    pub fn finish(mut self, succeeded: bool) {
+        self.finished = true;
+        let kind = if succeeded { EventKind::Finished } else { EventKind::Failed };
+        self.sinks.emit(&self.event(kind));
+    }
+
+    fn event(&self, event: EventKind) -> ProgressEvent {
+        let millis = self.started.elapsed().as_millis() as u64;
+        let bytes_per_second = match (millis, self.transferred) {
+            (0, _) | (_, 0) => None,
+            (millis, transferred) => Some(transferred * 1000 / millis),
+        };
+        let eta_seconds = bytes_per_second
+            .filter(|_| event == EventKind::Progress)
+            .map(|rate| self.size.saturating_sub(self.transferred) / rate);
+        ProgressEvent {
+            event,
+            time: Local::now(),
+            phase: self.phase,
+            source: self.source.clone(),
+            file: self.file.clone(),
+            transferred: self.transferred,
+            size: self.size,
+            bytes_per_second,
+            eta_seconds,
+        }
+    }
+}
+
+impl Drop for Transfer {
+    fn drop(&mut self) {
+        if !self.finished {
+            self.sinks.emit(&self.event(EventKind::Failed));
+        }
+    }
+}
+
+/// A reader that counts what's read from it towards a `Transfer`.
+#[derive(Debug)]
+pub struct ProgressReader<'a, R> {
+    reader: R,
+    transfer: &'a mut Transfer,
+}
+
+impl<'a, R: Read> Read for ProgressReader<'a, R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.reader.read(buf)?;
+        self.transfer.add(read as u64);
+        Ok(read)
+    }
+}
+
+/// Whether stderr, where the progress display goes, is a terminal.
+pub fn stderr_is_tty() -> bool {
+    unsafe { libc::isatty(libc::STDERR_FILENO) == 1 }
+}
+
+/// Writes each event to `writer` as a line of JSON.
+#[derive(Debug)]
+pub struct JsonProgress<W> {
+    writer: Mutex<W>,
+}
+
+impl<W: Write + Send> JsonProgress<W> {
+    pub fn new(writer: W) -> JsonProgress<W> {
+        JsonProgress {
+            writer: Mutex::new(writer),
+        }
+    }
+}
+
+impl<W: Write + Send> ProgressSink for JsonProgress<W> {
+    fn event(&self, event: &ProgressEvent) {
+        let mut writer = self.writer.lock().expect("Progress writer");
+        let written = serde_json::to_writer(&mut *writer, event)
+            .map_err(io::Error::from)
+            .and_then(|_| writer.write_all(b"\n"))
+            .and_then(|_| writer.flush());
+        if let Err(e) = written {
+            error!("Couldn't write progress: {:?}", e);
+        }
+    }
+}
+
+/// Draws a line on the terminal for each device and backend, showing the file it's working on and
+/// how much it's done so far.
+///
+/// Anything else written to stderr while it's drawn will be drawn over, so logging needs to go
+/// through a `TtyLogger`.
+#[derive(Debug, Default)]
+pub struct TtyProgress;
+
+impl TtyProgress {
+    pub fn new() -> TtyProgress {
+        Default::default()
+    }
+}
+
+#[derive(Debug, Default)]
+struct TtyState {
+    sources: BTreeMap<(Phase, String), SourceProgress>,
+    lines_drawn: usize,
+}
+
+/// How one device or backend is getting on.
+#[derive(Debug, Default)]
+struct SourceProgress {
+    files: usize,
+    bytes: u64,
+    current: Option<ProgressEvent>,
+}
+
+impl SourceProgress {
+    fn record(&mut self, event: &ProgressEvent) {
+        match event.event {
+            EventKind::Started | EventKind::Progress => self.current = Some(event.clone()),
+            EventKind::Finished => {
+                self.files += 1;
+                self.bytes += event.transferred;
+                self.current = None;
+            },
+            EventKind::Failed => self.current = None,
+        }
+    }
+
+    fn render(&self, phase: Phase, name: &str) -> String {
+        let done = match self.files {
+            1 => format!("1 file, {}b done", human_readable_size(self.bytes as usize)),
+            n => format!("{} files, {}b done", n, human_readable_size(self.bytes as usize)),
+        };
+        let current = match &self.current {
+            Some(current) => current,
+            None => return format!("{} {}: {}", phase, name, done),
+        };
+
+        let percent = match current.size {
+            0 => 100,
+            size => current.transferred * 100 / size,
+        };
+        let mut line = format!(
+            "{} {}: {} {}b/{}b ({}%)",
+            phase,
+            name,
+            current.file,
+            human_readable_size(current.transferred as usize),
+            human_readable_size(current.size as usize),
+            percent,
+        );
+        if let Some(rate) = current.bytes_per_second {
+            line.push_str(&format!(" at {}b/s", human_readable_size(rate as usize)));
+        }
+        if let Some(eta) = current.eta_seconds {
+            line.push_str(&format!(", ETA {}", human_readable_time(chrono::Duration::seconds(eta as i64))));
+        }
+        format!("{} [{}]", line, done)
+    }
+}
+
+impl TtyState {
+    fn erase(&self, frame: &mut String) {
+        for _ in 0..self.lines_drawn {
+            // Up a line, and clear it
+            frame.push_str("\x1b[1A\x1b[2K");
+        }
+    }
+
+    /// Remove everything we drew, so that something else can be written in its place.
+    fn clear<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let mut frame = String::new();
+        self.erase(&mut frame);
+        self.lines_drawn = 0;
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+
+    /// Redraw every line, replacing whatever we drew last time.
+    fn draw<W: Write>(&mut self, out: &mut W) -> io::Result<()> {
+        let mut frame = String::new();
+        self.erase(&mut frame);
+        for ((phase, name), source) in &self.sources {
+            frame.push_str(&source.render(*phase, name));
+            frame.push('\n');
+        }
+        self.lines_drawn = self.sources.len();
+        out.write_all(frame.as_bytes())?;
+        out.flush()
+    }
+}
+
+impl ProgressSink for TtyProgress {
+    fn event(&self, event: &ProgressEvent) {
+        let mut state = TTY.lock().expect("Progress display");
+        state
+            .sources
+            .entry((event.phase, event.source.clone()))
+            .or_insert_with(Default::default)
+            .record(event);
+        // There's nothing useful to do if the terminal has gone away.
+        let _ = state.draw(&mut io::stderr());
+    }
+}
+
+/// Wraps a logger that writes to stderr, so that each line it logs is written above the progress
+/// display instead of being drawn over by it.
+#[derive(Debug)]
+pub struct TtyLogger<L> {
+    inner: L,
+}
+
+impl<L: Log> TtyLogger<L> {
+    pub fn new(inner: L) -> TtyLogger<L> {
+        TtyLogger { inner }
+    }
+}
+
+impl<L: Log> Log for TtyLogger<L> {
+    fn enabled(&self, metadata: &Metadata<'_>) -> bool {
+        self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record<'_>) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        let mut state = TTY.lock().expect("Progress display");
+        if state.lines_drawn == 0 {
+            return self.inner.log(record);
+        }
+        let _ = state.clear(&mut io::stderr());
+        self.inner.log(record);
+        let _ = state.draw(&mut io::stderr());
+    }
+
+    fn flush(&self) {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::sync::Arc;
+    use std::thread;
+
+    #[derive(Clone, Default)]
+    struct Recorder {
+        events: Arc<Mutex<Vec<ProgressEvent>>>,
+    }
+
+    impl ProgressSink for Recorder {
+        fn event(&self, event: &ProgressEvent) {
+            self.events.lock().unwrap().push(event.clone());
+        }
+    }
+
+    fn event(event: EventKind, transferred: u64, size: u64) -> ProgressEvent {
+        ProgressEvent {
+            event,
+            time: Local::now(),
+            phase: Phase::Uploading,
+            source: "dropbox".to_string(),
+            file: "/18-08-24/gopro/09-55-30.mp4".to_string(),
+            transferred,
+            size,
+            bytes_per_second: Some(2 * 1024 * 1024),
+            eta_seconds: Some(75),
+        }
+    }
+
+    #[test]
+    fn test_tracks_transfers() {
+        // Installing into the global sinks would turn progress on for every other test
+        let sinks: &'static Sinks = Box::leak(Box::new(Sinks::default()));
+        let recorder = Recorder::default();
+        assert!(!sinks.is_enabled());
+        sinks.install(recorder.clone());
+        assert!(sinks.is_enabled());
+
+        let mut transfer = sinks.start(Phase::Staging, "test_tracks_transfers", "09-55-30.mp4", 16);
+        let mut buf = vec![];
+        transfer.reader(Cursor::new(vec![0; 8])).read_to_end(&mut buf).unwrap();
+        thread::sleep(PROGRESS_INTERVAL);
+        transfer.add(8);
+        transfer.finish(true);
+
+        // Dropping one before it's finished counts as failing
+        drop(sinks.start(Phase::Staging, "test_tracks_transfers", "09-55-31.mp4", 16));
+
+        let events = recorder.events.lock().unwrap();
+        let summary: Vec<_> = events.iter().map(|e| (e.event, &e.file[..], e.transferred)).collect();
+        assert_eq!(summary, vec![
+            (EventKind::Started, "09-55-30.mp4", 0),
+            (EventKind::Progress, "09-55-30.mp4", 16),
+            (EventKind::Finished, "09-55-30.mp4", 16),
+            (EventKind::Started, "09-55-31.mp4", 0),
+            (EventKind::Failed, "09-55-31.mp4", 0),
+        ]);
+        assert!(events[1].bytes_per_second.is_some());
+        assert_eq!(events[1].eta_seconds, Some(0));
+    }
+
+    #[test]
+    fn test_renders_sources() {
+        let mut source = SourceProgress::default();
+        source.record(&event(EventKind::Finished, 4096, 4096));
+        source.record(&event(EventKind::Progress, 40 * 1024 * 1024, 160 * 1024 * 1024));
+        assert_eq!(
+            source.render(Phase::Uploading, "dropbox"),
+            "uploading dropbox: /18-08-24/gopro/09-55-30.mp4 40mb/160mb (25%) at 2.0mb/s, ETA 1m15s [1 file, 4kb done]",
+        );
+
+        source.record(&event(EventKind::Failed, 40 * 1024 * 1024, 160 * 1024 * 1024));
+        assert_eq!(source.render(Phase::Uploading, "dropbox"), "uploading dropbox: 1 file, 4kb done");
+    }
+
+    #[test]
+    fn test_redraws_in_place() {
+        let mut state = TtyState::default();
+        state.sources.insert((Phase::Staging, "front".to_string()), SourceProgress::default());
+        let mut out = vec![];
+        state.draw(&mut out).unwrap();
+        state.draw(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "staging front: 0 files, 0b done\n\x1b[1A\x1b[2Kstaging front: 0 files, 0b done\n",
+        );
+
+        // Clearing it makes room for a log line, which the next frame is drawn below
+        let mut out = vec![];
+        state.clear(&mut out).unwrap();
+        out.extend_from_slice(b"INFO archiver: Staging to \"/srv/staging\"\n");
+        state.draw(&mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "\x1b[1A\x1b[2KINFO archiver: Staging to \"/srv/staging\"\nstaging front: 0 files, 0b done\n",
+        );
+    }
+}
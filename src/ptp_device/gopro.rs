@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use crate::config::GoproConfig;
+
+use ptp;
+use regex::Regex;
+
+use super::{PtpFile, PtpObjectFormat, PtpSession};
+
+pub(super) const GOPRO_VENDOR: u16 = 0x2672;
+pub(super) const GOPRO_MANUFACTURER: &str = "GoPro";
+
+#[repr(u16)]
+#[derive(Eq, PartialEq, Debug, Hash, Clone)]
+pub enum GoproKind {
+    Hero4Silver,
+    Hero2018,
+    Hero5Black,
+    UnknownGopro(u16),
+}
+
+impl GoproKind {
+    /// The kind of GoPro with USB product id `ty`. Models we don't know about are still GoPros, and
+    /// still lay out their files like one.
+    pub(super) fn from_u16(ty: u16) -> GoproKind {
+        use self::GoproKind::*;
+        match ty {
+            0x000d => Hero4Silver,
+            0x002d => Hero2018,
+            0x0027 => Hero5Black,
+            _ => UnknownGopro(ty),
+        }
+    }
+}
+
+/// The GoPro specific behaviour layered on top of a generic PTP device.
+#[derive(Eq, PartialEq, Debug, Hash, Clone)]
+pub struct GoproQuirks {
+    pub kind: GoproKind,
+    /// Whether to archive photos as well as videos.
+    pub photos: bool,
+    /// Whether to archive the low resolution proxies and thumbnails written alongside each video.
+    pub proxies: bool,
+}
+
+impl GoproQuirks {
+    pub(super) fn new(kind: GoproKind) -> GoproQuirks {
+        GoproQuirks {
+            kind,
+            photos: false,
+            proxies: false,
+        }
+    }
+
+    /// Apply the options from this gopro's configuration.
+    pub(super) fn configure(&mut self, cfg: &GoproConfig) {
+        self.photos = cfg.photos;
+        self.proxies = cfg.proxies;
+    }
+
+    /// Sort the objects found on a GoPro into the files we want to stage.
+    ///
+    /// Chapters are gathered up by recording, so that we can give every chapter of a recording
+    /// the same capture time and stage them alongside one another.
+    pub(super) fn files<'c>(
+        &self,
+        session: &Rc<Mutex<PtpSession<'c>>>,
+        objects: Vec<(u32, ptp::PtpObjectInfo)>,
+    ) -> Vec<PtpFile<'c>> {
+        let mut out = vec![];
+        let mut recordings: BTreeMap<GoproRecording, Vec<_>> = BTreeMap::new();
+
+        for (filehandle, object) in objects {
+            let name = GoproFilename::parse(&object.Filename);
+            let extension = match &name {
+                Some(name) => name.extension.clone(),
+                None => object.Filename.rsplit('.').next().unwrap_or("").to_lowercase(),
+            };
+            let kind = match GoproObjectKind::from_object(object.ObjectFormat, &extension) {
+                Some(GoproObjectKind::Photo) if !self.photos => continue,
+                Some(GoproObjectKind::Sidecar) if !self.proxies => continue,
+                Some(kind) => kind,
+                None => {
+                    trace!("Skipping {:?} with format {:#x}", &object.Filename, object.ObjectFormat);
+                    continue;
+                },
+            };
+            let extension = if extension.is_empty() {
+                kind.default_extension().to_string()
+            } else {
+                extension
+            };
+
+            match name {
+                Some(name) => {
+                    recordings
+                        .entry(name.recording)
+                        .or_insert_with(|| vec![])
+                        .push((name.chapter, kind, extension, filehandle, object));
+                },
+                None => {
+                    warn!("Couldn't parse a recording from {:?}, treating it as its own recording", &object.Filename);
+                    let file = PtpFile::new(session, filehandle, &object, object.CaptureDate.clone(), extension);
+                    trace!("Adding {:?} to the plan", &file);
+                    out.push(file)
+                },
+            }
+        }
+
+        for (recording, mut objects) in recordings {
+            // Sort sidecars in behind the chapter they belong to, so that they're staged together.
+            objects.sort_by_key(|&(chapter, kind, _, _, _)| (chapter, kind == GoproObjectKind::Sidecar));
+
+            let chapters: Vec<u16> = objects
+                .iter()
+                .filter(|&&(_, kind, _, _, _)| kind != GoproObjectKind::Sidecar)
+                .map(|&(chapter, _, _, _, _)| chapter)
+                .collect();
+            let chaptered = chapters.len() > 1;
+            let capturedate = objects
+                .iter()
+                .find(|&&(_, kind, _, _, _)| kind != GoproObjectKind::Sidecar)
+                .unwrap_or(&objects[0])
+                .4
+                .CaptureDate
+                .clone();
+            if chaptered {
+                info!("{:?} is split into {} chapters", recording, chapters.len());
+            }
+
+            for (chapter, _, extension, filehandle, object) in objects {
+                let mut file = PtpFile::new(session, filehandle, &object, capturedate.clone(), extension);
+                if chaptered {
+                    file.chapter = chapters.iter().position(|&c| c == chapter).map(|i| i as u16 + 1);
+                }
+                trace!("Adding {:?} to the plan", &file);
+                out.push(file)
+            }
+        }
+
+        out
+    }
+}
+
+/// Which recording a file on a GoPro belongs to.
+#[derive(Debug, Eq, PartialEq, Ord, PartialOrd, Clone, Copy)]
+enum GoproRecording {
+    /// A video or single photo, identified by its file number.
+    File(u16),
+    /// A timelapse or burst of photos, identified by its group number.
+    PhotoGroup(u16),
+}
+
+/// The parts of a filename that a GoPro assigns to the objects it creates.
+///
+/// Long recordings are split into chapters which share a file number. Older cameras name the
+/// first chapter `GOPR0042.MP4` and the ones after it `GP010042.MP4`, `GP020042.MP4` and so on,
+/// while newer cameras name every chapter `GH010042.MP4` (or `GX` for HEVC). Sidecar files share
+/// the name of the chapter they belong to, apart from newer cameras naming their proxies
+/// `GL010042.LRV`.
+///
+/// Timelapse and burst photos are named `G0010042.JPG`, where `001` is the group and `0042` is
+/// the frame, so we treat each frame as a chapter of the group.
+#[derive(Debug, Eq, PartialEq, Clone)]
+struct GoproFilename {
+    recording: GoproRecording,
+    chapter: u16,
+    extension: String,
+}
+
+impl GoproFilename {
+    fn parse(filename: &str) -> Option<GoproFilename> {
+        lazy_static! {
+            static ref FIRST_CHAPTER: Regex = Regex::new(r"(?i)^GOPR(?P<file>\d{4})\.(?P<ext>\w+)$")
+                .expect("Failed to compile regex");
+            static ref CHAPTER: Regex = Regex::new(r"(?i)^G[PHXL](?P<chapter>\d{2})(?P<file>\d{4})\.(?P<ext>\w+)$")
+                .expect("Failed to compile regex");
+            static ref PHOTO_GROUP: Regex = Regex::new(r"(?i)^G(?P<group>\d{3})(?P<frame>\d{4})\.(?P<ext>\w+)$")
+                .expect("Failed to compile regex");
+        }
+
+        if let Some(captures) = FIRST_CHAPTER.captures(filename) {
+            return Some(GoproFilename {
+                recording: GoproRecording::File(captures["file"].parse().ok()?),
+                chapter: 0,
+                extension: captures["ext"].to_lowercase(),
+            });
+        }
+
+        if let Some(captures) = CHAPTER.captures(filename) {
+            return Some(GoproFilename {
+                recording: GoproRecording::File(captures["file"].parse().ok()?),
+                chapter: captures["chapter"].parse().ok()?,
+                extension: captures["ext"].to_lowercase(),
+            });
+        }
+
+        if let Some(captures) = PHOTO_GROUP.captures(filename) {
+            return Some(GoproFilename {
+                recording: GoproRecording::PhotoGroup(captures["group"].parse().ok()?),
+                chapter: captures["frame"].parse().ok()?,
+                extension: captures["ext"].to_lowercase(),
+            });
+        }
+
+        None
+    }
+}
+
+/// The kinds of object we know how to archive from a GoPro.
+#[derive(Debug, Eq, PartialEq, Clone, Copy)]
+enum GoproObjectKind {
+    Video,
+    Photo,
+    /// Low resolution proxies (`.LRV`) and thumbnails (`.THM`), which belong to a video.
+    Sidecar,
+}
+
+impl GoproObjectKind {
+    fn from_object(format: u16, extension: &str) -> Option<GoproObjectKind> {
+        match PtpObjectFormat::from_u16(format) {
+            Some(PtpObjectFormat::QuickTime) => Some(GoproObjectKind::Video),
+            Some(PtpObjectFormat::ExifJpeg) => Some(GoproObjectKind::Photo),
+            _ => match extension {
+                "lrv" | "thm" => Some(GoproObjectKind::Sidecar),
+                _ => None,
+            },
+        }
+    }
+
+    fn default_extension(&self) -> &'static str {
+        match self {
+            GoproObjectKind::Video => "mp4",
+            GoproObjectKind::Photo => "jpg",
+            GoproObjectKind::Sidecar => "lrv",
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_kinds_from_product_ids() {
+        assert_eq!(GoproKind::from_u16(0x0027), GoproKind::Hero5Black);
+        assert_eq!(GoproKind::from_u16(0x0049), GoproKind::UnknownGopro(0x0049));
+    }
+
+    #[test]
+    fn test_parses_first_chapter() {
+        assert_eq!(GoproFilename::parse("GOPR0042.MP4"), Some(GoproFilename {
+            recording: GoproRecording::File(42),
+            chapter: 0,
+            extension: "mp4".into(),
+        }));
+    }
+
+    #[test]
+    fn test_parses_subsequent_chapters() {
+        assert_eq!(GoproFilename::parse("GP010042.MP4"), Some(GoproFilename {
+            recording: GoproRecording::File(42),
+            chapter: 1,
+            extension: "mp4".into(),
+        }));
+        assert_eq!(GoproFilename::parse("GP120042.MP4"), Some(GoproFilename {
+            recording: GoproRecording::File(42),
+            chapter: 12,
+            extension: "mp4".into(),
+        }));
+    }
+
+    #[test]
+    fn test_parses_newer_chapters() {
+        assert_eq!(GoproFilename::parse("GH010666.MP4"), Some(GoproFilename {
+            recording: GoproRecording::File(666),
+            chapter: 1,
+            extension: "mp4".into(),
+        }));
+        assert_eq!(GoproFilename::parse("GX020666.MP4"), Some(GoproFilename {
+            recording: GoproRecording::File(666),
+            chapter: 2,
+            extension: "mp4".into(),
+        }));
+    }
+
+    #[test]
+    fn test_parses_sidecars() {
+        assert_eq!(GoproFilename::parse("GOPR0042.THM"), Some(GoproFilename {
+            recording: GoproRecording::File(42),
+            chapter: 0,
+            extension: "thm".into(),
+        }));
+        assert_eq!(GoproFilename::parse("GL010666.LRV"), Some(GoproFilename {
+            recording: GoproRecording::File(666),
+            chapter: 1,
+            extension: "lrv".into(),
+        }));
+    }
+
+    #[test]
+    fn test_parses_photo_groups() {
+        assert_eq!(GoproFilename::parse("G0010043.JPG"), Some(GoproFilename {
+            recording: GoproRecording::PhotoGroup(1),
+            chapter: 43,
+            extension: "jpg".into(),
+        }));
+    }
+
+    #[test]
+    fn test_classifies_objects() {
+        assert_eq!(GoproObjectKind::from_object(0x300d, "mp4"), Some(GoproObjectKind::Video));
+        assert_eq!(GoproObjectKind::from_object(0x3801, "jpg"), Some(GoproObjectKind::Photo));
+        assert_eq!(GoproObjectKind::from_object(0x3000, "lrv"), Some(GoproObjectKind::Sidecar));
+        assert_eq!(GoproObjectKind::from_object(0x3000, "thm"), Some(GoproObjectKind::Sidecar));
+        assert_eq!(GoproObjectKind::from_object(0x3001, ""), None);
+        assert_eq!(GoproObjectKind::from_object(0x3000, "txt"), None);
+    }
+
+    #[test]
+    fn test_rejects_other_filenames() {
+        assert_eq!(GoproFilename::parse("GOPRO666.mp4"), None);
+        assert_eq!(GoproFilename::parse("invalid.ogg"), None);
+        assert_eq!(GoproFilename::parse("GOPR00420.MP4"), None);
+    }
+}
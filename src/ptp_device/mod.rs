@@ -0,0 +1,682 @@
+use std::cmp;
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Read};
+use std::path::Path;
+use std::rc::Rc;
+use std::sync::Mutex;
+
+use crate::config::{DeviceAction, GoproConfig};
+use crate::ctx;
+use crate::formatting;
+use crate::staging::{Staging, DateTimeUploadable, RemotePathDescriptor};
+use crate::mountable::{Mountable};
+
+use chrono;
+use chrono::prelude::*;
+use failure::Error;
+use libusb;
+use ptp;
+use std::hash::{Hash, Hasher};
+
+/// Quirks for GoPro cameras, which chapter their recordings and write sidecar files.
+pub mod gopro;
+mod session;
+
+pub use self::gopro::{GoproKind, GoproQuirks};
+pub use self::session::PtpSession;
+
+/// The USB interface class that PTP devices identify themselves with.
+const USB_CLASS_STILL_IMAGE: u8 = 0x06;
+
+/// The PTP operation for setting one of the device's properties.
+const SET_DEVICE_PROP_VALUE: u16 = 0x1016;
+/// The device property holding the device's clock.
+const DEVICE_PROP_DATE_TIME: u32 = 0x5011;
+
+/// How much of an object we ask for in each request. Every request is a round trip over USB, so
+/// we ask for a lot at once and hand it out in whatever sized pieces our reader asks for.
+const CHUNK_SIZE: u32 = 4 * 1024 * 1024;
+
+/// The extensions of files we'll archive even if the device doesn't report a format we recognise
+/// for them. Vendors are fond of inventing their own formats for raw images.
+const MEDIA_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "heic", "png", "tif", "tiff", "dng", "cr2", "cr3", "crw", "arw", "sr2", "srf",
+    "nef", "nrw", "raf", "orf", "rw2", "pef", "mp4", "mov", "avi", "mts", "m2ts", "mxf", "3gp",
+];
+
+/// PTP dates look like `20150101T000649`, but some devices append tenths of a second or a
+/// timezone, so we only look at the part every device agrees on.
+fn parse_ptp_date(date: &str) -> Result<DateTime<Local>, chrono::ParseError> {
+    let date = date.get(..15).unwrap_or(date);
+    Local.datetime_from_str(date, "%Y%m%dT%H%M%S")
+}
+
+fn format_ptp_date(date: &DateTime<Local>) -> String {
+    date.format("%Y%m%dT%H%M%S").to_string()
+}
+
+/// Encode a string the way PTP expects to find them in a dataset: a count of UCS-2 characters
+/// including the trailing null, followed by the characters themselves.
+fn encode_ptp_string(string: &str) -> Vec<u8> {
+    if string.is_empty() {
+        return vec![0];
+    }
+
+    let chars: Vec<u16> = string.encode_utf16().chain(Some(0)).collect();
+    let mut out = Vec::with_capacity(1 + chars.len() * 2);
+    out.push(chars.len() as u8);
+    for c in chars {
+        out.push((c & 0xff) as u8);
+        out.push((c >> 8) as u8);
+    }
+    out
+}
+
+pub struct PtpFile<'c> {
+    /// The capture date of the recording this file belongs to. For chaptered recordings this is
+    /// the capture date of the first chapter.
+    pub capturedate: String,
+    /// Which chapter of its recording this file is, if the recording spans more than one file.
+    chapter: Option<u16>,
+    /// The name the device gave this file, if we need it to tell this file apart from others
+    /// captured in the same second.
+    name: Option<String>,
+    extension: String,
+    // TODO(richo) I think this handle gets invalidated when we close the session down
+    handle: u32,
+    /// How far into the object we've requested.
+    offset: u32,
+    size: u32,
+    /// The most recent chunk we've received, and how much of it we've handed out.
+    buffer: Vec<u8>,
+    buffer_pos: usize,
+    /// When we made our first request, so that we can report on throughput.
+    started: Option<DateTime<Utc>>,
+    session: Rc<Mutex<PtpSession<'c>>>,
+}
+
+impl<'c> PtpFile<'c> {
+    fn new(
+        session: &Rc<Mutex<PtpSession<'c>>>,
+        handle: u32,
+        object: &ptp::PtpObjectInfo,
+        capturedate: String,
+        extension: String,
+    ) -> PtpFile<'c> {
+        PtpFile {
+            capturedate,
+            chapter: None,
+            name: None,
+            extension,
+            handle,
+            offset: 0,
+            size: object.ObjectCompressedSize,
+            buffer: vec![],
+            buffer_pos: 0,
+            started: None,
+            session: Rc::clone(session),
+        }
+    }
+
+    /// Replace our buffer with the next chunk of the object.
+    fn fill_buffer(&mut self) -> io::Result<()> {
+        let started = *self.started.get_or_insert_with(Utc::now);
+        let (handle, offset) = (self.handle, self.offset);
+        let len = chunk_len(offset, self.size);
+
+        let chunk = self.session.lock().unwrap().retrying(
+            &format!("reading {} bytes of object {:#x} at offset {}", len, handle, offset),
+            |camera| camera.get_partialobject(handle, offset, len, None),
+        ).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        if chunk.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                format!("Device sent nothing for object {:#x} at offset {}", handle, offset),
+            ));
+        }
+
+        self.offset += chunk.len() as u32;
+        self.buffer = chunk;
+        self.buffer_pos = 0;
+
+        if self.offset >= self.size {
+            let elapsed = Utc::now() - started;
+            info!(
+                "Transferred {} of object {:#x} in {} ({})",
+                formatting::human_readable_size(self.size as usize),
+                handle,
+                formatting::human_readable_time(elapsed),
+                formatting::human_readable_throughput(self.size as usize, elapsed),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Which of a device's files, given as their capture dates and extensions, were captured in the
+/// same second as another file of the same type.
+fn shared_capture_times<'a>(files: impl Iterator<Item = (&'a str, &'a str)>) -> Vec<bool> {
+    let keys: Vec<_> = files
+        .map(|(capturedate, extension)| (parse_ptp_date(capturedate).ok(), extension))
+        .collect();
+    let mut counts = HashMap::new();
+    for key in &keys {
+        *counts.entry(key).or_insert(0) += 1;
+    }
+    keys.iter().map(|key| counts[key] > 1).collect()
+}
+
+/// How much to ask for at `offset` into an object of `size` bytes. Requests are kept aligned to
+/// `CHUNK_SIZE`, which lets cameras serve them straight off the card.
+fn chunk_len(offset: u32, size: u32) -> u32 {
+    cmp::min(CHUNK_SIZE - offset % CHUNK_SIZE, size - offset)
+}
+
+impl<'c> fmt::Debug for PtpFile<'c> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PtpFile")
+            .field("capturedate", &self.capturedate)
+            .field("chapter", &self.chapter)
+            .field("name", &self.name)
+            .field("extension", &self.extension)
+            .field("handle", &self.handle)
+            .field("offset", &self.offset)
+            .field("size", &self.size)
+            .field("buffered", &(self.buffer.len() - self.buffer_pos))
+            .field("session", &"Rc<Mutex<PtpSession<'c> { ... }>>")
+            .finish()
+    }
+}
+
+impl<'c> DateTimeUploadable for PtpFile<'c> {
+    type Reader = PtpFile<'c>;
+
+    fn extension(&self) -> &str {
+        &self.extension
+    }
+
+    fn capture_datetime(&self) -> Result<DateTime<Local>, chrono::ParseError> {
+        parse_ptp_date(&self.capturedate)
+    }
+
+    fn remote_path(&self) -> Result<RemotePathDescriptor, Error> {
+        let capture_time = self.capture_datetime()?;
+        let extension = self.extension().to_string();
+        Ok(match (self.chapter, &self.name) {
+            (Some(chapter), _) => RemotePathDescriptor::Chapter {
+                capture_time,
+                extension,
+                chapter,
+            },
+            (None, Some(name)) => RemotePathDescriptor::Named {
+                capture_time,
+                name: name.clone(),
+                extension,
+            },
+            (None, None) => RemotePathDescriptor::DateTime {
+                capture_time,
+                extension,
+            },
+        })
+    }
+
+    fn reader(&mut self) -> &mut PtpFile<'c> {
+        self
+    }
+
+    fn delete(&mut self) -> Result<(), Error> {
+        // lol how even does into
+        Ok(self
+            .session
+            .lock()
+            .unwrap()
+            .camera()
+            .delete_object(self.handle, None)?)
+    }
+
+    fn size(&self) -> Result<u64, Error> {
+        Ok(u64::from(self.size))
+    }
+}
+
+impl<'b> Read for PtpFile<'b> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        // Tragically, ptp really wants to allocate it's own memory :(
+        // If I have luck with my other patches, we can try to upstream something
+        if self.buffer_pos >= self.buffer.len() {
+            if self.offset >= self.size {
+                return Ok(0);
+            }
+            self.fill_buffer()?;
+        }
+
+        let len = cmp::min(buf.len(), self.buffer.len() - self.buffer_pos);
+        buf[..len].copy_from_slice(&self.buffer[self.buffer_pos..self.buffer_pos + len]);
+        self.buffer_pos += len;
+        Ok(len)
+    }
+}
+
+#[repr(u16)]
+#[derive(Eq, PartialEq, Debug)]
+enum PtpObjectFormat {
+    Undefined = 0x3000,
+    Association = 0x3001,
+    Mpeg = 0x300a,
+    Asf = 0x300b,
+    Avi = 0x300c,
+    QuickTime = 0x300d,
+    ExifJpeg = 0x3801,
+    Bmp = 0x3804,
+    Gif = 0x3807,
+    Jfif = 0x3808,
+    Png = 0x380b,
+    Tiff = 0x380d,
+    Dng = 0x3811,
+    Mp4Container = 0xb982,
+    ThreeGpContainer = 0xb984,
+}
+
+impl PtpObjectFormat {
+    fn from_u16(format: u16) -> Option<PtpObjectFormat> {
+        use self::PtpObjectFormat::*;
+        match format {
+            0x3000 => Some(Undefined),
+            0x3001 => Some(Association),
+            0x300a => Some(Mpeg),
+            0x300b => Some(Asf),
+            0x300c => Some(Avi),
+            0x300d => Some(QuickTime),
+            0x3801 => Some(ExifJpeg),
+            0x3804 => Some(Bmp),
+            0x3807 => Some(Gif),
+            0x3808 => Some(Jfif),
+            0x380b => Some(Png),
+            0x380d => Some(Tiff),
+            0x3811 => Some(Dng),
+            0xb982 => Some(Mp4Container),
+            0xb984 => Some(ThreeGpContainer),
+            _ => None,
+        }
+    }
+
+    /// The extension to use for an image or video of this format, if its filename doesn't have one.
+    fn media_extension(&self) -> Option<&'static str> {
+        use self::PtpObjectFormat::*;
+        match self {
+            Undefined | Association => None,
+            Mpeg => Some("mpg"),
+            Asf => Some("asf"),
+            Avi => Some("avi"),
+            QuickTime => Some("mov"),
+            ExifJpeg | Jfif => Some("jpg"),
+            Bmp => Some("bmp"),
+            Gif => Some("gif"),
+            Png => Some("png"),
+            Tiff => Some("tif"),
+            Dng => Some("dng"),
+            Mp4Container => Some("mp4"),
+            ThreeGpContainer => Some("3gp"),
+        }
+    }
+}
+
+/// Work out the extension to stage an object with, or None if it isn't an image or video.
+///
+/// We trust the format the device reports where we recognise it, and otherwise fall back to the
+/// extension of the object's filename.
+fn media_extension(format: u16, filename: &str) -> Option<String> {
+    let extension = filename.rfind('.').map(|idx| filename[idx + 1..].to_lowercase());
+
+    match PtpObjectFormat::from_u16(format).and_then(|format| format.media_extension()) {
+        Some(default) => Some(extension.unwrap_or_else(|| default.to_string())),
+        None => extension.filter(|ext| MEDIA_EXTENSIONS.contains(&&ext[..])),
+    }
+}
+
+/// Vendor specific behaviour layered on top of the generic PTP support.
+#[derive(Eq, PartialEq, Debug, Hash, Clone)]
+pub enum PtpVendor {
+    Gopro(GoproQuirks),
+    Generic,
+}
+
+pub struct PtpDevice<'d> {
+    // TODO(richo) having a name in here would simplify the Staging impl
+    pub vendor: PtpVendor,
+    pub vendor_id: u16,
+    pub product_id: u16,
+    pub manufacturer: String,
+    pub model: String,
+    pub serial: String,
+    device: libusb::Device<'d>,
+}
+
+impl<'d> PartialEq for PtpDevice<'d> {
+    fn eq(&self, other: &PtpDevice<'d>) -> bool {
+        self.vendor_id == other.vendor_id &&
+            self.product_id == other.product_id &&
+            self.serial == other.serial
+    }
+}
+
+impl<'d> Eq for PtpDevice<'d> {}
+
+impl<'d> Hash for PtpDevice<'d> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.vendor_id.hash(state);
+        self.product_id.hash(state);
+        self.serial.hash(state);
+    }
+}
+
+pub struct PtpConnection<'c> {
+    vendor: PtpVendor,
+    model: String,
+    serial: String,
+    session: Rc<Mutex<PtpSession<'c>>>,
+}
+
+impl<'c> PtpConnection<'c> {
+    /// Every object on every storage of this device, apart from directories.
+    fn objects(&self) -> Result<Vec<(u32, ptp::PtpObjectInfo)>, Error> {
+        let mut out = vec![];
+        let timeout = None;
+
+        let mut session = self.session.lock().unwrap();
+        let storages = session.retrying("listing storages", |camera| camera.get_storageids(timeout))?;
+        for storage in storages {
+            let handles = session.retrying(&format!("listing objects on storage {:#x}", storage), |camera| {
+                camera.get_objecthandles_all(storage, None, timeout)
+            })?;
+            for handle in handles {
+                let object = session.retrying(&format!("getting info for object {:#x}", handle), |camera| {
+                    camera.get_objectinfo(handle, timeout)
+                })?;
+                if object.ObjectFormat == PtpObjectFormat::Association as u16 {
+                    continue;
+                }
+                out.push((handle, object));
+            }
+        }
+
+        Ok(out)
+    }
+
+    pub fn power_down(&mut self) -> Result<(), ptp::Error> {
+        self.session.lock().unwrap().camera().power_down(None)
+    }
+
+    /// Set the device's clock.
+    pub fn set_clock(&mut self, now: &DateTime<Local>) -> Result<(), ptp::Error> {
+        let data = encode_ptp_string(&format_ptp_date(now));
+        self.session.lock().unwrap().camera().command(
+            SET_DEVICE_PROP_VALUE,
+            &[DEVICE_PROP_DATE_TIME],
+            Some(&data),
+            None,
+        )?;
+        Ok(())
+    }
+}
+
+impl<'c> Staging for PtpConnection<'c> where {
+    type FileType = PtpFile<'c>;
+
+    fn files(&self) -> Result<Vec<PtpFile<'c>>, Error> {
+        let objects = self.objects()?;
+
+        let out: Vec<_> = match &self.vendor {
+            PtpVendor::Gopro(quirks) => quirks.files(&self.session, objects),
+            PtpVendor::Generic => {
+                let files: Vec<_> = objects
+                    .into_iter()
+                    .filter_map(|(handle, object)| {
+                        let extension = match media_extension(object.ObjectFormat, &object.Filename) {
+                            Some(extension) => extension,
+                            None => {
+                                trace!("Skipping {:?} with format {:#x}", &object.Filename, object.ObjectFormat);
+                                return None;
+                            },
+                        };
+                        // Not every device fills in the capture date, but they all seem to manage
+                        // the modification date.
+                        let capturedate = if object.CaptureDate.is_empty() {
+                            object.ModificationDate.clone()
+                        } else {
+                            object.CaptureDate.clone()
+                        };
+                        let name = match Path::new(&object.Filename).file_stem() {
+                            Some(stem) if !stem.is_empty() => stem.to_string_lossy().into_owned(),
+                            _ => format!("{:08x}", handle),
+                        };
+                        Some((PtpFile::new(&self.session, handle, &object, capturedate, extension), name))
+                    })
+                    .collect();
+
+                // Capture dates only go down to the second, so the frames of a burst would all be
+                // staged under the same name. Those get told apart by their original names.
+                let shared = shared_capture_times(
+                    files.iter().map(|(file, _)| (file.capturedate.as_str(), file.extension.as_str())),
+                );
+                files
+                    .into_iter()
+                    .zip(shared)
+                    .map(|((mut file, name), shared)| {
+                        if shared {
+                            file.name = Some(name);
+                        }
+                        trace!("Adding {:?} to the plan", &file);
+                        file
+                    })
+                    .collect()
+            },
+        };
+
+        info!(
+            "Loaded {} files from {:?} serial {}",
+            out.len(),
+            &self.vendor,
+            &self.serial
+        );
+
+        Ok(out)
+    }
+
+    fn perform_action(&mut self, action: DeviceAction) -> Result<String, Error> {
+        match action {
+            DeviceAction::PowerDown => {
+                self.power_down()?;
+                Ok("Powered down".to_string())
+            },
+            DeviceAction::SyncClock => {
+                let now = Local::now();
+                self.set_clock(&now)?;
+                Ok(format!("Set clock to {}", now.format("%Y-%m-%d %H:%M:%S")))
+            },
+            action => bail!("{} is not supported by this device", action),
+        }
+    }
+}
+
+impl<'a> Mountable for PtpDevice<'a> {
+    type Target = PtpConnection<'a>;
+
+    fn mount(self) -> Result<PtpConnection<'a>, Error> {
+        // The session takes the libusb device, and is shared out to files from here on, which
+        // keeps it on the thread that mounted us.
+        let session = PtpSession::open(self.device)?;
+
+        Ok(PtpConnection {
+            vendor: self.vendor,
+            model: self.model,
+            serial: self.serial,
+            session: Rc::new(Mutex::new(session)),
+        })
+    }
+}
+
+impl<'a> PtpDevice<'a> {
+    /// Apply the options from a gopro's configuration. This does nothing for other devices.
+    pub fn configure(mut self, cfg: &GoproConfig) -> PtpDevice<'a> {
+        if let PtpVendor::Gopro(quirks) = &mut self.vendor {
+            quirks.configure(cfg);
+        }
+        self
+    }
+}
+
+impl<'a> fmt::Debug for PtpDevice<'a> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PtpDevice")
+            .field("vendor", &self.vendor)
+            .field("vendor_id", &self.vendor_id)
+            .field("product_id", &self.product_id)
+            .field("manufacturer", &self.manufacturer)
+            .field("model", &self.model)
+            .field("serial", &self.serial)
+            .field("device", &"libusb::Device")
+            .finish()
+    }
+}
+
+impl<'c> fmt::Debug for PtpConnection<'c> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PtpConnection")
+            .field("vendor", &self.vendor)
+            .field("model", &self.model)
+            .field("serial", &self.serial)
+            .field("session", &"Rc<Mutex<PtpSession<'c> { ... }>>")
+            .finish()
+    }
+}
+
+/// Whether this device has a Still Image interface, which is how PTP devices identify themselves.
+fn is_still_image(device: &libusb::Device<'_>) -> bool {
+    match device.active_config_descriptor() {
+        Ok(config) => config.interfaces().any(|interface| {
+            interface
+                .descriptors()
+                .any(|desc| desc.class_code() == USB_CLASS_STILL_IMAGE)
+        }),
+        Err(_) => false,
+    }
+}
+
+pub fn locate_ptp_devices(ctx: &ctx::Ctx) -> Result<Vec<PtpDevice<'_>>, Error> {
+    let mut res = vec![];
+
+    for device in ctx.usb_ctx.devices()?.iter() {
+        let device_desc = device.device_descriptor()?;
+        let vendor_id = device_desc.vendor_id();
+        let product_id = device_desc.product_id();
+
+        // MTP devices don't always advertise themselves as Still Image devices, so we'll also try
+        // anything we've been explicitly told about.
+        let configured = ctx
+            .cfg
+            .ptps()
+            .iter()
+            .any(|cfg| cfg.matches_usb_ids(vendor_id, product_id));
+        if vendor_id != gopro::GOPRO_VENDOR && !configured && !is_still_image(&device) {
+            continue;
+        }
+
+        let info = match ptp::PtpCamera::new(&device).and_then(|mut camera| camera.get_device_info(None)) {
+            Ok(info) => info,
+            Err(e) => {
+                warn!("Couldn't get device info from {:04x}:{:04x}: {:?}", vendor_id, product_id, e);
+                continue;
+            },
+        };
+
+        // We'll just use the Manufacturer tag in the PtpDevice
+        let vendor = if vendor_id == gopro::GOPRO_VENDOR && info.Manufacturer == gopro::GOPRO_MANUFACTURER {
+            PtpVendor::Gopro(GoproQuirks::new(GoproKind::from_u16(product_id)))
+        } else {
+            PtpVendor::Generic
+        };
+
+        res.push(PtpDevice {
+            vendor,
+            vendor_id,
+            product_id,
+            manufacturer: info.Manufacturer,
+            model: info.Model,
+            serial: info.SerialNumber,
+            device,
+        });
+    }
+    Ok(res)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parses_gopro_date_correctly() {
+        let dt = Local.ymd(2015, 1, 1).and_hms(0, 6, 49);
+        // TODO(richo) get better testcases
+        assert_eq!(parse_ptp_date("20150101T000649"), Ok(dt.clone()));
+    }
+
+    #[test]
+    fn test_parses_dates_with_trailing_precision() {
+        let dt = Local.ymd(2019, 3, 2).and_hms(14, 21, 7);
+        assert_eq!(parse_ptp_date("20190302T142107.0"), Ok(dt.clone()));
+        assert_eq!(parse_ptp_date("20190302T142107+0100"), Ok(dt.clone()));
+    }
+
+    #[test]
+    fn test_formats_dates_for_ptp() {
+        let dt = Local.ymd(2019, 3, 2).and_hms(14, 21, 7);
+        assert_eq!(format_ptp_date(&dt), "20190302T142107");
+        assert_eq!(parse_ptp_date(&format_ptp_date(&dt)), Ok(dt.clone()));
+    }
+
+    #[test]
+    fn test_encodes_ptp_strings() {
+        assert_eq!(encode_ptp_string(""), vec![0]);
+        assert_eq!(encode_ptp_string("T1"), vec![3, b'T', 0, b'1', 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_tells_apart_files_captured_in_the_same_second() {
+        let files = vec![
+            ("20190302T142107", "jpg"),
+            ("20190302T142107", "jpg"),
+            ("20190302T142107", "mp4"),
+            ("20190302T142108", "jpg"),
+            ("20190302T142108.5", "jpg"),
+        ];
+        assert_eq!(
+            shared_capture_times(files.into_iter()),
+            vec![true, true, false, true, true],
+        );
+    }
+
+    #[test]
+    fn test_chunks_are_aligned() {
+        let size = 3 * CHUNK_SIZE + 42;
+        assert_eq!(chunk_len(0, size), CHUNK_SIZE);
+        assert_eq!(chunk_len(CHUNK_SIZE, size), CHUNK_SIZE);
+        assert_eq!(chunk_len(3 * CHUNK_SIZE, size), 42);
+        // If a device hands us a short chunk we realign on the next request
+        assert_eq!(chunk_len(1000, size), CHUNK_SIZE - 1000);
+        assert_eq!(chunk_len(0, 42), 42);
+    }
+
+    #[test]
+    fn test_media_extensions() {
+        assert_eq!(media_extension(0x3801, "DSC00042.JPG"), Some("jpg".into()));
+        assert_eq!(media_extension(0x300d, "C0001.MP4"), Some("mp4".into()));
+        assert_eq!(media_extension(0xb982, "VID_0001"), Some("mp4".into()));
+        assert_eq!(media_extension(0xb101, "IMG_0042.CR2"), Some("cr2".into()));
+        assert_eq!(media_extension(0x3000, "DSC00042.ARW"), Some("arw".into()));
+        assert_eq!(media_extension(0x3000, "MEDIAPRO.XML"), None);
+        assert_eq!(media_extension(0x3004, "README"), None);
+        assert_eq!(media_extension(0x3001, "DCIM"), None);
+    }
+}
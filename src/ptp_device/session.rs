@@ -0,0 +1,92 @@
+use std::fmt;
+use std::thread;
+use std::time::Duration;
+
+use failure::Error;
+use libusb;
+use ptp;
+
+/// How many times we'll try a single request before giving up on it.
+const MAX_ATTEMPTS: u32 = 4;
+
+/// An open session on a PTP device.
+///
+/// Cameras are prone to resetting their USB connection partway through a long transfer, so the
+/// session keeps hold of the device it was opened on and can open a fresh one when that happens.
+pub struct PtpSession<'c> {
+    device: libusb::Device<'c>,
+    camera: ptp::PtpCamera<'c>,
+}
+
+impl<'c> PtpSession<'c> {
+    pub fn open(device: libusb::Device<'c>) -> Result<PtpSession<'c>, Error> {
+        let mut camera = ptp::PtpCamera::new(&device)?;
+        camera.open_session(None)?;
+
+        Ok(PtpSession { device, camera })
+    }
+
+    pub fn camera(&mut self) -> &mut ptp::PtpCamera<'c> {
+        &mut self.camera
+    }
+
+    /// Close our session, if the device will still let us, and open a new one.
+    pub fn reopen(&mut self) -> Result<(), Error> {
+        info!("Reopening session on {:?}", &self);
+        let _ = self.camera.close_session(None);
+
+        let mut camera = ptp::PtpCamera::new(&self.device)?;
+        camera.open_session(None)?;
+        self.camera = camera;
+        Ok(())
+    }
+
+    /// Run a request against the device, reopening the session and trying again if it fails.
+    ///
+    /// The request is retried exactly as it was first made, so a chunk that fails partway through
+    /// is asked for again from the same offset.
+    pub fn retrying<T, F>(&mut self, what: &str, mut request: F) -> Result<T, ptp::Error>
+    where
+        F: FnMut(&mut ptp::PtpCamera<'c>) -> Result<T, ptp::Error>,
+    {
+        let mut attempt = 1;
+        loop {
+            match request(&mut self.camera) {
+                Ok(res) => return Ok(res),
+                Err(e) if attempt >= MAX_ATTEMPTS => {
+                    error!("Giving up on {} after {} attempts: {:?}", what, attempt, e);
+                    return Err(e);
+                },
+                Err(e) => {
+                    warn!("Attempt {} at {} failed: {:?}", attempt, what, e);
+                    thread::sleep(Duration::from_millis(500 * u64::from(attempt)));
+                    if let Err(e) = self.reopen() {
+                        warn!("Couldn't reopen session: {:?}", e);
+                    }
+                    attempt += 1;
+                },
+            }
+        }
+    }
+}
+
+impl<'c> Drop for PtpSession<'c> {
+    fn drop(&mut self) {
+        // If this fails.. who cares I guess
+        info!("Closing session on {:?}", &self);
+        let _ = self.camera.close_session(None);
+    }
+}
+
+impl<'c> fmt::Debug for PtpSession<'c> {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt.debug_struct("PtpSession")
+            .field("device", &format_args!(
+                "Bus {:03} Device {:03}",
+                self.device.bus_number(),
+                self.device.address()
+            ))
+            .field("camera", &"ptp::PtpCamera")
+            .finish()
+    }
+}
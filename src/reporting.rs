@@ -1,5 +1,8 @@
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
 
+use crate::config::DeviceAction;
+use crate::device::StagingOutcome;
 use crate::staging::UploadDescriptor;
 use crate::formatting::human_readable_size;
 
@@ -9,11 +12,13 @@ use serde::ser::{Serialize, Serializer, SerializeStruct};
 
 handlebars_helper!(header: |v: str| format!("{}\n{}", v, str::repeat("=", v.len())));
 handlebars_helper!(human_size: |v: u64| format!("{}b", human_readable_size(v as usize)));
+handlebars_helper!(indent: |v: str| v.replace("\n", "\n        "));
 
 fn handlebars() -> Handlebars {
     let mut handlebars = Handlebars::new();
     handlebars.register_helper("header", Box::new(header));
     handlebars.register_helper("human_readable_size", Box::new(human_size));
+    handlebars.register_helper("indent", Box::new(indent));
     handlebars.set_strict_mode(true);
     handlebars
 }
@@ -42,8 +47,74 @@ impl Serialize for UploadStatus {
 /// A report describing how a series of upload transactions went.
 #[derive(Debug, Default, Serialize)]
 pub struct UploadReport {
-    files: HashMap<String, Vec<ReportEntry>>,
+    files: HashMap<String, Vec<Recording>>,
     uploaded_tally: HashMap<String, u64>,
+    device_actions: HashMap<String, Vec<ActionEntry>>,
+    /// How staging went for each device, whether or not anything was uploaded from it.
+    staged: BTreeMap<String, String>,
+    /// Everything that went wrong during the run.
+    problems: Vec<Problem>,
+    /// Everything that was left for next time because the run was cancelled.
+    pending: Vec<String>,
+}
+
+/// Something that went wrong during a run, and what it happened to.
+#[derive(Debug, Serialize)]
+struct Problem {
+    source: String,
+    problem: String,
+}
+
+/// One or more entries which together make up a single recording.
+///
+/// Most recordings are a single file, but some devices split long recordings into chapters, in
+/// which case `path` is the path of the recording as a whole.
+#[derive(Debug)]
+struct Recording {
+    path: Option<PathBuf>,
+    entries: Vec<ReportEntry>,
+}
+
+#[derive(Serialize)]
+struct RecordingSummary {
+    remote_path: PathBuf,
+    size: String,
+    jump: Option<String>,
+}
+
+impl Serialize for Recording {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let no_results: &[(String, UploadStatus)] = &[];
+        let no_chapters: &[ReportEntry] = &[];
+
+        let mut ser = serializer.serialize_struct("Recording", 3)?;
+        match &self.path {
+            Some(path) => {
+                let size = self.entries.iter().map(|e| e.desc.size).sum::<u64>();
+                ser.serialize_field("desc", &RecordingSummary {
+                    remote_path: path.clone(),
+                    size: human_readable_size(size as usize),
+                    jump: None,
+                })?;
+                ser.serialize_field("results", no_results)?;
+                ser.serialize_field("chapters", &self.entries)?;
+            },
+            None => {
+                let entry = &self.entries[0];
+                ser.serialize_field("desc", &RecordingSummary {
+                    remote_path: entry.desc.remote_path(),
+                    size: human_readable_size(entry.desc.size as usize),
+                    jump: entry.desc.jump_summary.as_ref().map(|j| j.to_string()),
+                })?;
+                ser.serialize_field("results", &entry.results)?;
+                ser.serialize_field("chapters", no_chapters)?;
+            },
+        }
+        ser.end()
+    }
 }
 
 /// An entry in the report.
@@ -67,10 +138,41 @@ fn format_report<S>(desc: &UploadDescriptor, serializer: S) -> Result<S::Ok, S::
     let mut ser = serializer.serialize_struct("UploadDescriptor", 3)?;
     ser.serialize_field("remote_path", &desc.remote_path())?;
     ser.serialize_field("size", &human_readable_size(desc.size as usize))?;
+    ser.serialize_field("jump", &desc.jump_summary.as_ref().map(|j| j.to_string()))?;
     ser.end()
 
 }
 
+/// The outcome of performing a `DeviceAction` on a device after it was staged.
+#[derive(Debug, Serialize)]
+pub struct ActionEntry {
+    #[serde(skip)]
+    device_name: String,
+    action: DeviceAction,
+    #[serde(serialize_with = "format_action_result")]
+    result: Result<String, Error>,
+}
+
+fn format_action_result<S>(result: &Result<String, Error>, serializer: S) -> Result<S::Ok, S::Error>
+    where S: Serializer,
+{
+    let msg = match result {
+        Ok(msg) => msg.to_string(),
+        Err(error) => format!("Failed: {}", error),
+    };
+    serializer.serialize_str(&msg)
+}
+
+impl ActionEntry {
+    pub fn new(device_name: String, action: DeviceAction, result: Result<String, Error>) -> ActionEntry {
+        ActionEntry { device_name, action, result }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.result.is_ok()
+    }
+}
+
 impl ReportEntry {
     /// Bind an UploadDescriptor to this entry, returning the finalised ReportEntry.
     pub fn new(desc: UploadDescriptor, results: Vec<(String, UploadStatus)>) -> ReportEntry {
@@ -102,11 +204,84 @@ impl UploadReport {
             }
         }
 
-        let uploads = self
+        let recordings = self
             .files
             .entry(entry.desc.device_name.clone())
             .or_insert_with(|| vec![]);
-        uploads.push(entry);
+
+        // Chapters are folded into the recording they belong to, so that the report lists them
+        // as a single recording.
+        match entry.desc.recording_path() {
+            Some(path) => {
+                match recordings.iter_mut().find(|r| r.path.as_ref() == Some(&path)) {
+                    Some(recording) => recording.entries.push(entry),
+                    None => recordings.push(Recording {
+                        path: Some(path),
+                        entries: vec![entry],
+                    }),
+                }
+            },
+            None => recordings.push(Recording {
+                path: None,
+                entries: vec![entry],
+            }),
+        }
+    }
+
+    /// Attach the outcome of a device action to this report.
+    pub fn record_device_action(&mut self, entry: ActionEntry) {
+        self.device_actions
+            .entry(entry.device_name.clone())
+            .or_insert_with(|| vec![])
+            .push(entry);
+    }
+
+    /// Attach the outcome of staging a device to this report, along with the outcomes of the
+    /// actions performed on it.
+    pub fn record_staging(&mut self, device_name: &str, outcome: Result<StagingOutcome, Error>) {
+        let summary = match outcome {
+            Ok(outcome) => {
+                for action in outcome.actions {
+                    self.record_device_action(action);
+                }
+                if outcome.cancelled {
+                    self.record_pending(format!("device {}: Anything that wasn't staged is still on it", device_name));
+                }
+                match (outcome.num_files, outcome.cancelled) {
+                    (1, false) => "Staged 1 file".to_string(),
+                    (n, false) => format!("Staged {} files", n),
+                    (0, true) => "Cancelled".to_string(),
+                    (1, true) => "Cancelled after staging 1 file".to_string(),
+                    (n, true) => format!("Cancelled after staging {} files", n),
+                }
+            },
+            Err(e) => {
+                self.record_problem(format!("device {}", device_name), format!("Couldn't stage: {}", e));
+                format!("Failed: {}", e)
+            },
+        };
+        self.staged.insert(device_name.to_string(), summary);
+    }
+
+    /// Record that something went wrong with `source`, eg a device or a backend, so that it can be
+    /// called out at the top of the report.
+    pub fn record_problem(&mut self, source: String, problem: String) {
+        self.problems.push(Problem { source, problem });
+    }
+
+    /// Whether anything went wrong during the run.
+    pub fn has_problems(&self) -> bool {
+        !self.problems.is_empty()
+    }
+
+    /// Record something that was left for next time because the run was cancelled.
+    pub fn record_pending(&mut self, item: String) {
+        self.pending.push(item);
+    }
+
+    /// Whether the run was cancelled with anything left to do.
+    pub fn has_pending(&self) -> bool {
+        !self.pending.is_empty()
     }
 
     pub fn to_plaintext(&self) -> Result<String, TemplateRenderError> {
@@ -117,13 +292,15 @@ impl UploadReport {
     pub fn num_uploads(&self) -> usize {
         self.files
             .values() // Each device
-            .fold(0, |i, v| i + v.len())
+            .flat_map(|v| v.iter()) // Each recording
+            .fold(0, |i, r| i + r.entries.len())
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::flysight::JumpSummary;
     use chrono::prelude::*;
 
     fn dummy_report() -> UploadReport {
@@ -182,6 +359,229 @@ mod tests {
         assert_eq!(report.num_uploads(), 4);
     }
 
+    #[test]
+    fn test_groups_chapters_into_recordings() {
+        let mut report: UploadReport = Default::default();
+        let capture_time = Local.ymd(2018, 8, 24).and_hms(9, 55, 30);
+
+        for chapter in 1..=3 {
+            let mut desc = UploadDescriptor::build("gopro".to_string())
+                .chapter(capture_time, "mp4".to_string(), chapter);
+            desc.size = 4 * 1024 * 1024 * 1024;
+            report.record_activity(ReportEntry::new(
+                    desc,
+                    vec![
+                        ("vimeo".into(), UploadStatus::Succeeded),
+                    ],
+            ));
+        }
+
+        assert_eq!(report.num_uploads(), 3);
+
+        let expected = "\
+ARCHIVER UPLOAD REPORT
+======================
+
+gopro
+=====
+
+    /18-08-24/gopro/09-55-30.mp4 (12gb)
+        /18-08-24/gopro/09-55-30-ch01.mp4 (4.0gb)
+        # vimeo: Succeeded
+        /18-08-24/gopro/09-55-30-ch02.mp4 (4.0gb)
+        # vimeo: Succeeded
+        /18-08-24/gopro/09-55-30-ch03.mp4 (4.0gb)
+        # vimeo: Succeeded
+
+Uploaded Data
+=============
+
+vimeo: 12gb
+";
+        assert_eq!(report.to_plaintext().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_reports_device_actions() {
+        let mut report: UploadReport = Default::default();
+        report.record_device_action(ActionEntry::new(
+                "gopro".into(),
+                DeviceAction::SyncClock,
+                Ok("Set clock to 2018-08-24 09:55:30".into()),
+        ));
+        report.record_device_action(ActionEntry::new(
+                "gopro".into(),
+                DeviceAction::PowerDown,
+                Err(format_err!("Device went away")),
+        ));
+        report.record_device_action(ActionEntry::new(
+                "flysight".into(),
+                DeviceAction::PushConfig,
+                Ok("Updated config.txt\n-Volume: 6\n+Volume: 8".into()),
+        ));
+
+        let expected = "\
+ARCHIVER UPLOAD REPORT
+======================
+
+Uploaded Data
+=============
+
+
+Device Actions
+==============
+
+flysight
+    push_config: Updated config.txt
+        -Volume: 6
+        +Volume: 8
+gopro
+    sync_clock: Set clock to 2018-08-24 09:55:30
+    power_down: Failed: Device went away
+";
+        assert_eq!(report.to_plaintext().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_reports_staging() {
+        let mut report: UploadReport = Default::default();
+        report.record_staging("front", Ok(StagingOutcome {
+            num_files: 3,
+            actions: vec![],
+            cancelled: false,
+        }));
+        report.record_staging("back", Ok(StagingOutcome {
+            num_files: 1,
+            actions: vec![],
+            cancelled: false,
+        }));
+
+        let expected = "\
+ARCHIVER UPLOAD REPORT
+======================
+
+Uploaded Data
+=============
+
+
+Staging
+=======
+
+back: Staged 1 file
+front: Staged 3 files
+";
+        assert_eq!(report.to_plaintext().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_reports_problems() {
+        let mut report: UploadReport = Default::default();
+        assert!(!report.has_problems());
+
+        report.record_staging("back", Err(format_err!("Card went away")));
+        report.record_problem("backend dropbox".into(), "Couldn't set up: Token expired".into());
+        assert!(report.has_problems());
+
+        let expected = "\
+ARCHIVER UPLOAD REPORT
+======================
+
+Problems
+========
+
+device back: Couldn't stage: Card went away
+backend dropbox: Couldn't set up: Token expired
+
+Uploaded Data
+=============
+
+
+Staging
+=======
+
+back: Failed: Card went away
+";
+        assert_eq!(report.to_plaintext().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_reports_pending() {
+        let mut report: UploadReport = Default::default();
+        assert!(!report.has_pending());
+
+        report.record_staging("front", Ok(StagingOutcome {
+            num_files: 2,
+            actions: vec![],
+            cancelled: true,
+        }));
+        report.record_pending("staged /18-08-24/gopro/09-55-30.mp4 (4.0gb): Not uploaded yet".into());
+        assert!(report.has_pending());
+        assert!(!report.has_problems());
+
+        let expected = "\
+ARCHIVER UPLOAD REPORT
+======================
+
+Left Pending
+============
+
+device front: Anything that wasn't staged is still on it
+staged /18-08-24/gopro/09-55-30.mp4 (4.0gb): Not uploaded yet
+
+Uploaded Data
+=============
+
+
+Staging
+=======
+
+front: Cancelled after staging 2 files
+";
+        assert_eq!(report.to_plaintext().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_reports_jump_summaries() {
+        let mut report: UploadReport = Default::default();
+        let capture_time = Local.ymd(2018, 8, 24).and_hms(9, 55, 30);
+
+        let mut desc = UploadDescriptor::build("flysight".to_string())
+            .date_time(capture_time, "csv".to_string());
+        desc.size = 15487;
+        desc.jump_summary = Some(JumpSummary {
+            exit_time: capture_time,
+            exit_altitude: 1589.2,
+            freefall_duration: 9.4,
+            max_vertical_speed: 49.67,
+            max_horizontal_speed: 48.17,
+            deploy_altitude: 1296.3,
+        });
+        report.record_activity(ReportEntry::new(
+                desc,
+                vec![
+                    ("dropbox".into(), UploadStatus::Succeeded),
+                ],
+        ));
+
+        let expected = "\
+ARCHIVER UPLOAD REPORT
+======================
+
+flysight
+========
+
+    /18-08-24/flysight/09-55-30.csv (15kb)
+    # jump: exit at 09:55:30 from 1589m, 9.4s freefall, max 49.7m/s vertical 48.2m/s horizontal, deployed at 1296m
+    # dropbox: Succeeded
+
+Uploaded Data
+=============
+
+dropbox: 15kb
+";
+        assert_eq!(report.to_plaintext().unwrap(), expected);
+    }
+
     #[test]
     fn test_deals_with_large_totals() {
         let mut report: UploadReport = Default::default();
@@ -379,11 +779,29 @@ youtube: 15mb
 static UPLOAD_REPORT_TEMPLATE: &'static str = "\
 {{header \"ARCHIVER UPLOAD REPORT\"}}
 
+{{#if problems}}{{header \"Problems\"}}
+{{#each problems}}
+{{this.source}}: {{indent this.problem}}\
+{{/each}}
+
+{{/if}}\
+{{#if pending}}{{header \"Left Pending\"}}
+{{#each pending}}
+{{this}}\
+{{/each}}
+
+{{/if}}\
 {{#each files}}{{header @key}}
 {{#each this}}
     {{this.desc.remote_path}} ({{this.desc.size}}b)
+{{#if this.desc.jump}}    # jump: {{this.desc.jump}}
+{{/if}}\
 {{#each this.results}}    # {{this.[0]}}: {{this.[1]}}
 {{/each}}\
+{{#each this.chapters}}        {{this.desc.remote_path}} ({{this.desc.size}}b)
+{{#each this.results}}        # {{this.[0]}}: {{this.[1]}}
+{{/each}}\
+{{/each}}\
 {{/each}}
 {{/each}}\
 
@@ -391,4 +809,18 @@ static UPLOAD_REPORT_TEMPLATE: &'static str = "\
 {{#each uploaded_tally}}
 {{@key}}: {{human_readable_size this}}\
 {{/each}}
+{{#if staged}}
+{{header \"Staging\"}}
+{{#each staged}}
+{{@key}}: {{this}}\
+{{/each}}
+{{/if}}\
+{{#if device_actions}}
+{{header \"Device Actions\"}}
+{{#each device_actions}}
+{{@key}}
+{{#each this}}    {{this.action}}: {{indent this.result}}
+{{/each}}\
+{{/each}}\
+{{/if}}\
 ";
@@ -0,0 +1,373 @@
+use std::collections::HashSet;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use failure::Error;
+use serde::{Deserialize, Serialize};
+use serde_json;
+use walkdir::WalkDir;
+
+use crate::config::AfterStaging;
+use crate::staging::Disposal;
+
+/// Where files are moved to on a device with the `archive` policy.
+pub const ARCHIVE_DIR: &str = "archived";
+
+/// The file in a device's archiver folder that records which files were staged but kept on it.
+pub const KEPT_FILE: &str = "kept.json";
+
+/// Whether `relative` is the archive directory at the root of a device, and so shouldn't be
+/// staged from.
+pub fn is_archive_dir(relative: &Path) -> bool {
+    relative.to_string_lossy().eq_ignore_ascii_case(ARCHIVE_DIR)
+}
+
+/// What `policy` will do with `path` once it's been staged.
+pub fn disposal(policy: &AfterStaging, path: &Path) -> Result<Disposal, Error> {
+    Ok(match policy {
+        AfterStaging::Delete => Disposal::Delete,
+        AfterStaging::Archive { .. } => Disposal::Archive,
+        AfterStaging::KeepRecent { days } => {
+            let modified = fs::metadata(path)?.modified()?;
+            let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+            if age < Duration::from_secs(days * 24 * 60 * 60) {
+                Disposal::Keep
+            } else {
+                Disposal::Delete
+            }
+        },
+    })
+}
+
+/// A file that was staged from a device but kept on it. It's recognised by its size and when it
+/// was last modified, so that a file that's since been replaced is staged again.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+struct KeptFile {
+    /// Relative to where the device is mounted.
+    path: PathBuf,
+    size: u64,
+    modified: SystemTime,
+}
+
+impl KeptFile {
+    fn new(relative: &Path, metadata: &fs::Metadata) -> io::Result<KeptFile> {
+        Ok(KeptFile {
+            path: relative.to_path_buf(),
+            size: metadata.len(),
+            modified: metadata.modified()?,
+        })
+    }
+}
+
+/// The files on a device that have already been staged, but were kept on it.
+#[derive(Debug)]
+pub struct Kept {
+    root: PathBuf,
+    files: HashSet<KeptFile>,
+}
+
+impl Kept {
+    /// Whether `path`, described by `metadata`, was kept after it was staged and hasn't changed
+    /// since.
+    pub fn contains(&self, path: &Path, metadata: &fs::Metadata) -> bool {
+        match path.strip_prefix(&self.root) {
+            Ok(relative) => KeptFile::new(relative, metadata).map_or(false, |kept| self.files.contains(&kept)),
+            Err(_) => false,
+        }
+    }
+}
+
+/// How to deal with the files on a mounted device once they've been staged.
+#[derive(Debug, Clone)]
+pub struct Retention {
+    policy: AfterStaging,
+    /// Where the device is mounted.
+    root: PathBuf,
+    /// The device's archiver folder, where we record the files that we keep on it.
+    folder: PathBuf,
+}
+
+impl Retention {
+    pub fn new(policy: &AfterStaging, root: &Path, folder: &Path) -> Retention {
+        Retention {
+            policy: policy.clone(),
+            root: root.to_path_buf(),
+            folder: folder.to_path_buf(),
+        }
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// What will be done with `path` once it's been staged.
+    pub fn disposal(&self, path: &Path) -> Result<Disposal, Error> {
+        disposal(&self.policy, path)
+    }
+
+    /// Deal with `path` now that it's been staged.
+    pub fn dispose(&self, path: &Path) -> Result<(), Error> {
+        match self.disposal(path)? {
+            Disposal::Delete => {
+                fs::remove_file(path)?;
+            },
+            Disposal::Archive => {
+                let dest = self.root.join(ARCHIVE_DIR).join(path.strip_prefix(&self.root)?);
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                info!("Archiving {:?} to {:?}", path, &dest);
+                fs::rename(path, &dest)?;
+            },
+            Disposal::Keep => {
+                info!("Keeping {:?} on the device for now", path);
+                self.record_kept(path)?;
+            },
+        }
+        Ok(())
+    }
+
+    /// The files that were kept on the device after being staged, which shouldn't be staged
+    /// again.
+    pub fn kept(&self) -> Result<Kept, Error> {
+        Ok(Kept {
+            root: self.root.clone(),
+            files: self.load_kept()?.into_iter().collect(),
+        })
+    }
+
+    /// Tidy up the device once everything on it has been staged, deleting kept files once
+    /// they're old enough, and pruning its archive if it's running out of space.
+    ///
+    /// Returns how many files were removed.
+    pub fn tidy(&self) -> Result<usize, Error> {
+        match &self.policy {
+            AfterStaging::Archive { min_free_space: Some(min_free_space) } => {
+                prune_archive(&self.root, *min_free_space, || crate::mountable::free_space(&self.root))
+            },
+            AfterStaging::KeepRecent { .. } => self.expire_kept(),
+            _ => Ok(0),
+        }
+    }
+
+    fn kept_path(&self) -> PathBuf {
+        self.folder.join(KEPT_FILE)
+    }
+
+    fn load_kept(&self) -> Result<Vec<KeptFile>, Error> {
+        let path = self.kept_path();
+        let file = match File::open(&path) {
+            Ok(file) => file,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut out = vec![];
+        for line in BufReader::new(file).lines() {
+            let line = line?;
+            match serde_json::from_str(&line) {
+                Ok(kept) => out.push(kept),
+                // The device may have been pulled out halfway through writing a line
+                Err(e) => warn!("Ignoring {:?} in {:?}: {}", line, &path, e),
+            }
+        }
+        Ok(out)
+    }
+
+    fn record_kept(&self, path: &Path) -> Result<(), Error> {
+        let kept = KeptFile::new(path.strip_prefix(&self.root)?, &fs::metadata(path)?)?;
+        fs::create_dir_all(&self.folder)?;
+        let mut file = OpenOptions::new().create(true).append(true).open(self.kept_path())?;
+        writeln!(file, "{}", serde_json::to_string(&kept)?)?;
+        Ok(())
+    }
+
+    /// Delete the kept files that are now old enough to go, and forget the ones that have since
+    /// been removed or replaced.
+    fn expire_kept(&self) -> Result<usize, Error> {
+        let recorded = self.load_kept()?;
+        if recorded.is_empty() {
+            return Ok(0);
+        }
+
+        let mut remaining = vec![];
+        let mut expired = 0;
+        for kept in recorded {
+            let path = self.root.join(&kept.path);
+            let unchanged = match fs::metadata(&path) {
+                Ok(metadata) => KeptFile::new(&kept.path, &metadata)? == kept,
+                Err(ref e) if e.kind() == io::ErrorKind::NotFound => false,
+                Err(e) => return Err(e.into()),
+            };
+            if !unchanged {
+                continue;
+            }
+            if self.disposal(&path)? == Disposal::Delete {
+                info!("Deleting {:?}, it's been kept for long enough", &path);
+                fs::remove_file(&path)?;
+                expired += 1;
+            } else {
+                remaining.push(kept);
+            }
+        }
+        self.write_kept(&remaining)?;
+        Ok(expired)
+    }
+
+    fn write_kept(&self, files: &[KeptFile]) -> Result<(), Error> {
+        let path = self.kept_path();
+        if files.is_empty() {
+            fs::remove_file(&path)?;
+            return Ok(());
+        }
+        // Written alongside and renamed into place, so that the record survives the device
+        // being pulled out halfway through
+        let partial = self.folder.join(format!("{}.partial", KEPT_FILE));
+        {
+            let mut out = File::create(&partial)?;
+            for kept in files {
+                writeln!(out, "{}", serde_json::to_string(kept)?)?;
+            }
+        }
+        fs::rename(&partial, &path)?;
+        Ok(())
+    }
+}
+
+/// Remove the oldest files from the archive on the device mounted at `root`, until there are at
+/// least `min_free_space` bytes free.
+fn prune_archive<F>(root: &Path, min_free_space: u64, mut free_space: F) -> Result<usize, Error>
+where F: FnMut() -> io::Result<u64>,
+{
+    let archive = root.join(ARCHIVE_DIR);
+    if free_space()? >= min_free_space || !archive.exists() {
+        return Ok(0);
+    }
+
+    let mut archived = vec![];
+    for entry in WalkDir::new(&archive) {
+        let entry = entry?;
+        if entry.file_type().is_file() {
+            archived.push((entry.metadata()?.modified()?, entry.path().to_path_buf()));
+        }
+    }
+    archived.sort();
+
+    let mut pruned = 0;
+    for (_, path) in archived {
+        if free_space()? >= min_free_space {
+            break;
+        }
+        info!("Pruning {:?} to free up space", &path);
+        fs::remove_file(&path)?;
+        pruned += 1;
+    }
+    Ok(pruned)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+    use filetime::{self, FileTime};
+
+    use std::fs::File;
+    use std::io::Write;
+
+    fn archive_policy() -> AfterStaging {
+        AfterStaging::Archive { min_free_space: None }
+    }
+
+    #[test]
+    fn test_archives_files() {
+        let root = test_helpers::tempdir();
+        let path = root.path().join("DCIM/100GOPRO/GOPR0042.MP4");
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        File::create(&path).unwrap();
+
+        let retention = Retention::new(&archive_policy(), root.path(), &root.path().join("archiver"));
+        retention.dispose(&path).unwrap();
+        assert!(!path.exists());
+        assert!(root.path().join("archived/DCIM/100GOPRO/GOPR0042.MP4").exists());
+    }
+
+    #[test]
+    fn test_keeps_recent_files() {
+        let root = test_helpers::tempdir();
+        let recent = root.path().join("recent.mp4");
+        let old = root.path().join("old.mp4");
+        File::create(&recent).unwrap();
+        File::create(&old).unwrap();
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        let a_week_ago = FileTime::from_unix_time(now.as_secs() as i64 - 7 * 24 * 60 * 60, 0);
+        filetime::set_file_times(&old, a_week_ago, a_week_ago).unwrap();
+
+        let policy = AfterStaging::KeepRecent { days: 2 };
+        assert_eq!(disposal(&policy, &recent).unwrap(), Disposal::Keep);
+        assert_eq!(disposal(&policy, &old).unwrap(), Disposal::Delete);
+        let retention = Retention::new(&policy, root.path(), &root.path().join("archiver"));
+        retention.dispose(&recent).unwrap();
+        retention.dispose(&old).unwrap();
+        assert!(recent.exists());
+        assert!(!old.exists());
+
+        // The file we kept has been staged, so it shouldn't be staged again
+        let kept = retention.kept().unwrap();
+        assert!(kept.contains(&recent, &fs::metadata(&recent).unwrap()));
+        File::create(&recent).unwrap().write_all(b"a new video").unwrap();
+        assert!(!retention.kept().unwrap().contains(&recent, &fs::metadata(&recent).unwrap()));
+    }
+
+    #[test]
+    fn test_deletes_kept_files_once_they_are_old_enough() {
+        let root = test_helpers::tempdir();
+        let path = root.path().join("recent.mp4");
+        File::create(&path).unwrap();
+
+        let policy = AfterStaging::KeepRecent { days: 2 };
+        let retention = Retention::new(&policy, root.path(), &root.path().join("archiver"));
+        retention.dispose(&path).unwrap();
+        assert_eq!(retention.tidy().unwrap(), 0);
+        assert!(path.exists());
+
+        let now = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).unwrap();
+        let a_week_ago = FileTime::from_unix_time(now.as_secs() as i64 - 7 * 24 * 60 * 60, 0);
+        filetime::set_file_times(&path, a_week_ago, a_week_ago).unwrap();
+        // Touching the file makes it look like a different one, so record it again as it is now
+        retention.record_kept(&path).unwrap();
+
+        assert_eq!(retention.tidy().unwrap(), 1);
+        assert!(!path.exists());
+        assert!(!root.path().join("archiver").join(KEPT_FILE).exists());
+    }
+
+    #[test]
+    fn test_prunes_oldest_archived_files() {
+        let root = test_helpers::tempdir();
+        let archive = root.path().join(ARCHIVE_DIR);
+        fs::create_dir(&archive).unwrap();
+        for i in 0..4 {
+            let path = archive.join(format!("{}.mp4", i));
+            File::create(&path).unwrap().write_all(&[0; 10]).unwrap();
+            let mtime = FileTime::from_unix_time(1_500_000_000 + i, 0);
+            filetime::set_file_times(&path, mtime, mtime).unwrap();
+        }
+
+        // Pretend that the card has 100 bytes, of which the archive is the only thing using any.
+        let free_space = || -> io::Result<u64> {
+            let used: u64 = fs::read_dir(&archive)?
+                .map(|e| e.and_then(|e| e.metadata()).map(|m| m.len()))
+                .sum::<io::Result<u64>>()?;
+            Ok(100 - used)
+        };
+
+        assert_eq!(prune_archive(root.path(), 50, free_space).unwrap(), 0);
+        assert_eq!(prune_archive(root.path(), 80, free_space).unwrap(), 2);
+        assert!(!archive.join("0.mp4").exists());
+        assert!(!archive.join("1.mp4").exists());
+        assert!(archive.join("2.mp4").exists());
+        assert!(archive.join("3.mp4").exists());
+    }
+}
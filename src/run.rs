@@ -0,0 +1,128 @@
+use std::io::Write;
+
+use failure::Error;
+
+use crate::cancel::Cancel;
+use crate::ctx::Ctx;
+use crate::device::{self, Device};
+use crate::mailer::MailReport;
+use crate::mountable::Mountable;
+use crate::progress;
+use crate::reporting::UploadReport;
+use crate::staging::StageableLocation;
+use crate::storage;
+
+/// Perform a single run: discover the attached devices, stage from up to `concurrency` of them at
+/// once, and upload whatever was staged.
+///
+/// Nothing that goes wrong along the way stops the run. Failures are recorded as problems in the
+/// report that's returned, so that the `report` phase can tell someone about them. Stopping
+/// `cancel` does, once the files currently being staged or uploaded are done, and whatever was left
+/// to do is recorded as pending in the report.
+pub fn run(ctx: &Ctx, concurrency: usize, cancel: &Cancel) -> UploadReport {
+    let mut report = UploadReport::default();
+
+    let devices = discover(ctx, &mut report);
+
+    let staging = match ctx.staging().mount() {
+        Ok(staging) => staging,
+        Err(e) => {
+            error!("Couldn't mount staging: {:?}", e);
+            report.record_problem("staging".into(), format!("Couldn't mount: {}", e));
+            return report;
+        },
+    };
+    info!("Staging to {:?}", &staging);
+
+    stage(ctx, devices, &staging, concurrency, &mut report, cancel);
+    upload(ctx, &staging, &mut report, cancel);
+
+    report
+}
+
+/// Find the attached devices of every registered kind. A kind that can't look for its devices
+/// doesn't stop us looking for the others.
+pub fn discover<'a>(ctx: &'a Ctx, report: &mut UploadReport) -> Vec<Box<dyn Device + 'a>> {
+    let mut devices = vec![];
+    for kind in ctx.registry().kinds() {
+        match kind.attached(ctx) {
+            Ok(attached) => devices.extend(attached),
+            Err(e) => {
+                error!("Couldn't look for {} devices: {:?}", kind.name(), e);
+                report.record_problem(format!("{} devices", kind.name()), format!("Couldn't look for devices: {}", e));
+            },
+        }
+    }
+
+    info!("Attached devices:");
+    for device in &devices {
+        info!("  {:?}", device);
+    }
+    info!("");
+
+    devices
+}
+
+/// Stage from each of `devices`, recording how each of them went.
+pub fn stage(
+    ctx: &Ctx,
+    devices: Vec<Box<dyn Device + '_>>,
+    staging: &dyn StageableLocation,
+    concurrency: usize,
+    report: &mut UploadReport,
+    cancel: &Cancel,
+) {
+    for (name, outcome) in device::stage_concurrently(devices, staging, concurrency, cancel) {
+        if let Ok(ref outcome) = outcome {
+            if outcome.num_files > 0 {
+                if let Err(e) = ctx.notify(&format!("Finished staging: {}", &name)) {
+                    error!("Failed to send push notification: {:?}", e);
+                }
+            }
+        }
+        report.record_staging(&name, outcome);
+    }
+}
+
+/// Upload everything in `staging` to each of the configured backends.
+pub fn upload(ctx: &Ctx, staging: &dyn StageableLocation, report: &mut UploadReport, cancel: &Cancel) {
+    let backends = ctx.cfg.backends();
+    info!("Configured backends:");
+    for backend in &backends {
+        info!("  {:?}", backend);
+    }
+    info!("");
+
+    if let Err(e) = storage::upload_staged(staging, &backends, report, cancel) {
+        error!("Couldn't upload from staging: {:?}", e);
+        report.record_problem("staging".into(), format!("Couldn't upload: {}", e));
+    }
+}
+
+/// Let everyone know how the run went. The report is mailed if anything was uploaded, if anything
+/// went wrong, or if the run was cancelled with work left to do.
+pub fn report(ctx: &Ctx, report: &UploadReport) -> Result<(), Error> {
+    if report.num_uploads() > 0 {
+        if let Err(e) = ctx.notify("Finished uploading media") {
+            error!("Failed to send push notification: {:?}", e);
+        }
+    }
+    if report.has_problems() {
+        if let Err(e) = ctx.notify("Archiver ran into problems, check the report") {
+            error!("Failed to send push notification: {:?}", e);
+        }
+    }
+
+    let plaintext = report.to_plaintext()?;
+    writeln!(progress::report_output(), "{}", plaintext)?;
+
+    if report.num_uploads() > 0 || report.has_problems() || report.has_pending() {
+        if let Err(e) = ctx.mailer.send_report(&plaintext) {
+            error!("Failed to send upload report: {:?}", e);
+        }
+    } else {
+        info!("Not mailing report as no work was scheduled");
+    }
+
+    Ok(())
+}
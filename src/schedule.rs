@@ -0,0 +1,77 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::prelude::*;
+use cron;
+use humantime;
+
+/// When the daemon should run a full cycle of staging and uploading, on top of the ones it runs
+/// whenever a device is attached.
+#[derive(Debug, Clone)]
+pub enum Schedule {
+    /// Run once every so often, counting from when the last run started.
+    Interval(Duration),
+    /// Run whenever a cron expression matches.
+    Cron(cron::Schedule),
+}
+
+impl Schedule {
+    /// Parse an interval like `1h 30m`.
+    pub fn interval(interval: &str) -> Result<Schedule, String> {
+        match humantime::parse_duration(interval) {
+            Ok(ref duration) if *duration == Duration::from_secs(0) => Err("The interval can't be zero".to_string()),
+            Ok(duration) => Ok(Schedule::Interval(duration)),
+            Err(e) => Err(e.to_string()),
+        }
+    }
+
+    /// Parse a cron expression. Both the five field expressions that crontab uses, and ones with
+    /// an extra leading field for seconds, are accepted.
+    pub fn cron(expression: &str) -> Result<Schedule, String> {
+        let expression = if expression.split_whitespace().count() == 5 {
+            format!("0 {}", expression)
+        } else {
+            expression.to_string()
+        };
+        cron::Schedule::from_str(&expression)
+            .map(Schedule::Cron)
+            .map_err(|e| e.to_string())
+    }
+
+    /// When the next run is due, given that the last one started at `last`.
+    pub fn next_after(&self, last: DateTime<Local>) -> Option<DateTime<Local>> {
+        match self {
+            Schedule::Interval(interval) => {
+                chrono::Duration::from_std(*interval).ok().map(|interval| last + interval)
+            },
+            Schedule::Cron(schedule) => schedule.after(&last).next(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_schedules_intervals() {
+        let schedule = Schedule::interval("1h 30m").unwrap();
+        let last = Local.ymd(2019, 4, 20).and_hms(10, 0, 0);
+        assert_eq!(schedule.next_after(last), Some(Local.ymd(2019, 4, 20).and_hms(11, 30, 0)));
+
+        assert!(Schedule::interval("0s").is_err());
+        assert!(Schedule::interval("every now and then").is_err());
+    }
+
+    #[test]
+    fn test_schedules_cron_expressions() {
+        let schedule = Schedule::cron("15 */6 * * *").unwrap();
+        let last = Local.ymd(2019, 4, 20).and_hms(10, 0, 0);
+        assert_eq!(schedule.next_after(last), Some(Local.ymd(2019, 4, 20).and_hms(12, 15, 0)));
+
+        let schedule = Schedule::cron("30 15 */6 * * *").unwrap();
+        assert_eq!(schedule.next_after(last), Some(Local.ymd(2019, 4, 20).and_hms(12, 15, 30)));
+
+        assert!(Schedule::cron("whenever").is_err());
+    }
+}
@@ -0,0 +1,122 @@
+use std::fs;
+use std::path::Path;
+
+use failure::Error;
+use hex;
+use sha2::{Digest, Sha256};
+
+use crate::staging::{StageableLocation, StagedFile, UploadDescriptor};
+
+/// How many characters of a staged file's id we show. Any unique prefix can be used to refer to it.
+const ID_LENGTH: usize = 8;
+
+/// A file waiting in staging, along with the id people can use to refer to it.
+#[derive(Debug)]
+pub struct StagedEntry {
+    pub id: String,
+    pub file: StagedFile,
+    pub manifest: UploadDescriptor,
+}
+
+impl StagedEntry {
+    /// The start of the id, which is what we show in listings.
+    pub fn short_id(&self) -> &str {
+        &self.id[..ID_LENGTH]
+    }
+}
+
+/// The id of a staged file. Content hashes aren't unique in staging, the same flysight config can
+/// be staged from several flysights, so ids come from the name a file was staged under instead.
+fn id(manifest: &UploadDescriptor) -> String {
+    let digest = Sha256::digest(manifest.staging_name().as_bytes());
+    hex::encode(&digest[..])
+}
+
+/// Everything waiting in `staging`, oldest first.
+pub fn entries(staging: &dyn StageableLocation) -> Result<Vec<StagedEntry>, Error> {
+    let mut entries = vec![];
+    for (file, manifest) in staging.staged_files()? {
+        let staged_at = file.staged_at()?;
+        let id = id(&manifest);
+        entries.push((staged_at, StagedEntry { id, file, manifest }));
+    }
+    entries.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.id.cmp(&b.1.id)));
+    Ok(entries.into_iter().map(|(_, entry)| entry).collect())
+}
+
+/// Find the file in `staging` that `id` refers to.
+pub fn find(staging: &dyn StageableLocation, id: &str) -> Result<StagedEntry, Error> {
+    if id.is_empty() || !id.chars().all(|c| c.is_ascii_hexdigit()) {
+        bail!("{:?} isn't an id, they look like the ones in `archiver staging list`", id);
+    }
+    let mut found: Vec<_> = entries(staging)?
+        .into_iter()
+        .filter(|entry| entry.id.starts_with(id))
+        .collect();
+    match found.len() {
+        0 => bail!("Nothing in staging has the id {}", id),
+        1 => Ok(found.remove(0)),
+        n => bail!("{} files in staging have ids starting with {}", n, id),
+    }
+}
+
+/// Copy a staged file's content out of staging, to `path`.
+///
+/// Returns the number of bytes copied.
+pub fn export(entry: &StagedEntry, path: &Path) -> Result<u64, Error> {
+    if path.exists() {
+        bail!("{:?} already exists", path);
+    }
+    Ok(fs::copy(&entry.file.content_path, path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_helpers;
+
+    #[test]
+    fn test_finds_entries_by_id_prefix() {
+        let data = test_helpers::staged_data(3).expect("Couldn't create staging data");
+        let entries = entries(&data).unwrap();
+        assert_eq!(entries.len(), 3);
+        assert!(entries.iter().all(|entry| entry.short_id().len() == ID_LENGTH));
+
+        let entry = &entries[1];
+        let found = find(&data, &entry.id[..6]).unwrap();
+        assert_eq!(found.manifest, entry.manifest);
+        // Prefixes longer than what we show still work, right up to the whole id
+        let found = find(&data, &entry.id[..ID_LENGTH + 4]).unwrap();
+        assert_eq!(found.manifest, entry.manifest);
+        let found = find(&data, &entry.id).unwrap();
+        assert_eq!(found.manifest, entry.manifest);
+
+        assert!(find(&data, "").is_err());
+        assert!(find(&data, "not-an-id").is_err());
+    }
+
+    #[test]
+    fn test_rejects_ids_that_would_match_anything() {
+        let data = test_helpers::staged_data(1).expect("Couldn't create staging data");
+        let entry = entries(&data).unwrap().remove(0);
+
+        assert!(find(&data, "").is_err());
+        assert!(find(&data, &format!("{}*", &entry.id[..4])).is_err());
+        assert!(find(&data, &entry.id[..4]).is_ok());
+    }
+
+    #[test]
+    fn test_exports_and_verifies_staged_files() {
+        let data = test_helpers::staged_data(1).expect("Couldn't create staging data");
+        let dest = test_helpers::tempdir();
+        let entry = entries(&data).unwrap().remove(0);
+
+        assert!(entry.file.verify(&entry.manifest).unwrap());
+        let path = dest.path().join("exported");
+        assert_eq!(export(&entry, &path).unwrap(), entry.manifest.size);
+        assert!(export(&entry, &path).is_err());
+
+        fs::write(&entry.file.content_path, b"Something else entirely").unwrap();
+        assert!(!entry.file.verify(&entry.manifest).unwrap());
+    }
+}
@@ -1,19 +1,34 @@
+use std::collections::HashSet;
 use std::path::{Path, PathBuf};
 use std::fmt::Debug;
 use std::fs::{self, File};
 use std::io::{self, Read};
+use std::time::SystemTime;
 
 use chrono;
 use chrono::prelude::*;
-use dropbox_content_hasher::DropboxContentHasher;
+use digest::Digest;
+use dropbox_content_hasher::{self, DropboxContentHasher};
 use crate::formatting;
 use failure::Error;
 use hashing_copy;
 use serde::{Deserialize, Serialize};
 use serde_json;
 
-use crate::config::{MountableDeviceLocation, StagingConfig};
+use crate::cancel::Cancel;
+use crate::config::{DeviceAction, MountableDeviceLocation, Mounter, StagingConfig};
+use crate::flysight::JumpSummary;
 use crate::mountable::{MountedFilesystem, MountableFilesystem, MountableKind};
+use crate::progress::{self, Phase};
+
+/// What will become of a file on a device once it's been staged.
+#[derive(Eq, PartialEq, Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Disposal {
+    Delete,
+    Archive,
+    Keep,
+}
 
 #[derive(Eq, PartialEq, Debug, Serialize, Deserialize)]
 pub enum RemotePathDescriptor {
@@ -24,6 +39,20 @@ pub enum RemotePathDescriptor {
     SpecifiedPath {
         path: PathBuf,
     },
+    /// One chapter of a recording that the device split across several files. Every chapter
+    /// shares the capture time of the recording, so that they end up alongside one another.
+    Chapter {
+        capture_time: DateTime<Local>,
+        extension: String,
+        chapter: u16,
+    },
+    /// A file that shares its capture time with other files from the same device, like the frames
+    /// of a burst, told apart by the name the device gave it.
+    Named {
+        capture_time: DateTime<Local>,
+        name: String,
+        extension: String,
+    },
 }
 
 impl MountableFilesystem for StagingConfig {
@@ -32,6 +61,10 @@ impl MountableFilesystem for StagingConfig {
     fn location(&self) -> &MountableDeviceLocation {
         &self.location
     }
+
+    fn mounter(&self) -> Option<Mounter> {
+        self.mounter
+    }
 }
 
 impl MountableKind for MountedStaging {
@@ -69,12 +102,23 @@ pub trait UploadableFile {
     fn size(&self) -> Result<u64, Error>;
     fn reader(&mut self) -> &mut Self::Reader;
 
+    /// A summary of the jump recorded in this file, for devices that record jumps.
+    fn jump_summary(&self) -> Option<JumpSummary> {
+        None
+    }
+
+    /// What `delete` will do with this file.
+    fn disposal(&self) -> Result<Disposal, Error> {
+        Ok(Disposal::Delete)
+    }
+
     fn descriptor(&self, name: &str) -> Result<UploadDescriptor, Error> {
         Ok(UploadDescriptor {
             path: self.remote_path()?,
             content_hash: [0; 32],
             device_name: name.to_string(),
             size: self.size()?,
+            jump_summary: self.jump_summary(),
         })
     }
 }
@@ -94,6 +138,14 @@ pub trait DateTimeUploadable {
     fn delete(&mut self) -> Result<(), Error>;
     fn size(&self) -> Result<u64, Error>;
     fn reader(&mut self) -> &mut Self::Reader;
+
+    fn jump_summary(&self) -> Option<JumpSummary> {
+        None
+    }
+
+    fn disposal(&self) -> Result<Disposal, Error> {
+        Ok(Disposal::Delete)
+    }
 }
 
 impl<T> UploadableFile for T where T: DateTimeUploadable {
@@ -111,9 +163,79 @@ impl<T> UploadableFile for T where T: DateTimeUploadable {
     fn reader(&mut self) -> &mut Self::Reader {
         self.reader()
     }
+    fn jump_summary(&self) -> Option<JumpSummary> {
+        self.jump_summary()
+    }
+    fn disposal(&self) -> Result<Disposal, Error> {
+        self.disposal()
+    }
+}
+
+/// Hash everything left in `reader` the same way it's hashed as it's staged.
+fn content_hash<R: Read>(reader: &mut R) -> Result<[u8; 32], io::Error> {
+    let mut hasher: DropboxContentHasher = Default::default();
+    let mut buf = vec![0; dropbox_content_hasher::BLOCK_SIZE];
+    loop {
+        let len = reader.read(&mut buf)?;
+        if len == 0 { break; }
+        hasher.input(&buf[..len]);
+    }
+    let mut hash = [0; 32];
+    hash.copy_from_slice(hasher.result().as_slice());
+    Ok(hash)
+}
+
+/// A file that would be staged, and what would become of it on the device afterwards.
+#[derive(Debug)]
+pub struct PlannedFile {
+    pub descriptor: UploadDescriptor,
+    pub disposal: Disposal,
+    /// Whether the descriptor's content hash is known. Backends need it to tell whether they
+    /// already have the file.
+    pub hashed: bool,
+}
+
+/// Work out everything that `stage_file` would, without staging or deleting anything.
+///
+/// Unless `hash` is set the file isn't read, so its content hash is left unknown. Reading every
+/// file on a card can take as long as staging it.
+pub fn plan_file<T>(mut file: T, name: &str, hash: bool) -> Result<PlannedFile, Error>
+where T: UploadableFile,
+{
+    let mut descriptor = file.descriptor(name)?;
+
+    if hash {
+        descriptor.content_hash = content_hash(file.reader())?;
+    }
+
+    Ok(PlannedFile {
+        descriptor,
+        disposal: file.disposal()?,
+        hashed: hash,
+    })
+}
+
+/// Like `stage_file`, except that being aborted isn't an error. Returns whether the file was
+/// staged, so that callers can stop there and report how many files they got through.
+pub fn stage_file_unless_aborted<T, U>(file: T, destination: &U, name: &str, cancel: &Cancel) -> Result<bool, Error>
+where T: UploadableFile,
+      U: StageableLocation,
+{
+    match stage_file(file, destination, name, cancel) {
+        Ok(()) => Ok(true),
+        Err(e) if cancel.is_aborting() => {
+            info!("Aborted staging from {}: {}", name, e);
+            Ok(false)
+        },
+        Err(e) => Err(e),
+    }
 }
 
-pub fn stage_file<T, U>(mut file: T, destination: &U, name: &str) -> Result<(), Error>
+/// Stage `file` to `destination`, deleting it from its device once it's staged.
+///
+/// If the copy fails, including because `cancel` was aborted partway through it, the partial copy
+/// is removed rather than left in staging.
+pub fn stage_file<T, U>(mut file: T, destination: &U, name: &str, cancel: &Cancel) -> Result<(), Error>
 where T: UploadableFile,
       U: StageableLocation,
 {
@@ -131,10 +253,21 @@ where T: UploadableFile,
     info!("Staging {} to {:?}", &staging_name, &staging_path);
     {
         let mut staged = options.open(&staging_path)?;
-        let (size, hash) = hashing_copy::copy_and_hash::<_, _, DropboxContentHasher>(
-            file.reader(),
+        let mut transfer = progress::start(Phase::Staging, name, &desc.remote_path().display().to_string(), desc.size);
+        let copied = hashing_copy::copy_and_hash::<_, _, DropboxContentHasher>(
+            &mut transfer.reader(cancel.reader(file.reader())),
             &mut staged,
-            )?;
+            );
+        transfer.finish(copied.is_ok());
+        let (size, hash) = match copied {
+            Ok(copied) => copied,
+            Err(e) => {
+                if let Err(e) = fs::remove_file(&staging_path) {
+                    error!("Couldn't remove partially staged {:?}: {:?}", &staging_path, e);
+                }
+                return Err(e.into());
+            },
+        };
         assert_eq!(size, desc.size);
         desc.content_hash.copy_from_slice(&hash);
         info!("Staged {}: shasum={:x} size={}", &staging_name, &hash, formatting::human_readable_size(size as usize));
@@ -143,8 +276,12 @@ where T: UploadableFile,
     {
         info!("Manifesting {}", &manifest_name);
         trace!(" To {:?}", manifest_path);
-        let mut staged = options.open(&manifest_path)?;
+        // Files are only picked up for uploading once they have a manifest, so it's written in
+        // full before being moved into place, so that an upload never sees half of one.
+        let partial_path = destination.path_for_name(&format!("{}.partial", manifest_name));
+        let mut staged = options.open(&partial_path)?;
         serde_json::to_writer(&mut staged, &desc)?;
+        fs::rename(&partial_path, &manifest_path)?;
     }
 
     file.delete()?;
@@ -223,6 +360,15 @@ impl<T: StageableLocation> StageableLocation for Box<T> {
     }
 }
 
+/// An attempt to upload a staged file that didn't work out. The errors from the last attempt are
+/// kept alongside the file, so that whoever goes looking at what's stuck in staging can see why.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct UploadError {
+    pub backend: String,
+    pub error: String,
+    pub time: DateTime<Local>,
+}
+
 #[derive(Debug)]
 pub struct StagedFile {
     pub content_path: PathBuf,
@@ -235,12 +381,51 @@ impl StagedFile {
         fs::remove_file(&self.manifest_path)?;
         info!("removing {:?}", &self.content_path);
         fs::remove_file(&self.content_path)?;
-        Ok(())
+        match fs::remove_file(self.errors_path()) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            other => other,
+        }
     }
 
     pub fn content_handle(&self) -> Result<File, io::Error> {
         File::open(&self.content_path)
     }
+
+    pub fn manifest_path(&self) -> &Path {
+        &self.manifest_path
+    }
+
+    /// When this file was staged.
+    pub fn staged_at(&self) -> Result<SystemTime, io::Error> {
+        fs::metadata(&self.manifest_path)?.modified()
+    }
+
+    fn errors_path(&self) -> PathBuf {
+        let mut path = self.content_path.clone().into_os_string();
+        path.push(".errors");
+        path.into()
+    }
+
+    /// The errors from the last time we failed to upload this file, if we have.
+    pub fn upload_errors(&self) -> Result<Vec<UploadError>, Error> {
+        match File::open(self.errors_path()) {
+            Ok(file) => Ok(serde_json::from_reader(file)?),
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(vec![]),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Replace the errors kept for this file with those from the latest attempt to upload it.
+    pub fn record_upload_errors(&self, errors: &[UploadError]) -> Result<(), Error> {
+        let file = File::create(self.errors_path())?;
+        serde_json::to_writer(file, errors)?;
+        Ok(())
+    }
+
+    /// Hash the content of this file again, and check that it still matches `manifest`.
+    pub fn verify(&self, manifest: &UploadDescriptor) -> Result<bool, Error> {
+        Ok(content_hash(&mut self.content_handle()?)? == manifest.content_hash)
+    }
 }
 
 #[derive(Fail, Debug)]
@@ -277,21 +462,63 @@ pub trait Staging: Sized {
     fn files(&self) -> Result<Vec<Self::FileType>, Error>;
 
     /// Stage all available files on this device, erasing the device copies as they are staged.
+    /// If `cancel` is stopped, we stop before the next file, and the device isn't tidied up. If
+    /// it's aborted, we stop partway through the current file.
+    ///
+    /// Returns the number of files staged, including when we stopped early.
     ///
-    /// Returns the number of files staged.
-    fn stage_files<T>(self, name: &str, destination: &T) -> Result<usize, Error>
+    /// A file that would be staged under the same name as one we've already staged is left on
+    /// the device, rather than overwriting the staged copy and then deleting both originals.
+    fn stage_files<T>(&self, name: &str, destination: &T, cancel: &Cancel) -> Result<usize, Error>
     where
         T: StageableLocation,
     {
         let mut i = 0;
+        let mut staged = HashSet::new();
 
         for file in self.files()? {
-            stage_file(file, destination, name)?;
+            if cancel.is_stopping() {
+                info!("Stopping with {} files staged from {}", i, name);
+                return Ok(i);
+            }
+            let desc = file.descriptor(name)?;
+            if !staged.insert(desc.staging_name()) {
+                error!(
+                    "Not staging {} from {}, another file was already staged as {}",
+                    desc.remote_path().display(), name, desc.staging_name(),
+                );
+                continue;
+            }
+            if !stage_file_unless_aborted(file, destination, name, cancel)? {
+                return Ok(i);
+            }
             i += 1;
         }
+        self.tidy_up()?;
 
         Ok(i)
     }
+
+    /// Plan staging all available files on this device, without staging or erasing anything,
+    /// reading each of them to find its content hash if `hash` is set.
+    fn plan_files(&self, name: &str, hash: bool) -> Result<Vec<PlannedFile>, Error> {
+        self.files()?
+            .into_iter()
+            .map(|file| plan_file(file, name, hash))
+            .collect()
+    }
+
+    /// Tidy up this device once all of its files have been staged.
+    fn tidy_up(&self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    /// Perform an action on this device once its files have been staged.
+    ///
+    /// Returns a short description of what was done, for the report.
+    fn perform_action(&mut self, action: DeviceAction) -> Result<String, Error> {
+        bail!("{} is not supported by this device", action)
+    }
 }
 
 #[derive(PartialEq, Debug, Serialize, Deserialize)]
@@ -300,6 +527,8 @@ pub struct UploadDescriptor {
     pub device_name: String,
     pub content_hash: [u8; 32],
     pub size: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub jump_summary: Option<JumpSummary>,
 }
 
 #[derive(Debug)]
@@ -317,6 +546,21 @@ impl UploadDescriptorBuilder {
             content_hash: Default::default(),
             device_name: self.device_name,
             size: 0,
+            jump_summary: None,
+        }
+    }
+
+    pub fn chapter(self, capture_time: DateTime<Local>, extension: String, chapter: u16) -> UploadDescriptor {
+        UploadDescriptor {
+            path: RemotePathDescriptor::Chapter {
+                capture_time,
+                extension,
+                chapter,
+            },
+            content_hash: Default::default(),
+            device_name: self.device_name,
+            size: 0,
+            jump_summary: None,
         }
     }
 
@@ -328,6 +572,7 @@ impl UploadDescriptorBuilder {
             content_hash: Default::default(),
             device_name: self.device_name,
             size: 0,
+            jump_summary: None,
         }
     }
 }
@@ -357,7 +602,23 @@ impl UploadDescriptor {
                     &self.device_name,
                     path.to_str().expect("path wasn't valid utf8").replace("/", "-"),
                 )
-            }
+            },
+            RemotePathDescriptor::Chapter {
+                capture_time, extension, chapter
+            } => {
+                format!(
+                    "{}-{}-ch{:02}.{}",
+                    &self.device_name, capture_time, chapter, extension
+                )
+            },
+            RemotePathDescriptor::Named {
+                capture_time, name, extension
+            } => {
+                format!(
+                    "{}-{}-{}.{}",
+                    &self.device_name, capture_time, name, extension
+                )
+            },
         }
     }
 
@@ -386,7 +647,52 @@ impl UploadDescriptor {
                 assert!(!path.is_absolute());
                 buf.extend(path);
                 buf
-            }
+            },
+            RemotePathDescriptor::Chapter {
+                capture_time, extension, chapter,
+            } => {
+                format!(
+                    "/{}/{}/{}-ch{:02}.{}",
+                    capture_time.format("%y-%m-%d"),
+                    &self.device_name,
+                    capture_time.format("%H-%M-%S"),
+                    chapter,
+                    extension,
+                ).into()
+            },
+            RemotePathDescriptor::Named {
+                capture_time, name, extension,
+            } => {
+                format!(
+                    "/{}/{}/{}-{}.{}",
+                    capture_time.format("%y-%m-%d"),
+                    &self.device_name,
+                    capture_time.format("%H-%M-%S"),
+                    name,
+                    extension,
+                ).into()
+            },
+        }
+    }
+
+    /// The path of the recording that this file is a part of, if it is one of several files that
+    /// make up that recording.
+    pub fn recording_path(&self) -> Option<PathBuf> {
+        match &self.path {
+            RemotePathDescriptor::Chapter {
+                capture_time, extension, ..
+            } => {
+                Some(format!(
+                    "/{}/{}/{}.{}",
+                    capture_time.format("%y-%m-%d"),
+                    &self.device_name,
+                    capture_time.format("%H-%M-%S"),
+                    extension,
+                ).into())
+            },
+            RemotePathDescriptor::DateTime { .. } |
+            RemotePathDescriptor::SpecifiedPath { .. } |
+            RemotePathDescriptor::Named { .. } => None,
         }
     }
 
@@ -400,6 +706,7 @@ impl UploadDescriptor {
             device_name: "test-device".into(),
             content_hash: Default::default(),
             size: 1024,
+            jump_summary: None,
         }
     }
 }
@@ -428,6 +735,7 @@ fn content_path_from_manifest(manifest: &Path) -> PathBuf {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::test_helpers::{self, DummyDataDevice};
 
     #[test]
     fn test_formats_correctly() {
@@ -441,6 +749,7 @@ mod tests {
             device_name: "test".to_string(),
             content_hash: [0; 32],
             size: 0,
+            jump_summary: None,
         };
 
         assert_eq!(
@@ -461,6 +770,7 @@ mod tests {
             device_name: "test".to_string(),
             content_hash: [0; 32],
             size: 0,
+            jump_summary: None,
         };
 
         assert_eq!(
@@ -469,6 +779,32 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_formats_chapters_correctly() {
+        let datetime = Local.ymd(2017, 11, 22).and_hms(15, 36, 10);
+
+        let upload = UploadDescriptor {
+            path: RemotePathDescriptor::Chapter {
+                capture_time: datetime,
+                extension: "mp4".to_string(),
+                chapter: 2,
+            },
+            device_name: "test".to_string(),
+            content_hash: [0; 32],
+            size: 0,
+            jump_summary: None,
+        };
+
+        assert_eq!(
+            upload.remote_path(),
+            PathBuf::from("/17-11-22/test/15-36-10-ch02.mp4".to_string())
+        );
+        assert_eq!(
+            upload.recording_path(),
+            Some(PathBuf::from("/17-11-22/test/15-36-10.mp4".to_string()))
+        );
+    }
+
     #[test]
     fn test_uploaddescriptor_roundtrips_serializtion() {
         let datetime = Local.ymd(2001, 1, 2).and_hms(3, 4, 5);
@@ -481,6 +817,7 @@ mod tests {
             device_name: "test".to_string(),
             content_hash: [0; 32],
             size: 0,
+            jump_summary: None,
         };
 
         let serialized = serde_json::to_string(&original).expect("Couldn't serialize test vector");
@@ -489,6 +826,38 @@ mod tests {
         assert_eq!(&original, &hydrated);
     }
 
+    #[test]
+    fn test_formats_named_files_correctly() {
+        let datetime = Local.ymd(2017, 11, 22).and_hms(15, 36, 10);
+
+        let upload = UploadDescriptor {
+            path: RemotePathDescriptor::Named {
+                capture_time: datetime,
+                name: "DSC_0042".to_string(),
+                extension: "jpg".to_string(),
+            },
+            device_name: "test".to_string(),
+            content_hash: [0; 32],
+            size: 0,
+            jump_summary: None,
+        };
+
+        assert_eq!(
+            upload.remote_path(),
+            PathBuf::from("/17-11-22/test/15-36-10-DSC_0042.jpg".to_string())
+        );
+        assert_eq!(upload.recording_path(), None);
+    }
+
+    #[test]
+    fn test_refuses_to_stage_two_files_under_one_name() {
+        let device = DummyDataDevice::captured_at(2, Local.ymd(2019, 3, 2).and_hms(14, 21, 7));
+        let dest = test_helpers::tempdir();
+
+        assert_eq!(device.stage_files("dummy", &dest, &Cancel::new()).unwrap(), 1);
+        assert_eq!(fs::read_dir(dest.path()).unwrap().count(), 2);
+    }
+
     #[test]
     fn test_absolute_manifest_conversion() {
         let manifest = Path::new("/tmp/foo/bar/butts.manifest");
@@ -555,7 +924,7 @@ impl StageableLocation for tempfile::TempDir {
     }
 }
 
-impl<T> StageableLocation for &T where T: StageableLocation {
+impl<T> StageableLocation for &T where T: StageableLocation + ?Sized {
     fn relative_path(&self, path: &Path) -> PathBuf {
         (*self).relative_path(path)
     }
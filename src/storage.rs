@@ -1,20 +1,31 @@
 use std::fmt::Debug;
 use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 
+use crate::cancel::{Abortable, Cancel};
+use crate::progress::{self, Phase};
 use crate::reporting::{ReportEntry, UploadReport, UploadStatus};
-use crate::staging::{self, StageableLocation};
+use crate::staging::{self, StageableLocation, StagedFile, UploadError};
 use crate::formatting;
 
 
 use failure::Error;
 use chrono::prelude::*;
+use crossbeam_utils::thread;
 
 const MAX_RETRIES: usize = 3;
+/// How often to check how far through a file an upload has got.
+const PROGRESS_POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+/// What storage adaptors read staged files through. Reads fail once the upload is aborted.
+pub type StagedReader = Abortable<File>;
 
 #[derive(Debug)]
 pub struct MaybeStorageAdaptor {
     name: String,
-    adaptor: Result<Box<dyn StorageAdaptor<File>>, Error>,
+    adaptor: Result<Box<dyn StorageAdaptor<StagedReader>>, Error>,
 }
 
 impl MaybeStorageAdaptor {
@@ -22,13 +33,13 @@ impl MaybeStorageAdaptor {
         &self.name
     }
 
-    pub fn adaptor(&self) -> &Result<Box<dyn StorageAdaptor<File>>, Error> {
+    pub fn adaptor(&self) -> &Result<Box<dyn StorageAdaptor<StagedReader>>, Error> {
         &self.adaptor
     }
 
     #[allow(non_snake_case)]
     pub fn Ok<T>(adaptor: T) -> MaybeStorageAdaptor
-    where T: 'static + StorageAdaptor<File> {
+    where T: 'static + StorageAdaptor<StagedReader> {
         MaybeStorageAdaptor {
             name: adaptor.name(),
             adaptor: Ok(Box::new(adaptor)),
@@ -69,70 +80,204 @@ pub fn upload_from_staged(
     adaptors: &[MaybeStorageAdaptor],
 ) -> Result<UploadReport, Error> {
     let mut report: UploadReport = Default::default();
+    upload_staged(staged, adaptors, &mut report, &Cancel::new())?;
+    Ok(report)
+}
+
+/// Upload everything in `staged` to each of `adaptors`, recording how it went in `report`.
+///
+/// Backends that couldn't be set up, uploads that failed, and files that couldn't be removed from
+/// staging afterwards are recorded as problems, and don't stop the rest being uploaded. If `cancel`
+/// is stopped, we stop before the next file and leave the rest in staging for next time, recording
+/// them as pending.
+pub fn upload_staged(
+    staged: &dyn StageableLocation,
+    adaptors: &[MaybeStorageAdaptor],
+    report: &mut UploadReport,
+    cancel: &Cancel,
+) -> Result<(), Error> {
+    for ad in adaptors {
+        if let Err(e) = ad.adaptor() {
+            report.record_problem(format!("backend {}", ad.name()), format!("Couldn't set up: {}", e));
+        }
+    }
+
     info!("Starting upload from {:?}", &staged);
-    for (staged_file, manifest) in staged.staged_files()? {
-
-        let results: Vec<_> = adaptors
-            .iter()
-            .map(|ad| {
-                // Does it actually make sense to use Errored when it was a mount failure?
-                // dunno but we're doing it.
-                let ad = match ad.adaptor() {
-                    Ok(ad) => ad,
-                    // TODO(richo) throwing away the info with format_err is a little blunt
-                    Err(e) => return (ad.name().to_string(), UploadStatus::Errored(format_err!("Failed to get adaptor: {:?}", e))),
-                };
+    let mut staged_files = staged.staged_files()?.into_iter();
+    for (staged_file, manifest) in &mut staged_files {
+        if cancel.is_stopping() {
+            info!("Stopping, leaving the rest of {:?} staged", &staged);
+            report.record_pending(pending_upload(&manifest));
+            break;
+        }
+        upload_file(staged_file, manifest, adaptors, report, cancel);
+    }
+    for (_, manifest) in staged_files {
+        report.record_pending(pending_upload(&manifest));
+    }
+    Ok(())
+}
+
+fn pending_upload(manifest: &staging::UploadDescriptor) -> String {
+    format!(
+        "staged {} ({}b): Not uploaded yet",
+        manifest.remote_path().display(),
+        formatting::human_readable_size(manifest.size as usize),
+    )
+}
+
+/// Upload a single staged file to each of `adaptors`, removing it from staging if every one of
+/// them has it afterwards. Otherwise the errors are kept alongside it, for `archiver staging`.
+/// Anything that goes wrong is recorded in `report`.
+///
+/// Aborting `cancel` cuts the current upload short, and it isn't retried.
+pub fn upload_file(
+    staged_file: StagedFile,
+    manifest: staging::UploadDescriptor,
+    adaptors: &[MaybeStorageAdaptor],
+    report: &mut UploadReport,
+    cancel: &Cancel,
+) {
+    let mut problems = vec![];
+    let mut errors = vec![];
+
+    let results: Vec<_> = adaptors
+        .iter()
+        .map(|ad| {
+            // Does it actually make sense to use Errored when it was a mount failure?
+            // dunno but we're doing it.
+            let ad = match ad.adaptor() {
+                Ok(ad) => ad,
+                // TODO(richo) throwing away the info with format_err is a little blunt
+                Err(e) => {
+                    errors.push(UploadError {
+                        backend: ad.name().to_string(),
+                        error: format!("Couldn't set up: {}", e),
+                        time: Local::now(),
+                    });
+                    return (ad.name().to_string(), UploadStatus::Errored(format_err!("Failed to get adaptor: {:?}", e)));
+                },
+            };
+
+            let start = Utc::now();
+            info!("Starting {} adaptor for {:?}", ad.name(), &staged_file.content_path);
+            info!("Checking if file already exists");
+            if ad.already_uploaded(&manifest) {
+                info!("File was already uploaded - skipping");
+                return (ad.name(), UploadStatus::AlreadyUploaded);
+            }
 
-                let start = Utc::now();
-                info!("Starting {} adaptor for {:?}", ad.name(), &staged_file.content_path);
-                info!("Checking if file already exists");
-                if ad.already_uploaded(&manifest) {
-                    info!("File was already uploaded - skipping");
-                    return (ad.name(), UploadStatus::AlreadyUploaded);
+            info!("File not present upstream - beginning upload");
+            // I have no idea how bad it is to lie about the adaptor name here
+            // We have inverted the sense of "success" and "failure" from try_for_each
+            let result = (0..MAX_RETRIES).try_fold(format_err!("dummy error"), |error, i| {
+                // Keep the last error rather than retrying once we're aborting
+                if i > 0 && cancel.is_aborting() {
+                    return Some(error);
+                }
+                let content = match staged_file.content_handle() {
+                    Ok(content) => content,
+                    Err(e) => return Some(e.into()),
+                };
+                match upload_with_progress(&**ad, content, &manifest, cancel) {
+                    Ok(_resp) => {
+                        let finish = Utc::now();
+                        info!("Upload succeeded in {}", formatting::human_readable_time(finish - start));
+                        // Returning Err short circuits the iterator
+                        None
+                    }
+                    Err(error) => {
+                        error!(
+                           "Attempt {} of upload of {:?} failed: {:?}",
+                            &i, &staged_file.content_path, &error
+                        );
+                        Some(error)
+                    }
                 }
+            });
+            // So we have to pull them apart to flip them
+            match result {
+                // The "ok" state means we fell all the way through
+                Some(err) => {
+                    errors.push(UploadError {
+                        backend: ad.name(),
+                        error: err.to_string(),
+                        time: Local::now(),
+                    });
+                    problems.push((
+                        format!("backend {}", ad.name()),
+                        format!("Couldn't upload {}: {}", manifest.remote_path().display(), err),
+                    ));
+                    (ad.name(), UploadStatus::Errored(err))
+                },
+                None => (ad.name(), UploadStatus::Succeeded),
+            }
+        })
+        .collect();
+
+    let remote_path = manifest.remote_path();
+    let entry = ReportEntry::new(manifest, results);
+    if entry.is_success() {
+        if let Err(e) = staged_file.delete() {
+            error!("Couldn't remove {} from staging: {:?}", remote_path.display(), e);
+            problems.push((
+                format!("staged {}", remote_path.display()),
+                format!("Uploaded, but couldn't remove it from staging: {}", e),
+            ));
+        }
+    } else {
+        info!("one or more adaptors failed, preserving {:?}", &staged_file);
+        if let Err(e) = staged_file.record_upload_errors(&errors) {
+            error!("Couldn't record upload errors for {:?}: {:?}", &staged_file, e);
+        }
+    }
+    report.record_activity(entry);
+    for (source, problem) in problems {
+        report.record_problem(source, problem);
+    }
+}
 
-                info!("File not present upstream - beginning upload");
-                // I have no idea how bad it is to lie about the adaptor name here
-                // We have inverted the sense of "success" and "failure" from try_for_each
-                let result = (0..MAX_RETRIES).try_fold(format_err!("dummy error"), |_, i| {
-                    let content = match staged_file.content_handle() {
-                        Ok(content) => content,
-                        Err(e) => return Some(e.into()),
-                    };
-                    match ad.upload(content, &manifest) {
-                        Ok(_resp) => {
-                            let finish = Utc::now();
-                            info!("Upload succeeded in {}", formatting::human_readable_time(finish - start));
-                            // Returning Err short circuits the iterator
-                            None
-                        }
-                        Err(error) => {
-                            error!(
-                               "Attempt {} of upload of {:?} failed: {:?}",
-                                &i, &staged_file.content_path, &error
-                            );
-                            Some(error)
-                        }
+/// Upload `content` with `adaptor`, reporting progress by watching how far through the file the
+/// adaptor has read. Adaptors read their files however they like, so that's the one thing we can
+/// see for all of them.
+fn upload_with_progress(
+    adaptor: &dyn StorageAdaptor<StagedReader>,
+    content: File,
+    manifest: &staging::UploadDescriptor,
+    cancel: &Cancel,
+) -> Result<StorageStatus, Error> {
+    let mut transfer = progress::start(
+        Phase::Uploading,
+        &adaptor.name(),
+        &manifest.remote_path().display().to_string(),
+        manifest.size,
+    );
+    // A clone shares the file's offset, so it sees every read the adaptor makes.
+    let watched = if progress::is_enabled() { content.try_clone().ok() } else { None };
+    let done = AtomicBool::new(false);
+
+    let result = thread::scope(|scope| {
+        if let Some(mut watched) = watched {
+            let (transfer, done) = (&mut transfer, &done);
+            scope.spawn(move |_| {
+                while !done.load(Ordering::SeqCst) {
+                    if let Ok(offset) = watched.seek(SeekFrom::Current(0)) {
+                        transfer.update(offset);
                     }
-                });
-                // So we have to pull them apart to flip them
-                match result {
-                    // The "ok" state means we fell all the way through
-                    Some(err) => (ad.name(), UploadStatus::Errored(err)),
-                    None => (ad.name(), UploadStatus::Succeeded),
+                    std::thread::sleep(PROGRESS_POLL_INTERVAL);
                 }
-            })
-            .collect();
-
-        let entry = ReportEntry::new(manifest, results);
-        if entry.is_success() {
-            staged_file.delete()?;
-        } else {
-            info!("one or more adaptors failed, preserving {:?}", &staged_file);
+            });
         }
-        report.record_activity(entry);
+        let result = adaptor.upload(cancel.reader(content), manifest);
+        done.store(true, Ordering::SeqCst);
+        result
+    }).map_err(|_| format_err!("Watching the progress of an upload panicked"))?;
+
+    if result.is_ok() {
+        transfer.update(manifest.size);
     }
-    Ok(report)
+    transfer.finish(result.is_ok());
+    result
 }
 
 #[cfg(test)]
@@ -140,6 +285,7 @@ mod tests {
     use super::*;
     use std::fs;
     use std::cell::Cell;
+    use std::path::PathBuf;
     use tempfile;
     use crate::staging::UploadDescriptor;
     use crate::test_helpers;
@@ -162,8 +308,8 @@ mod tests {
         }
     }
 
-    impl StorageAdaptor<File> for TemporarilyBrokenStorageAdaptor {
-        fn upload(&self, _: File, _: &staging::UploadDescriptor) -> Result<StorageStatus, Error> {
+    impl<T> StorageAdaptor<T> for TemporarilyBrokenStorageAdaptor {
+        fn upload(&self, _: T, _: &staging::UploadDescriptor) -> Result<StorageStatus, Error> {
             let this_attempt = self.attempts.get() + 1;
             self.attempts.set(this_attempt);
 
@@ -207,6 +353,70 @@ mod tests {
         assert_eq!(10, files.len());
     }
 
+    #[test]
+    fn test_failed_uploads_are_kept_with_the_staged_file() {
+        let data = test_helpers::staged_data(1).expect("Couldn't create staging data");
+        let uploader = TemporarilyBrokenStorageAdaptor::new(4);
+
+        let report = upload_from_staged(&data, &[MaybeStorageAdaptor::Ok(uploader)]).expect("Didn't upload successfully");
+        assert!(report.has_problems());
+
+        let staged = data.staged_files().unwrap();
+        assert_eq!(staged.len(), 1);
+        let errors = staged[0].0.upload_errors().unwrap();
+        assert_eq!(errors.len(), 1);
+        assert_eq!(errors[0].backend, "TemporarilyBrokenStorageAdaptor");
+    }
+
+    #[test]
+    fn test_stopping_leaves_the_rest_staged_and_pending() {
+        let data = test_helpers::staged_data(3).expect("Couldn't create staging data");
+        let uploader = TemporarilyBrokenStorageAdaptor::new(1);
+        let cancel = Cancel::new();
+        cancel.stop();
+
+        let mut report = UploadReport::default();
+        upload_staged(&data, &[MaybeStorageAdaptor::Ok(uploader)], &mut report, &cancel).expect("Didn't stop cleanly");
+        assert_eq!(report.num_uploads(), 0);
+        assert!(report.has_pending());
+        assert!(!report.has_problems());
+        assert_eq!(data.staged_files().unwrap().len(), 3);
+    }
+
+    /// A storage adaptor that takes the content of every file it uploads out of staging, as if
+    /// somebody had got there first.
+    #[derive(Debug)]
+    struct StagingRemovingStorageAdaptor {
+        staging: PathBuf,
+    }
+
+    impl<T> StorageAdaptor<T> for StagingRemovingStorageAdaptor {
+        fn upload(&self, _: T, manifest: &staging::UploadDescriptor) -> Result<StorageStatus, Error> {
+            fs::remove_file(self.staging.join(manifest.staging_name()))?;
+            Ok(StorageStatus::Success)
+        }
+
+        fn already_uploaded(&self, _: &staging::UploadDescriptor) -> bool {
+            false
+        }
+
+        fn name(&self) -> String {
+            "StagingRemovingStorageAdaptor".to_string()
+        }
+    }
+
+    #[test]
+    fn test_files_stuck_in_staging_dont_stop_the_rest() {
+        let data = test_helpers::staged_data(3).expect("Couldn't create staging data");
+        let uploader = StagingRemovingStorageAdaptor {
+            staging: data.path().to_path_buf(),
+        };
+
+        let report = upload_from_staged(&data, &[MaybeStorageAdaptor::Ok(uploader)]).expect("Didn't upload successfully");
+        assert_eq!(report.num_uploads(), 3);
+        assert!(report.has_problems());
+    }
+
     #[test]
     fn test_two_failures_and_then_success_erases_staged_files() {
         let data = test_helpers::staged_data(5).expect("Couldn't create staging data");
@@ -0,0 +1,120 @@
+use std::env;
+use std::ffi::OsStr;
+use std::process;
+use std::time::Duration;
+
+use failure::Error;
+
+/// Tell systemd about our state, eg `READY=1`, if it's listening.
+///
+/// Returns whether there was anyone to tell.
+pub fn notify(state: &str) -> Result<bool, Error> {
+    match env::var_os("NOTIFY_SOCKET") {
+        Some(socket) => {
+            send(&socket, state)?;
+            Ok(true)
+        },
+        None => Ok(false),
+    }
+}
+
+/// Like `notify`, for callers with nothing better to do with an error than log it.
+pub fn notify_or_log(state: &str) {
+    if let Err(e) = notify(state) {
+        error!("Couldn't notify systemd of {:?}: {:?}", state, e);
+    }
+}
+
+/// How often systemd's watchdog expects to hear from us, if it's watching.
+pub fn watchdog_interval() -> Option<Duration> {
+    watchdog_interval_with(env::var("WATCHDOG_USEC").ok(), env::var("WATCHDOG_PID").ok(), process::id())
+}
+
+fn watchdog_interval_with(usec: Option<String>, pid: Option<String>, our_pid: u32) -> Option<Duration> {
+    // The watchdog may be meant for some other process, in which case it'll have told us who.
+    if let Some(pid) = pid {
+        if pid.parse::<u32>().ok() != Some(our_pid) {
+            return None;
+        }
+    }
+    match usec?.parse::<u64>() {
+        Ok(0) | Err(_) => None,
+        Ok(usec) => Some(Duration::from_micros(usec)),
+    }
+}
+
+#[cfg(target_os = "linux")]
+fn send(socket: &OsStr, state: &str) -> Result<(), Error> {
+    use std::io;
+    use std::mem;
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::io::AsRawFd;
+    use std::os::unix::net::UnixDatagram;
+
+    let path = socket.as_bytes();
+    let mut addr: libc::sockaddr_un = unsafe { mem::zeroed() };
+    if path.is_empty() || path.len() >= addr.sun_path.len() {
+        bail!("Invalid NOTIFY_SOCKET {:?}", socket);
+    }
+    addr.sun_family = libc::AF_UNIX as libc::sa_family_t;
+    for (dst, src) in addr.sun_path.iter_mut().zip(path) {
+        *dst = *src as libc::c_char;
+    }
+    // Abstract sockets are written with a leading @, which std can't address, so we do it ourselves.
+    if path[0] == b'@' {
+        addr.sun_path[0] = 0;
+    }
+    let len = mem::size_of::<libc::sa_family_t>() + path.len();
+
+    let sock = UnixDatagram::unbound()?;
+    let ret = unsafe {
+        libc::sendto(
+            sock.as_raw_fd(),
+            state.as_ptr() as *const libc::c_void,
+            state.len(),
+            libc::MSG_NOSIGNAL,
+            &addr as *const libc::sockaddr_un as *const libc::sockaddr,
+            len as libc::socklen_t,
+        )
+    };
+    if ret < 0 {
+        return Err(io::Error::last_os_error().into());
+    }
+    Ok(())
+}
+
+#[cfg(not(target_os = "linux"))]
+fn send(_socket: &OsStr, _state: &str) -> Result<(), Error> {
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_watchdog_interval() {
+        let usec = Some("30000000".to_string());
+        assert_eq!(watchdog_interval_with(usec.clone(), None, 42), Some(Duration::from_secs(30)));
+        assert_eq!(watchdog_interval_with(usec.clone(), Some("42".into()), 42), Some(Duration::from_secs(30)));
+        assert_eq!(watchdog_interval_with(usec, Some("1".into()), 42), None);
+        assert_eq!(watchdog_interval_with(Some("0".into()), None, 42), None);
+        assert_eq!(watchdog_interval_with(None, None, 42), None);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_sends_notifications() {
+        use std::os::unix::net::UnixDatagram;
+        use crate::test_helpers;
+
+        let dir = test_helpers::tempdir();
+        let path = dir.path().join("notify");
+        let listener = UnixDatagram::bind(&path).unwrap();
+
+        send(path.as_os_str(), "READY=1").unwrap();
+        let mut buf = [0; 64];
+        let len = listener.recv(&mut buf).unwrap();
+        assert_eq!(&buf[..len], b"READY=1");
+    }
+}
@@ -7,6 +7,9 @@ use std::path::PathBuf;
 use chrono::prelude::*;
 use failure::Error;
 
+use crate::cancel::Cancel;
+use crate::ctx;
+use crate::device::{Device, DeviceKind};
 use crate::staging::{Staging, DateTimeUploadable};
 
 /// Copy data from the test-data directory to a tempdir, then return the owned TestDir object to
@@ -52,10 +55,20 @@ impl DummyDataDevice {
             }).collect(),
         }
     }
+
+    /// A device with `num_files` files that were all captured at `capture_time`.
+    pub(crate) fn captured_at(num_files: usize, capture_time: DateTime<Local>) -> DummyDataDevice {
+        let mut device = DummyDataDevice::new(num_files);
+        for file in &mut device.files {
+            file.capture_time = capture_time;
+        }
+        device
+    }
 }
 
 pub(crate) struct DummyDataFile {
     file: File,
+    capture_time: DateTime<Local>,
     deleted: bool,
 }
 
@@ -63,6 +76,7 @@ impl Clone for DummyDataFile {
     fn clone(&self) -> Self {
         DummyDataFile {
             file: self.file.try_clone().expect("Couldn't clone file"),
+            capture_time: self.capture_time,
             deleted: self.deleted,
         }
     }
@@ -76,6 +90,7 @@ impl DummyDataFile {
 
         Ok(DummyDataFile {
             file,
+            capture_time: Local::now(),
             deleted: false,
         })
     }
@@ -93,7 +108,7 @@ impl DateTimeUploadable for DummyDataFile {
     }
 
     fn capture_datetime(&self) -> Result<DateTime<Local>, chrono::ParseError> {
-        Ok(Local::now())
+        Ok(self.capture_time)
     }
 
     fn reader(&mut self) -> &mut Self::Reader {
@@ -121,7 +136,7 @@ pub(crate) fn staged_data(num_files: usize) -> Result<tempfile::TempDir, Error>
     let device = DummyDataDevice::new(num_files);
 
     // Stage it's contents
-    device.stage_files("dummy", &data_dir)?;
+    device.stage_files("dummy", &data_dir, &Cancel::new())?;
 
     Ok(data_dir)
 }
@@ -129,3 +144,17 @@ pub(crate) fn staged_data(num_files: usize) -> Result<tempfile::TempDir, Error>
 pub(crate) fn tempdir() -> tempfile::TempDir {
     tempfile::tempdir().unwrap()
 }
+
+/// A kind of device that isn't built in, for testing the registry.
+#[derive(Debug)]
+pub(crate) struct AltimeterKind;
+
+impl DeviceKind for AltimeterKind {
+    fn name(&self) -> &'static str {
+        "altimeter"
+    }
+
+    fn attached<'a>(&self, _ctx: &'a ctx::Ctx) -> Result<Vec<Box<dyn Device + 'a>>, Error> {
+        Ok(vec![])
+    }
+}
@@ -1,5 +1,3 @@
-use std::fs::File;
-
 use failure::Error;
 use reqwest;
 use reqwest::header::{HeaderMap, HeaderValue};
@@ -7,7 +5,7 @@ use serde_json;
 use tus;
 
 use crate::staging;
-use crate::storage::{StorageAdaptor, StorageStatus};
+use crate::storage::{StagedReader, StorageAdaptor, StorageStatus};
 
 /// A client for the vimeo API
 #[derive(RedactedDebug)]
@@ -87,7 +85,7 @@ impl VimeoClient {
     }
 }
 
-impl StorageAdaptor<File> for VimeoClient {
+impl StorageAdaptor<StagedReader> for VimeoClient {
     fn already_uploaded(&self, _: &staging::UploadDescriptor) -> bool {
         // TODO(richo) Actually figure out how to check if we've already done this
         false
@@ -96,11 +94,11 @@ impl StorageAdaptor<File> for VimeoClient {
     /// Upload a file from the local filesystem to vimeo.
     fn upload(
         &self,
-        file: File,
+        file: StagedReader,
         manifest: &staging::UploadDescriptor,
     ) -> Result<StorageStatus, Error> {
         // First we find out how big the file is so we can create our video object upstream
-        let size = file.metadata()?.len();
+        let size = file.get_ref().metadata()?.len();
         // Then we create an upload handle
         let handle = self.create_upload_handle(&manifest.staging_name(), size)?;
 
@@ -130,6 +128,8 @@ impl Drop for UploadHandle {
 mod tests {
     use super::*;
     use std::env;
+    use std::fs::File;
+    use crate::cancel::Cancel;
 
     #[test]
     #[ignore]
@@ -153,6 +153,6 @@ mod tests {
             VimeoClient::new(env::var("ARCHIVER_TEST_VIMEO_KEY").expect("Didn't provide test key"));
         let fh = File::open("/tmp/test.mp4").expect("Couldn't open video");
         let desc = staging::UploadDescriptor::test_descriptor();
-        client.upload(fh, &desc).expect("Could not upload file");
+        client.upload(Cancel::new().reader(fh), &desc).expect("Could not upload file");
     }
 }
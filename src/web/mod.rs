@@ -3,6 +3,7 @@ use rocket::config::Environment;
 use rocket_contrib::serve::StaticFiles;
 use rocket_contrib::templates::Template;
 
+use crate::device::DeviceRegistry;
 use crate::web::db::init_pool;
 
 use logging::RequestLogger;
@@ -28,8 +29,15 @@ handlebars_helper!(maybe_selected: |field: str, active: str| {
     }});
 
 pub fn configure_rocket() -> Rocket {
+    configure_rocket_with_registry(DeviceRegistry::default())
+}
+
+/// Configure the web service to know about the kinds of device in `registry`, rather than just the
+/// ones that are built in.
+pub fn configure_rocket_with_registry(registry: DeviceRegistry) -> Rocket {
     rocket::ignite()
         .manage(init_pool(false))
+        .manage(registry)
         .mount(
             "/",
             routes![
@@ -56,6 +64,7 @@ pub fn configure_rocket() -> Rocket {
                 routes::integrations::finish_integration,
 
                 routes::devices::create_device,
+                routes::devices::register_device,
                 routes::devices::delete_device,
             ],
         )
@@ -67,8 +76,13 @@ pub fn configure_rocket() -> Rocket {
 }
 
 pub fn create_test_rocket(routes: Vec<rocket::Route>) -> Rocket {
+    create_test_rocket_with_registry(routes, DeviceRegistry::default())
+}
+
+pub fn create_test_rocket_with_registry(routes: Vec<rocket::Route>, registry: DeviceRegistry) -> Rocket {
     rocket::ignite()
         .manage(init_pool(true))
+        .manage(registry)
         .mount(
             "/",
             routes,
@@ -1,10 +1,10 @@
 use diesel::prelude::*;
+use serde_json;
 
 use super::*;
 use crate::web::schema::devices;
 
-use crate::config;
-use crate::config::{FlysightConfig, GoproConfig, MassStorageConfig, MountableDeviceLocation};
+use crate::device::RegisteredDevice;
 
 #[derive(Identifiable, Queryable, Associations, Debug, Serialize)]
 #[belongs_to(User)]
@@ -17,36 +17,20 @@ pub struct Device {
     /// with combined with the type, you can concretely say whether or not a candidate device is
     /// this one.
     pub identifier: String,
+    /// Kind specific settings for this device.
+    pub metadata: serde_json::Value,
 }
 
-impl From<Device> for config::DeviceConfig {
-    fn from(device: Device) -> Self {
-        match &device.kind[..] {
-            "ptp" => config::DeviceConfig::Gopro(GoproConfig {
-                name: device.name,
-                serial: device.identifier,
-            }),
-            "mass_storage" => {
-                config::DeviceConfig::MassStorage(MassStorageConfig {
-                    name: device.name,
-                    // TODO(richo) add a metadata field and store this there
-                    extensions: vec!["mp4".into()],
-                    location: MountableDeviceLocation::from_label(device.identifier),
-                })
-            }
-            "flysight" => config::DeviceConfig::Flysight(FlysightConfig {
-                name: device.name,
-                location: MountableDeviceLocation::from_label(device.identifier),
-            }),
-            kind => {
-                // This feels sound with the overlapping borrows, revisit?
-                config::DeviceConfig::UnknownDevice(kind.to_string())
-            }
+impl Device {
+    /// This device, for its kind to add to a config.
+    pub fn registered(&self) -> RegisteredDevice<'_> {
+        RegisteredDevice {
+            name: &self.name,
+            identifier: &self.identifier,
+            metadata: &self.metadata,
         }
     }
-}
 
-impl Device {
     pub fn by_id(&self, device_id: i32, conn: &PgConnection) -> QueryResult<Device> {
         use crate::web::schema::devices::dsl::*;
 
@@ -67,6 +51,8 @@ pub struct NewDevice<'a> {
     pub name: &'a str,
     pub kind: &'a str,
     pub identifier: &'a str,
+    /// Kind specific settings, or the default of none.
+    pub metadata: Option<&'a serde_json::Value>,
 }
 
 impl<'a> NewDevice<'a> {
@@ -76,6 +62,7 @@ impl<'a> NewDevice<'a> {
             kind,
             name,
             identifier,
+            metadata: None,
         }
     }
 
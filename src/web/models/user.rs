@@ -29,6 +29,8 @@ pub enum StagingKind {
     None,
     Mountpoint,
     Label,
+    Uuid,
+    PartUuid,
 }
 
 impl<'v> FromFormValue<'v> for StagingKind {
@@ -40,6 +42,8 @@ impl<'v> FromFormValue<'v> for StagingKind {
             Ok(ref kind) if kind == "None" => Ok(StagingKind::None),
             Ok(ref kind) if kind == "Label" => Ok(StagingKind::Label),
             Ok(ref kind) if kind == "Mountpoint" => Ok(StagingKind::Mountpoint),
+            Ok(ref kind) if kind == "Uuid" => Ok(StagingKind::Uuid),
+            Ok(ref kind) if kind == "PartUuid" => Ok(StagingKind::PartUuid),
             _ => Err(format!("unknown staging_kind {}", form_value)),
         }
     }
@@ -116,9 +120,12 @@ impl User {
             StagingKind::None => return None,
             StagingKind::Label => MountableDeviceLocation::Label(loc.to_owned()),
             StagingKind::Mountpoint => MountableDeviceLocation::Mountpoint(loc.into()),
+            StagingKind::Uuid => MountableDeviceLocation::Uuid(loc.to_owned()),
+            StagingKind::PartUuid => MountableDeviceLocation::PartUuid(loc.to_owned()),
         };
         Some(StagingConfig {
             location,
+            mounter: None,
         })
     }
 
@@ -191,6 +198,8 @@ mod tests {
                 StagingKind::None => {},
                 StagingKind::Label => {},
                 StagingKind::Mountpoint => {},
+                StagingKind::Uuid => {},
+                StagingKind::PartUuid => {},
             }
         }
 
@@ -198,6 +207,8 @@ mod tests {
             match ml {
                 MountableDeviceLocation::Label(_) => {},
                 MountableDeviceLocation::Mountpoint(_) => {},
+                MountableDeviceLocation::Uuid(_) => {},
+                MountableDeviceLocation::PartUuid(_) => {},
             }
         }
         // If you find yourself looking at this test, it's because one of those enums was updated
@@ -1,17 +1,23 @@
 use failure::Error;
 
 use rocket::get;
+use rocket::State;
 use rocket::response::content::Content;
 use rocket::http::ContentType;
 use rocket::response::{Flash, Redirect};
 
-use crate::config::{Config, DeviceConfig};
+use crate::config::Config;
+use crate::device::DeviceRegistry;
 use crate::web::auth::AuthenticatedUser;
 use crate::web::db::DbConn;
 use crate::messages::Oauth2Provider;
 
 #[get("/config")]
-pub fn get_config(user: AuthenticatedUser, conn: DbConn) -> Result<Content<String>, Flash<Redirect>> {
+pub fn get_config(
+    user: AuthenticatedUser,
+    conn: DbConn,
+    kinds: State<'_, DeviceRegistry>,
+) -> Result<Content<String>, Flash<Redirect>> {
     let mut config = Config::build();
 
     let integrations = user.user().integrations(&*conn).map_err(|e| {
@@ -44,11 +50,16 @@ pub fn get_config(user: AuthenticatedUser, conn: DbConn) -> Result<Content<Strin
     })?;
 
     for device in devices {
-        match device.into() {
-            DeviceConfig::Gopro(gopro) => config = config.gopro(gopro),
-            DeviceConfig::Flysight(flysight) => config = config.flysight(flysight),
-            DeviceConfig::MassStorage(mass_storage) => config = config.mass_storage(mass_storage),
-            DeviceConfig::UnknownDevice(kind) => warn!("Unknown device kind: {}", kind),
+        match kinds.get(&device.kind) {
+            Some(kind) => {
+                config = kind.add_registered(config, &device.registered()).map_err(|e| {
+                    Flash::error(
+                        Redirect::to("/"),
+                        format!("There was a problem adding {} to your configuration: {}", &device.name, e),
+                    )
+                })?;
+            },
+            None => warn!("Unknown device kind: {}", &device.kind),
         }
     }
 
@@ -66,7 +77,7 @@ pub fn get_config(user: AuthenticatedUser, conn: DbConn) -> Result<Content<Strin
             )
     }
 
-    match config.finish().map(|c| c.to_toml()) {
+    match config.finish_with_registry(&kinds).map(|c| c.to_toml()) {
         Ok(Ok(config)) => Ok(Content(
             ContentType::new("application", "toml"),
             config,
@@ -89,9 +100,16 @@ mod tests {
     use crate::web::models::extra::StagingKind;
 
     use crate::config::{Config, FlysightConfig, MountableDeviceLocation};
+    use crate::test_helpers::AltimeterKind;
 
     client_for_routes!(get_config => client);
 
+    fn client_with_registry(registry: DeviceRegistry) -> rocket::local::Client {
+        let routes = routes![crate::web::routes::sessions::post_signin, get_config];
+        let rocket = crate::web::create_test_rocket_with_registry(routes, registry);
+        rocket::local::Client::new(rocket).expect("valid rocket instance")
+    }
+
     #[test]
     fn test_anon_get_config() {
         let client = client();
@@ -252,6 +270,37 @@ mod tests {
         assert_eq!(config.gopros().len(), 1);
     }
 
+    #[test]
+    fn test_get_config_with_registered_kinds() {
+        let registry = || DeviceRegistry::default().register(AltimeterKind);
+        let client = client_with_registry(registry());
+
+        let user = create_user(&client, "test@email.com", "p@55w0rd");
+        signin(&client, "test%40email.com", "p%4055w0rd").unwrap();
+
+        {
+            let conn = db_conn(&client);
+
+            NewIntegration::new(&user, "dropbox", "test_oauth_token", None)
+                .create(&*conn)
+                .unwrap();
+            NewDevice::new(&user, "wrist", "altimeter", "ALT-1")
+                .create(&*conn)
+                .unwrap();
+        }
+
+        let req = client.get("/config");
+
+        let mut response = req.dispatch();
+        assert_eq!(response.status(), Status::Ok);
+        let body = response.body_string().expect("Didn't recieve a body");
+        let config = Config::from_str_with_registry(&body, &registry()).unwrap();
+        let altimeters: Vec<toml::Value> = config.devices_of("altimeter").unwrap();
+        assert_eq!(altimeters.len(), 1);
+        assert_eq!(altimeters[0]["name"].as_str(), Some("wrist"));
+        assert_eq!(altimeters[0]["identifier"].as_str(), Some("ALT-1"));
+    }
+
     #[test]
     fn test_get_config_with_staging() {
         let client = client();
@@ -1,61 +1,38 @@
-use rocket::http::RawStr;
-use rocket::request::{Form, FromFormValue};
+use rocket::State;
+use rocket::request::Form;
 use rocket::response::{Flash, Redirect};
+use rocket_contrib::json::Json;
 
-use crate::web::auth::WebUser;
+use crate::device::DeviceRegistry;
+use crate::messages::{RegisterDevice, RegisterDeviceResp};
+use crate::web::auth::{ApiUser, WebUser};
 use crate::web::db::DbConn;
 use crate::web::models::{
     NewDevice,
 };
 
-#[derive(Debug)]
-pub enum DeviceKind {
-    Ptp,
-    Flysight,
-    MassStorage,
-}
-
-impl<'v> FromFormValue<'v> for DeviceKind {
-    type Error = String;
-
-    fn from_form_value(form_value: &'v RawStr) -> Result<DeviceKind, Self::Error> {
-        let decoded = form_value.url_decode();
-        match decoded {
-            Ok(ref kind) if kind == "ptp" => Ok(DeviceKind::Ptp),
-            Ok(ref kind) if kind == "flysight" => Ok(DeviceKind::Flysight),
-            Ok(ref kind) if kind == "mass_storage" => Ok(DeviceKind::MassStorage),
-            _ => Err(format!("unknown provider {}", form_value)),
-        }
-    }
-}
-
-impl DeviceKind {
-    pub fn name(&self) -> &'static str {
-        match self {
-            DeviceKind::Ptp => "ptp",
-            DeviceKind::Flysight => "flysight",
-            DeviceKind::MassStorage => "mass_storage",
-        }
-    }
-}
-
 #[derive(Debug, FromForm)]
 pub struct DeviceForm {
     name: String,
-    kind: DeviceKind,
+    kind: String,
     identifier: String,
 }
 
-#[post("/device", data = "<device>")]
+#[post("/device", format = "application/x-www-form-urlencoded", data = "<device>")]
 pub fn create_device(
     user: WebUser,
     conn: DbConn,
+    kinds: State<'_, DeviceRegistry>,
     device: Form<DeviceForm>,
 ) -> Result<Flash<Redirect>, Flash<Redirect>> {
+    let kind = match kinds.get(&device.kind) {
+        Some(kind) => kind,
+        None => return Err(Flash::error(Redirect::to("/"), format!("unknown device kind {}", &device.kind))),
+    };
     let row = NewDevice::new(
         &user.user,
         &device.name,
-        device.kind.name(),
+        kind.name(),
         &device.identifier,
     )
     .create(&*conn)
@@ -63,22 +40,45 @@ pub fn create_device(
     match row {
         Some(_) => Ok(Flash::success(
             Redirect::to("/"),
-            format!("{} was added to your configuration.", device.kind.name()),
+            format!("{} was added to your configuration.", kind.name()),
         )),
         None => Err(Flash::error(
             Redirect::to("/"),
             format!(
                 "There was a problem adding {} to your configuration.",
-                device.kind.name()
+                kind.name()
             ),
         )),
     }
 }
 
+/// Register a device from the cli, usually as it's bootstrapped.
+#[post("/device", format = "json", data = "<device>")]
+pub fn register_device(
+    user: ApiUser,
+    conn: DbConn,
+    kinds: State<'_, DeviceRegistry>,
+    device: Json<RegisterDevice>,
+) -> Json<RegisterDeviceResp> {
+    let kind = match kinds.get(&device.kind) {
+        Some(kind) => kind,
+        None => return Json(RegisterDeviceResp::Error(format!("unknown device kind {}", &device.kind))),
+    };
+    let mut new_device = NewDevice::new(&user.user, &device.name, kind.name(), &device.identifier);
+    new_device.metadata = device.metadata.as_ref();
+    match new_device.create(&*conn) {
+        Ok(_) => Json(RegisterDeviceResp::Registered),
+        Err(e) => {
+            warn!("{}", e);
+            Json(RegisterDeviceResp::Error(format!("There was a problem adding {}", &device.name)))
+        },
+    }
+}
+
 #[derive(Debug, FromForm)]
 pub struct DeleteDeviceForm {
     device_id: i32,
-    kind: DeviceKind,
+    kind: String,
 }
 
 #[post("/device/delete", data = "<device>")]
@@ -93,7 +93,7 @@ pub fn delete_device(
         .map(|_| {
             Flash::success(
                 Redirect::to("/"),
-                format!("{} has been removed from your account.", device.kind.name()),
+                format!("{} has been removed from your account.", &device.kind),
             )
         })
         .map_err(|e| {
@@ -102,7 +102,7 @@ pub fn delete_device(
                 Redirect::to("/"),
                 format!(
                     "{} could not be removed from your account.",
-                    device.kind.name()
+                    &device.kind
                 ),
             )
         })
@@ -113,9 +113,9 @@ mod tests {
     use super::*;
     use crate::web::test_helpers::*;
 
-    use rocket::http::{ContentType, Status};
+    use rocket::http::{ContentType, Header, Status};
 
-    client_for_routes!(create_device, delete_device => client);
+    client_for_routes!(create_device, register_device, delete_device => client);
 
     #[test]
     fn test_create_devices() {
@@ -155,7 +155,7 @@ mod tests {
         init_env();
 
         let client = client();
-        create_user(&client, "test@email.com", "p@55w0rd");
+        let user = create_user(&client, "test@email.com", "p@55w0rd");
         signin(&client, "test%40email.com", "p%4055w0rd").unwrap();
 
         let add_device = |kind, name| {
@@ -170,6 +170,54 @@ mod tests {
         };
 
         add_device("nonexistant", "gopro5");
+
+        let response = client
+            .post("/device")
+            .header(ContentType::Form)
+            .body("name=gopro5&kind=nonexistant&identifier=C123456")
+            .dispatch();
+        assert_eq!(response.status(), Status::SeeOther);
+
+        let conn = db_conn(&client);
+        assert_eq!(user.devices(&*conn).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_register_devices() {
+        init_env();
+
+        let client = client();
+        let user = create_user(&client, "test@email.com", "p@55w0rd");
+        let token = signin_api(&client, "test@email.com", "p@55w0rd").unwrap();
+
+        let register = |kind: &str, metadata: &str| {
+            let mut response = client
+                .post("/device")
+                .header(ContentType::JSON)
+                .header(Header::new("Authorization", format!("Bearer: {}", token)))
+                .body(format!(r#"{{"name": "front", "kind": "{}", "identifier": "UUID=5C8A-1F2B"{}}}"#, kind, metadata))
+                .dispatch();
+            assert_eq!(response.status(), Status::Ok);
+            serde_json::from_str::<RegisterDeviceResp>(&response.body_string().unwrap()).unwrap()
+        };
+
+        assert_eq!(register("mass_storage", ""), RegisterDeviceResp::Registered);
+        assert_eq!(
+            register("mass_storage", r#", "metadata": {"extensions": ["mov"]}"#),
+            RegisterDeviceResp::Registered,
+        );
+        match register("floppy", "") {
+            RegisterDeviceResp::Error(_) => {},
+            resp => panic!("Unexpected response: {:?}", resp),
+        }
+
+        let conn = db_conn(&client);
+        let devices = user.devices(&*conn).unwrap();
+        assert_eq!(devices.len(), 2);
+        assert_eq!(devices[0].identifier, "UUID=5C8A-1F2B");
+        let metadata: Vec<_> = devices.iter().map(|d| d.metadata.clone()).collect();
+        assert!(metadata.contains(&json!({})));
+        assert!(metadata.contains(&json!({"extensions": ["mov"]})));
     }
 
     #[test]
@@ -58,9 +58,12 @@ impl SettingsForm {
                 let pathbuf = PathBuf::from(&self.staging_data);
                 MountableDeviceLocation::Mountpoint(pathbuf)
             }
+            StagingKind::Uuid => MountableDeviceLocation::Uuid(self.staging_data.clone()),
+            StagingKind::PartUuid => MountableDeviceLocation::PartUuid(self.staging_data.clone()),
         };
         Some(StagingConfig {
             location,
+            mounter: None,
         })
     }
 }
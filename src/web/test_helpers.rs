@@ -61,7 +61,8 @@ pub fn create_user(client: &Client, username: &str, password: &str) -> User {
     let user = NewUser::new(username, password).create(&*conn).unwrap();
 
     user.update_staging(&StagingConfig {
-        location: MountableDeviceLocation::Mountpoint("/path".into())
+        location: MountableDeviceLocation::Mountpoint("/path".into()),
+        mounter: None,
     }, &*conn).unwrap();
 
     user